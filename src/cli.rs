@@ -1,86 +1,804 @@
-use clap::{Parser, ValueEnum};
+use crate::{exec::DEFAULT_TMP_SUFFIX, plan::SidecarIndex};
+use clap::{Parser, Subcommand, ValueEnum};
 use color_eyre::{
-    eyre::{self, eyre},
+    eyre::{self, bail, eyre},
     Section,
 };
 use itertools::{Either, Itertools};
-use snafu::Snafu;
-use std::{ops::Not, path::PathBuf};
-use wax::{FilterTarget, Glob, IteratorExt};
+use regex::Regex;
+use rename_seq::{BuiltinTransform, DynamicRenameContent, Order};
+use snafu::{ResultExt, Snafu};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt::{self, Display},
+    fs, io,
+    num::NonZeroUsize,
+    ops::Not,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use wax::{FilterTarget, Glob, IteratorExt, LinkBehavior, WalkBehavior};
 
 /// Command-line arguments parsed by [`main`].
 #[derive(Debug, Parser)]
 pub struct MainArgs {
+    /// Print the capabilities of this build (e.g., supported replacement groups) as JSON, then exit.
+    #[clap(long, exclusive = true)]
+    pub capabilities: bool,
     /// Actually rename files, instead of performing a dry run.
     #[clap(long)]
     pub go: bool,
+    /// Write the planned renames to the given file as JSON, for running later with `apply`, then
+    /// exit without touching files.
+    #[clap(long, value_name = "FILE", conflicts_with = "go")]
+    pub dry_run_to: Option<PathBuf>,
+    /// Show the planned renames as a table, then ask whether to proceed, performing them only if
+    /// the answer is `y`. Unlike `--go`, this can't be used non-interactively.
+    ///
+    /// Requires standard input to be a terminal.
+    #[clap(long, conflicts_with_all = ["dry_run_to", "count_only", "table"])]
+    pub interactive: bool,
+    /// Ask whether to perform each rename, answering `y` to rename the file, `n` to skip it, or `q`
+    /// to skip it and every rename after it.
+    ///
+    /// Requires standard input to be a terminal.
+    #[clap(long, requires = "go")]
+    pub confirm_each: bool,
+    /// Don't show how many files are done on standard error, which is otherwise shown when it's a
+    /// terminal and many files are renamed.
+    #[clap(long)]
+    pub no_progress: bool,
+    /// How to report the outcome of the run on standard output.
+    #[clap(long, default_value_t, value_enum)]
+    pub format: OutputFormat,
+    /// After renaming, write a JSON report of the run to the given file: when it happened, its
+    /// command line, how many files were renamed, skipped, or failed (and why), and how many bytes
+    /// were transferred.
+    #[clap(long, value_name = "FILE")]
+    pub report: Option<PathBuf>,
     /// Execute renaming even if there are warnings of likely unintended behavior.
     #[clap(long)]
     pub allow_warnings: bool,
-    /// The order in which selected files should be renamed.
-    #[clap(long, default_value_t = Order::Sequential, value_enum)]
+    /// Refuse, rather than warn about, rename specs that could rename more than one selected file
+    /// to the same name, i.e., those without dynamic content, or without `{padded_idx}` or
+    /// `{idx}`.
+    #[clap(long)]
+    pub strict_spec: bool,
+    /// Check target names against the rules of Windows, which forbids characters like `:` and `?`,
+    /// trailing dots and spaces, and device names like `CON` and `NUL`, even on other platforms.
+    /// Directories that already exist aren't checked, since renaming doesn't change their names.
+    #[clap(long)]
+    pub strict_names: bool,
+    /// Permit relative target paths that lead outside of the current directory (e.g., with `../`).
+    #[clap(long)]
+    pub allow_escape: bool,
+    /// Only print the number of files that would be renamed, skipping those that already have
+    /// their target name, then exit without touching the file system.
+    #[clap(long)]
+    pub count_only: bool,
+    /// Print the planned renames as a table of aligned columns, then exit without touching the
+    /// file system.
+    #[clap(long)]
+    pub table: bool,
+    /// Precede `--table` output with a header row.
+    #[clap(long, requires = "table")]
+    pub table_header: bool,
+    /// Separate every `N` rows of `--table` output with a line.
+    #[clap(long, value_name = "N", requires = "table")]
+    pub table_ruler: Option<NonZeroUsize>,
+    /// Print a rename spec suggested by the structure shared by selected file names, then exit.
+    #[clap(long)]
+    pub suggest_spec: bool,
+    /// Print groups of selected files with byte-identical contents, then exit.
+    #[clap(long)]
+    pub detect_duplicates: bool,
+    /// Rather than exiting after `--detect-duplicates`, rename only the first file of each group of
+    /// duplicates, leaving the rest untouched.
+    #[clap(long, requires = "detect_duplicates")]
+    pub skip_duplicates: bool,
+    /// Rename only the first of each group of selected files with byte-identical contents, so
+    /// that each group gets a single index.
+    ///
+    /// Unlike `--skip-duplicates`, duplicates are not printed.
+    #[clap(long)]
+    pub dedup_targets_by_hash: bool,
+    /// Delete the duplicates left out by `--dedup-targets-by-hash`, after renaming.
+    #[clap(long, requires = "dedup_targets_by_hash")]
+    pub delete_duplicates: bool,
+    /// The order in which selected files should be renamed, i.e., `sequential`,
+    /// `single-sided-scans`, which alternates between the first and last files not yet renamed, or
+    /// `shuffle`, which is random (see `--seed`).
+    #[clap(long, default_value_t)]
     pub order: Order,
-    /// A pattern describing renamed path names, of the form `[<prefix>{padded_idx}]<suffix>`.
+    /// The base, from 2 to 36, in which indices are written, which also determines how many digits
+    /// `{padded_idx}` is padded to. `bin`, `oct`, `dec`, and `hex` name common bases. Digits beyond
+    /// `9` are lowercase letters, unless `--uppercase-digits` is set.
+    #[clap(
+        long,
+        visible_alias = "radix",
+        default_value_t = 10,
+        value_parser = parse_numbering_base
+    )]
+    pub numbering_base: u32,
+    /// Write digits beyond `9` in uppercase letters, e.g., `FF` rather than `ff` with
+    /// `--numbering-base hex`.
+    #[clap(long)]
+    pub uppercase_digits: bool,
+    /// Never reorder selected files, using the order they were provided or discovered in, even if
+    /// a selection specifies sorting (e.g., `from-glob --sort-by`).
+    ///
+    /// Note that the order in which files are discovered depends on the file system, and may
+    /// differ between runs or platforms.
+    #[clap(long)]
+    pub no_sort: bool,
+    /// Number selected files back to front, i.e., reverse the order they were provided in (with
+    /// `from-files`), sorted into (with `from-glob --sort-by`), or discovered in (with
+    /// `--no-sort`), so that the last file is given the first index.
+    ///
+    /// With `--order single-sided-scans`, files are reversed before being interleaved, so scans
+    /// start from the last file (i.e., the last file, then the first, then the second-to-last, and
+    /// so on).
+    #[clap(long)]
+    pub reverse: bool,
+    /// Rename only the first `N` selected files, once they're sorted and ordered (e.g., with
+    /// `--order`), leaving the rest untouched.
+    ///
+    /// Indices are numbered and padded as if only those files were selected, so with 100 files,
+    /// `--limit 3` renders `{padded_idx}` as `0`, `1`, and `2`, rather than `00`, `01`, and `02`
+    /// as a run over every file would. Use `--pad-width` to preview the padding of a full run.
+    #[clap(long, value_name = "N")]
+    pub limit: Option<NonZeroUsize>,
+    /// The index of the first file, e.g., `1` for numbering from one. See also `--step`.
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    pub start_at: usize,
+    /// How much the index grows from one file to the next. With `--start-at`, the `n`th file
+    /// (counting from zero) is numbered `N + n * STEP`, and indices are padded to the width of the
+    /// largest one.
+    #[clap(long, value_name = "STEP", default_value = "1")]
+    pub step: NonZeroUsize,
+    /// Append each file's original extension to its new name, unless the rename spec already ends
+    /// with an extension (a `.` followed by one or more ASCII alphanumeric characters) or uses
+    /// `{ext}`.
+    #[clap(long)]
+    pub keep_ext: bool,
+    /// Seed the random characters of `{rand:N}`, and the order of `--order shuffle`, so that they
+    /// are the same between runs.
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Rather than using a rename spec, find the run of digits that varies between selected file
+    /// names, and zero-pad it to the given width, or the width of its largest existing value.
+    ///
+    /// For instance, `a1b.txt` and `a10b.txt` are renamed to `a01b.txt` and `a10b.txt`.
+    #[clap(
+        long,
+        value_name = "WIDTH",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with_all = ["rename_spec", "group_by", "keep_ext"],
+    )]
+    pub index_field_only: Option<Option<usize>>,
+    /// Rather than using a rename spec, rename selected files to the names of the files in the
+    /// given directory, by position once both are sorted, keeping their own extensions.
+    ///
+    /// For instance, with `IMG_1.raw` and `IMG_2.raw` in the directory, `a.jpg` and `b.jpg` are
+    /// renamed to `IMG_1.jpg` and `IMG_2.jpg`.
+    #[clap(
+        long,
+        value_name = "DIR",
+        group = "positional_names",
+        conflicts_with_all = ["rename_spec", "group_by", "keep_ext", "index_field_only"],
+    )]
+    pub mirror: Option<PathBuf>,
+    /// Rather than using a rename spec, rename selected files to the names listed in the given
+    /// file, one per line, by position. Names are relative to each file's own directory.
+    ///
+    /// Files are taken in the order they're selected in, so sort them (e.g., with `from-glob
+    /// --sort-by lexicographical`) to map names onto a sorted selection. `from-files` keeps the
+    /// order its files were given in.
+    #[clap(
+        long,
+        value_name = "FILE",
+        group = "positional_names",
+        conflicts_with_all = ["rename_spec", "group_by", "keep_ext", "index_field_only"],
+    )]
+    pub names_from: Option<PathBuf>,
+    /// Permit `--mirror` directories or `--names-from` files with a different number of names
+    /// than files are selected, renaming only as many files as both have.
+    #[clap(long, requires = "positional_names")]
+    pub allow_mismatch: bool,
+    /// Give each selected file that doesn't already have a name matching the rename spec the
+    /// smallest index not yet used by an existing file, rather than numbering files in order.
+    /// Indices below the smallest one used are left alone, so `img-001.jpg`, `img-003.jpg`, and a
+    /// new file give the new file `img-002.jpg`.
+    ///
+    /// Existing files are found among selected files, and in the directory of the first target.
+    #[clap(long, conflicts_with_all = ["group_by", "index_field_only", "positional_names"])]
+    pub fill_gaps: bool,
+    /// Give selected files that differ only in their extension (e.g., `doc.pdf` and `doc.docx`)
+    /// the same index, numbering each distinct stem once.
+    ///
+    /// Usually combined with `--keep-ext`, so that such files keep distinct names.
+    #[clap(
+        long,
+        conflicts_with_all = ["group_by", "index_field_only", "positional_names", "fill_gaps"],
+    )]
+    pub stable_across_extensions: bool,
+    /// Keep each file's index stable across runs by recording it in the given sidecar file
+    /// (`.rename-seq-index.json` by default), by the file's original name.
+    ///
+    /// Files seen in earlier runs, under either their original or rename spec's name, reuse
+    /// their recorded indices, and new files get the smallest indices not in use.
+    #[clap(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = SidecarIndex::DEFAULT_PATH,
+        conflicts_with_all = [
+            "group_by",
+            "index_field_only",
+            "positional_names",
+            "fill_gaps",
+            "stable_across_extensions",
+        ],
+    )]
+    pub sidecar_index: Option<PathBuf>,
+    /// Forget the indices of files recorded by `--sidecar-index` that are no longer selected, so
+    /// that new files can reuse them, rather than leaving gaps.
+    #[clap(long, requires = "sidecar_index")]
+    pub reuse_freed_indices: bool,
+    /// Keep the count of files renamed by every run using the given file, for `{global_idx}` to
+    /// continue from.
+    ///
+    /// The file is locked while in use, so concurrent runs wait for each other.
+    #[clap(long, value_name = "FILE")]
+    pub counter_file: Option<PathBuf>,
+    /// Shorten `{name}` in target paths longer than the given number of characters (e.g., `260`
+    /// for Windows' `MAX_PATH`), by replacing characters from its middle with `~`, failing to
+    /// rename files whose targets can't be shortened enough.
+    ///
+    /// Targets are measured in full, including `--out-dir`, and again once `--transform`, `--case`,
+    /// and collision handling have changed them, failing files whose targets have grown too long.
+    /// Relative targets are measured as they are written, without the current directory.
+    #[clap(long, value_name = "N")]
+    pub max_path_len: Option<usize>,
+    /// Pad `{global_idx}` with zeroes to at least the given number of digits.
+    #[clap(
+        long,
+        value_name = "DIGITS",
+        default_value_t = 0,
+        requires = "counter_file"
+    )]
+    pub counter_padding: usize,
+    /// Pad indices to exactly the given number of digits, rather than to the width of the largest
+    /// index. Indices that need more digits are written in full.
+    #[clap(long, value_name = "WIDTH", conflicts_with = "width_lock")]
+    pub pad_width: Option<usize>,
+    /// Pad indices to the width recorded in the given file, recording the width chosen for this
+    /// run if the file doesn't exist yet, so that separate runs over batches of files pad alike.
+    ///
+    /// Fails if a batch needs more digits than were recorded.
+    #[clap(long, value_name = "FILE")]
+    pub width_lock: Option<PathBuf>,
+    /// Reject rename specs using any of the given comma-separated replacement groups (e.g., `rand`
+    /// for `{rand:N}`), for specs from untrusted sources.
+    #[clap(long, value_name = "NAMES", value_delimiter = ',', value_parser = parse_placeholder_name)]
+    pub disable_placeholder: Vec<String>,
+    /// Rewrite target file names with a transform, after the rename spec (if any) is rendered. May
+    /// be given several times, to apply several transforms in order.
+    ///
+    /// Without a rename spec, files' current names are transformed. Transforms are
+    /// `strip-prefix=PREFIX`, `strip-suffix=SUFFIX` (from the stem), `lowercase`, `uppercase`,
+    /// `normalize-spaces`, `set-ext=EXT`, and `add-index`.
+    #[clap(long, value_name = "TRANSFORM")]
+    pub transform: Vec<BuiltinTransform>,
+    /// Change the case of every letter in target file names (but not their parent directories),
+    /// after they are otherwise determined (including by `--transform`).
+    #[clap(long, default_value_t, value_enum)]
+    pub case: Case,
+    /// Ask how to resolve each rename whose target is shared with another file, or already
+    /// exists: by choosing a new file name, overwriting the target, or skipping the file.
+    ///
+    /// Ignored with a warning, in favor of `--collision-strategy`, if standard input is not a
+    /// terminal.
+    #[clap(long)]
+    pub resolve_conflicts_interactively: bool,
+    /// What to do when multiple files would be renamed to the same target.
+    #[clap(long, default_value_t, value_enum)]
+    pub collision_strategy: CollisionStrategy,
+    /// How to tell apart files from different directories that would be renamed to the same
+    /// target, e.g., when flattening them into one directory with `{name}`.
+    ///
+    /// Applied before `--collision-strategy`, which handles any collisions that remain.
+    #[clap(long, default_value_t, value_enum)]
+    pub on_flatten_collision: OnFlattenCollision,
+    /// When a file has to be copied to another file system rather than renamed, check that the
+    /// copy's contents match the original before removing the original.
+    #[clap(long)]
+    pub verify_copy: bool,
+    /// Copy files to their targets, rather than renaming them, so that the originals remain.
+    ///
+    /// Fails if a target is one of the selected files, which would be overwritten.
+    #[clap(long, conflicts_with = "verify_copy")]
+    pub copy: bool,
+    /// Link targets to the selected files, rather than renaming them, so that the originals
+    /// remain without their contents being duplicated. Symbolic links point to the absolute paths
+    /// of the originals.
+    ///
+    /// Fails if a target is one of the selected files, like `--copy`.
+    #[clap(long, value_enum, conflicts_with_all = ["copy", "verify_copy"])]
+    pub link: Option<Link>,
+    /// Overwrite files that already exist at targets, but weren't selected, rather than refusing
+    /// to rename.
+    #[clap(long, overrides_with = "no_clobber")]
+    pub clobber: bool,
+    /// Refuse to rename if any target already exists, but wasn't selected. This is the default,
+    /// so this only undoes an earlier `--clobber`.
+    #[clap(long, overrides_with = "clobber")]
+    pub no_clobber: bool,
+    /// Once done renaming, save a plan that undoes every rename that was performed to the given
+    /// path, which `apply` can perform (with `--safe`, if renames permuted names).
+    ///
+    /// Nothing is saved for dry runs.
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["copy", "link"])]
+    pub undo_log: Option<PathBuf>,
+    /// What to do when renaming a single file fails. Either way, the run exits with an error if
+    /// any file failed.
+    #[clap(long, default_value_t, value_enum)]
+    pub on_error: OnError,
+    /// Stop at the first file that fails to be renamed; short for `--on-error=abort`.
+    #[clap(long, conflicts_with = "on_error")]
+    pub fail_fast: bool,
+    /// The maximum time to wait for any single rename before treating it as failed, e.g., `500ms`
+    /// or `30s`.
+    ///
+    /// Useful on network or FUSE file systems, where a rename may hang indefinitely. Renames can't
+    /// be cancelled, so the thread performing a timed-out rename is left running in the
+    /// background until the process exits.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub op_timeout: Option<Duration>,
+    /// Create any missing parent directories of renamed paths.
+    ///
+    /// Directory separators are those of the current platform, so `\` may be used on Windows.
+    #[clap(long)]
+    pub mkdirs: bool,
+    /// Put renamed files in `DIR`, which is created if it's missing, by treating the names that
+    /// they're given as relative to it. Files may be moved across file systems, in which case they
+    /// are copied, and then removed.
+    #[clap(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+    /// Move every file to a temporary name before moving any file to its target, so that renames
+    /// may safely permute names (e.g., `a` to `b` and `b` to `a`).
+    ///
+    /// Files that fail to reach their targets are moved back from their temporary names where
+    /// possible, as are all files not yet at their targets when `--on-error=abort` stops the run.
+    #[clap(long)]
+    pub safe: bool,
+    /// Rename files with up to the given number of threads.
+    ///
+    /// Renames are split up by the directories they touch: renames in unrelated directories run
+    /// concurrently, while those sharing a directory (e.g., in chains or cycles) run in order on
+    /// one thread.
+    ///
+    /// This also applies to `--copy` and `--link`. Failures from every thread are reported
+    /// together, each naming the file that failed.
+    #[clap(
+        long,
+        value_name = "N",
+        default_value = "1",
+        conflicts_with = "confirm_each"
+    )]
+    pub jobs: NonZeroUsize,
+    /// With `--safe`, the suffix which, followed by a file's index, is appended to its target to
+    /// form its temporary name.
+    ///
+    /// If that name is already taken, `-1`, `-2`, etc. are appended until it isn't.
+    #[clap(long, default_value = DEFAULT_TMP_SUFFIX, requires = "safe")]
+    pub tmp_suffix: OsString,
+    /// Only rename selected files with one of the given extensions (compared case-insensitively,
+    /// with or without a leading `.`).
+    ///
+    /// May be repeated.
+    #[clap(long, value_name = "EXT", value_parser = parse_ext)]
+    pub filter_ext: Vec<String>,
+    /// Leave out selected files with any of the given extensions (compared case-insensitively,
+    /// with or without a leading `.`), even if they are also given to `--filter-ext`.
+    ///
+    /// May be repeated.
+    #[clap(long, value_name = "EXT", value_parser = parse_ext)]
+    pub exclude_ext: Vec<String>,
+    /// A regular expression matched against file names, whose capture groups may be inserted
+    /// with `{cap1}`, `{cap2}`, and so on. It is an error for a file name not to match, unless
+    /// `--skip-unmatched` is given.
+    #[clap(long = "match", value_name = "REGEX", value_parser = Regex::new)]
+    pub match_pattern: Option<Regex>,
+    /// Leave out selected files whose names don't match `--match`, rather than failing to rename
+    /// them.
+    #[clap(long, requires = "match_pattern")]
+    pub skip_unmatched: bool,
+    /// Start numbering over from the first index after every `N` files, padding indices as if
+    /// there were only `N` files.
+    #[clap(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "group_by",
+            "index_field_only",
+            "positional_names",
+            "fill_gaps",
+            "stable_across_extensions",
+            "sidecar_index",
+        ],
+    )]
+    pub reset_every: Option<NonZeroUsize>,
+    /// Number the files in each directory separately, as if grouped with `--group-by`, so that
+    /// `{group}` is the directory.
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "group_by",
+            "reset_every",
+            "index_field_only",
+            "positional_names",
+            "fill_gaps",
+            "stable_across_extensions",
+            "sidecar_index",
+        ],
+    )]
+    pub reset_per_dir: bool,
+    /// A regular expression matched against file names to sort them into groups, each of which is
+    /// numbered separately.
+    ///
+    /// A group is identified by the first capture group of the pattern, if it has any, or the whole
+    /// match otherwise, and may be inserted with `{group}`. It is an error for a file name not to
+    /// match.
+    #[clap(long, value_parser = Regex::new)]
+    pub group_by: Option<Regex>,
+    /// Use the given text for `{tag:NAME}` in files without the tag (or with only characters that
+    /// can't be in file names), rather than failing to rename them.
+    #[cfg(feature = "id3v1-tags")]
+    #[clap(long, value_name = "TEXT")]
+    pub missing_tag: Option<String>,
+    /// Leave files whose image dimensions can't be read out of the selection, rather than failing
+    /// to rename them, when `{width}` or `{height}` are used.
+    #[cfg(feature = "image")]
+    #[clap(long)]
+    pub skip_non_images: bool,
+    /// A pattern describing renamed path names, made of literal text and replacement groups.
+    ///
+    /// Supported replacement groups are:
+    ///
+    /// - `{padded_idx}`: the index of the file, zero-padded to the width of the largest index.
+    ///
+    /// - `{idx}`: the index of the file, without any padding.
+    ///
+    /// - `{padded_idx1}`, `{idx1}`: like `{padded_idx}` and `{idx}`, but one more, i.e., counting
+    ///   from one.
+    ///
+    /// - `{alpha}`, `{ALPHA}`: the index of the file in lowercase or uppercase letters, like
+    ///   spreadsheet columns: `a`, `b`, …, `z`, `aa`, `ab`, ….
+    ///
+    /// - `{roman}`, `{roman_lower}`: the index of the file in uppercase or lowercase Roman numerals.
+    ///   There's no Roman numeral for 0, so use these with `--start-at 1`.
+    ///
+    /// - `{total}`, `{padded_total}`: the number of files being renamed, e.g., for
+    ///   `page-{padded_idx}-of-{total}`, unpadded or zero-padded to the width of `{padded_idx}`.
+    ///
+    /// - `{padded_dir_idx}`, `{dir_idx}`: like `{padded_idx}` and `{idx}`, but counting files in
+    ///   each source directory separately, and padded to the width of the largest index in the
+    ///   directory. These can't be used with `--fill-gaps` or `--sidecar-index`, which choose
+    ///   indices themselves.
+    ///
+    /// - `{date}`: the date that the file was last modified, in UTC, as `YYYYMMDD`.
+    ///
+    /// - `{mtime:FORMAT}`: the time that the file was last modified, in UTC, where `%Y`, `%m`, `%d`,
+    ///   `%H`, `%M`, and `%S` in `FORMAT` are replaced by the year, month, day, hour, minute, and
+    ///   second, and `%%` by `%`.
+    ///
+    /// - `{group}`: the group of the file, as determined by `--group-by` or `--reset-per-dir`.
+    ///
+    /// - `{cap1}`, `{cap2}`, …: the capture groups of `--match` in the file name of the source.
+    ///
+    /// - `{name}`: the file name of the source, without its extension (see `--keep-ext`), e.g.,
+    ///   for flattening files from many directories into one.
+    ///
+    /// - `{ext}`: the extension of the source, without its leading `.`. For sources without an
+    ///   extension (including dotfiles like `.bashrc`), this is empty, and a `.` just before it is
+    ///   left out, so that `{name}.{ext}` keeps the original name.
+    ///
+    /// - `{global_idx}`: the position of the file among all selected files, even with
+    ///   `--group-by`, continuing from the count kept in `--counter-file`, so that it never
+    ///   repeats across runs.
+    ///
+    /// - `{tail}`: the part of the source's stem after the run of digits that varies between
+    ///   selected files (or the first run, if none varies), without leading `_`, `-`, `.`, or
+    ///   spaces. Empty for files without such a run.
+    ///
+    /// - `{rand:N}`: `N` random lowercase letters and digits, which differ for each file (see also
+    ///   `--seed`).
+    ///
+    /// - `{tag:NAME}`: the `title`, `artist`, `album`, `year`, `comment`, or `track` field of the
+    ///   ID3v1 tag of the source file, with characters not permitted in file names replaced with
+    ///   `_`, if built with the `id3v1-tags` feature. Other tag formats, like the ID3v2 tags most
+    ///   MP3 files carry, aren't read. See also
+    ///   `--missing-tag`.
+    ///
+    /// - `{width}`, `{height}`: the pixel dimensions of the source image, if built with the `image`
+    ///   feature.
+    ///
+    /// Text from `{group}`, `{capN}`, `{name}`, `{ext}`, `{tail}`, and `{tag:NAME}` may be
+    /// changed to `lower`, `upper`, or `title` case by writing it after a `:`, e.g.,
+    /// `{name:lower}-{padded_idx}`. Other replacement groups can't be changed this way.
     ///
     /// Further examples:
     ///
-    /// - `photo-{padded_idx}.jpg` # `photo-1.jpg`, `photo-2.jpg`, etc.
+    /// - `photo-{padded_idx}.jpg` # `photo-0.jpg`, `photo-1.jpg`, etc.
     ///
-    /// - `asdf.txt` # Renames all files, in succession, to `asdf.txt`. You probably don't want
-    /// this.
-    pub rename_spec: String,
+    /// - `asdf.txt` # Refused for more than one file, since they would all be renamed to
+    ///   `asdf.txt`.
+    ///
+    /// - `{{padded_idx}}-{padded_idx}` # `{padded_idx}-0`, `{padded_idx}-1`, etc.; `{{` and `}}`
+    ///   are literal braces, and a lone `}` is an error.
+    ///
+    /// Required, unless `--capabilities`, `--suggest-spec`, `--index-field-only`, `--mirror`,
+    /// `--names-from`, `--transform`, or `apply` are used. With `reverse-spec`, this is the spec
+    /// that files were renamed with.
+    pub rename_spec: Option<String>,
+    /// Always present, unless `--capabilities` is specified.
     #[clap(subcommand)]
-    pub selection: Selection,
+    pub command: Option<Command>,
+}
+
+/// What [`MainArgs`] should rename.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    #[clap(flatten)]
+    Select(Selection),
+    /// Perform the renames of a plan saved by `--dry-run-to`, after checking that they are still
+    /// possible.
+    ///
+    /// A rename spec is not used.
+    Apply { plan: PathBuf },
+    /// Rename `files`, whose names were rendered by the rename spec, back to the names rendered by
+    /// `original` for the same indices, e.g., to undo a rename without a saved plan.
+    ///
+    /// This is best-effort: it only works if both specs use `{padded_idx}` as their only kind of
+    /// replacement group, so that each name maps to exactly one index and back. Original names
+    /// are padded to as many digits as the widest index in `files`.
+    ReverseSpec {
+        original: String,
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+fn parse_ext(ext: &str) -> eyre::Result<String> {
+    let ext = ext.strip_prefix('.').unwrap_or(ext);
+    if ext.is_empty() {
+        bail!("expected a non-empty extension");
+    }
+    Ok(ext.to_lowercase())
+}
+
+fn parse_numbering_base(base: &str) -> eyre::Result<u32> {
+    let base = match base {
+        "bin" | "binary" => 2,
+        "oct" | "octal" => 8,
+        "dec" | "decimal" => 10,
+        "hex" | "hexadecimal" => 16,
+        base => base
+            .parse()
+            .ok()
+            .filter(|base| (2..=36).contains(base))
+            .ok_or_else(|| {
+                eyre!("expected `bin`, `oct`, `dec`, `hex`, or a number from 2 to 36")
+            })?,
+    };
+    Ok(base)
+}
+
+fn parse_placeholder_name(name: &str) -> eyre::Result<String> {
+    if DynamicRenameContent::names().any(|n| n == name) {
+        Ok(name.to_owned())
+    } else {
+        Err(eyre!(
+            "unrecognized replacement group; expected one of {:?}",
+            DynamicRenameContent::names().collect::<Vec<_>>()
+        ))
+    }
 }
 
 /// Represents a selection of files in [`MainArgs`], according to rules that differ between variants.
 #[derive(Clone, Debug, Parser)]
 pub enum Selection {
     /// Select files by shell arguments, in the order provided.
-    FromFiles { files: Vec<PathBuf> },
+    FromFiles {
+        /// Permit selecting directories, which are otherwise rejected, like a glob would skip them.
+        #[clap(long)]
+        include_dirs: bool,
+        files: Vec<PathBuf>,
+    },
     /// Select files via cross-platform globbing.
     FromGlob {
-        /// A `wax` glob pattern to use to list files to be renamed.
-        glob: String,
-        /// The way that paths matching `glob` should be sorted.
-        #[clap(long, default_value_t, value_enum)]
+        /// One or more `wax` glob patterns to use to list files to be renamed.
+        #[clap(required = true)]
+        globs: Vec<String>,
+        /// The way that paths matching `globs` should be sorted, i.e., one of `discovered`,
+        /// `lexicographical`, `match-order`, `sidecar=FIELD`, `natural`, or `modified-time`. All
+        /// but `discovered` sort in ascending order, which the top-level `--reverse` flag flips.
+        #[clap(long, default_value_t)]
         sort_by: SortBy,
+        /// The same as the top-level `--reverse` flag, from when it only applied to `from-glob`.
+        #[clap(long, hide = true)]
+        reverse: bool,
+        /// A `wax` glob pattern of paths to leave out, even if they match `globs`, e.g.,
+        /// `**/.cache/**` for anything under a `.cache` directory. May be given more than once.
+        #[clap(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+        /// How many directories deep to look for matches of each of `globs`, counting from the
+        /// fixed part at its start (e.g., `photos` in `photos/**/*.jpg`, or the current directory
+        /// for `**/*.jpg`). `1` only looks at files directly in that directory. Unbounded by
+        /// default.
+        #[clap(long, value_name = "N")]
+        max_depth: Option<usize>,
+        /// Select symbolic links to files, and look for matches in directories that symbolic links
+        /// point to, which are otherwise skipped. Links are renamed themselves, not their targets.
+        ///
+        /// A link that leads back to a directory that contains it is reported as an error, rather
+        /// than followed endlessly.
+        #[clap(long)]
+        follow_symlinks: bool,
+        /// The directory that relative `globs` are matched in. Selected files are still named by
+        /// paths relative to the current directory (e.g., `../photos/a.jpg` for `a.jpg` with
+        /// `--base-dir ../photos`), or by absolute paths if `PATH` is absolute.
+        #[clap(long, value_name = "PATH", default_value = ".")]
+        base_dir: PathBuf,
     },
 }
 
 impl Selection {
+    /// Uses files in the order they were provided or discovered, regardless of any sorting
+    /// specified for this selection.
+    pub fn without_sorting(self) -> Self {
+        match self {
+            Self::FromFiles { .. } => self,
+            Self::FromGlob {
+                globs,
+                reverse,
+                exclude,
+                max_depth,
+                follow_symlinks,
+                base_dir,
+                ..
+            } => Self::FromGlob {
+                globs,
+                sort_by: SortBy::Discovered,
+                reverse,
+                exclude,
+                max_depth,
+                follow_symlinks,
+                base_dir,
+            },
+        }
+    }
+
     pub fn files(self) -> eyre::Result<Vec<PathBuf>> {
         let files = match self {
-            Selection::FromFiles { files } => files,
-            Selection::FromGlob { glob, sort_by } => {
-                let glob = Glob::new(&glob).map_err(|source| CliGlobParseError {
-                    source: source.into_owned(),
-                })?;
-
-                let (mut files, fs_errs): (Vec<_>, Vec<_>) = glob
-                    .walk(".")
-                    .filter_tree(|entry| {
-                        entry
-                            .file_type()
-                            .is_file()
-                            .not()
-                            .then_some(FilterTarget::File)
+            Selection::FromFiles {
+                include_dirs,
+                files,
+            } => {
+                if !include_dirs {
+                    let dirs = files
+                        .iter()
+                        .filter(|path| path.is_dir())
+                        .collect::<Vec<_>>();
+                    if !dirs.is_empty() {
+                        bail!("refusing to select directories without `--include-dirs`: {dirs:?}");
+                    }
+                }
+                files
+            }
+            Selection::FromGlob {
+                globs,
+                sort_by,
+                reverse: _,
+                exclude,
+                max_depth,
+                follow_symlinks,
+                base_dir,
+            } => {
+                let globs = globs
+                    .iter()
+                    .map(|glob| {
+                        Glob::new(glob).map_err(|source| CliGlobParseError {
+                            source: source.into_owned(),
+                        })
                     })
-                    .partition_map(|res| match res {
-                        Ok(entry) => Either::Left(entry.into_path()),
-                        Err(e) => Either::Right(e),
-                    });
-
-                if !fs_errs.is_empty() {
-                    fs_errs.into_iter().fold(
-                        Err(eyre!("encountered one or more file system errors")),
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let behavior = WalkBehavior {
+                    depth: max_depth.unwrap_or(usize::MAX),
+                    link: match follow_symlinks {
+                        true => LinkBehavior::ReadTarget,
+                        false => LinkBehavior::ReadFile,
+                    },
+                };
+                let mut seen = HashSet::new();
+                let mut fs_errs = Vec::new();
+                let mut matches_by_glob = Vec::with_capacity(globs.len());
+                for glob in &globs {
+                    let (matches, errs): (Vec<_>, Vec<_>) = glob
+                        .walk_with_behavior(&base_dir, behavior)
+                        .not(exclude.iter().map(|pattern| pattern.as_str()))
+                        .map_err(|source| CliGlobParseError {
+                            source: source.into_owned(),
+                        })?
+                        .filter_tree(|entry| {
+                            entry
+                                .file_type()
+                                .is_file()
+                                .not()
+                                .then_some(FilterTarget::File)
+                        })
+                        .partition_map(|res| match res {
+                            Ok(entry) => Either::Left(entry.into_path()),
+                            Err(e) => Either::Right(e),
+                        });
+                    fs_errs.extend(errs);
+                    // A file matched by more than one glob belongs to the first.
+                    matches_by_glob.push(
+                        matches
+                            .into_iter()
+                            .filter(|path| seen.insert(path.clone()))
+                            .collect::<Vec<_>>(),
+                    );
+                }
+
+                let mut mtimes = HashMap::new();
+                let mut mtime_errs = Vec::new();
+                if let SortBy::ModifiedTime = &sort_by {
+                    for path in matches_by_glob.iter().flatten() {
+                        match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+                            Ok(mtime) => {
+                                mtimes.insert(path.clone(), mtime);
+                            }
+                            Err(source) => mtime_errs.push(ReadModifiedTimeError {
+                                path: path.clone(),
+                                source,
+                            }),
+                        }
+                    }
+                }
+
+                if !fs_errs.is_empty() || !mtime_errs.is_empty() {
+                    let report = fs_errs.into_iter().fold(
+                        eyre!("encountered one or more file system errors"),
                         |report, e| report.error(e),
-                    )?;
+                    );
+                    return Err(mtime_errs
+                        .into_iter()
+                        .fold(report, |report, e| report.error(e)));
                 }
 
+                if let SortBy::MatchOrder = &sort_by {
+                    matches_by_glob
+                        .iter_mut()
+                        .for_each(|matches| matches.sort());
+                }
+                let mut files = matches_by_glob.concat();
                 match sort_by {
-                    SortBy::Discovered => (),
+                    SortBy::Discovered | SortBy::MatchOrder => (),
                     SortBy::Lexicographical => files.sort(),
+                    SortBy::Natural => files
+                        .sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())),
+                    SortBy::Sidecar { field } => sort_by_sidecar(&mut files, &field)?,
+                    SortBy::ModifiedTime => {
+                        files.sort_by(|a, b| mtimes[a].cmp(&mtimes[b]).then_with(|| a.cmp(b)))
+                    }
                 };
 
                 files
@@ -91,25 +809,260 @@ impl Selection {
     }
 }
 
-#[derive(Clone, Copy, Debug, ValueEnum)]
-pub enum Order {
-    Sequential,
-    SingleSidedScans,
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OnError {
+    /// Log the failure, and move on to the next file, exiting with an error once done.
+    #[default]
+    Continue,
+    /// Stop renaming files, and exit with an error.
+    Abort,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum CollisionStrategy {
+    /// Refuse to rename any files.
+    #[default]
+    Error,
+    /// Append the time each colliding file was last modified (e.g., `-143022` for 14:30:22 UTC)
+    /// before its extension, failing if targets still collide.
+    Timestamp,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Only log what happens, on standard error.
+    #[default]
+    Text,
+    /// Also print a single JSON object of counts once the run is done (even if it fails), of the
+    /// form `{"renamed": n, "skipped": n, "failed": n, "conflicts": n, "total": n, "dry_run":
+    /// bool}`, where `conflicts` counts existing files overwritten with `--clobber`.
+    SummaryJson,
+    /// Instead of renaming anything, print the plan as a JSON array of the form `[{"index": n,
+    /// "from": "…", "to": "…"}, …]`, where `to` is `null` (with an `"error"` saying why) if it
+    /// couldn't be determined. Paths that aren't valid UTF-8 are objects of their raw bytes (e.g.,
+    /// `{"unix_bytes": [n, …]}`) instead of strings.
+    Json,
+    /// Instead of renaming anything, print the planned renames as `--table` does, but with an
+    /// arrow between each source and target.
+    Table,
 }
 
 #[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Link {
+    /// A hard link, which only works within a single file system.
+    Hard,
+    /// A symbolic link.
+    Symlink,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OnFlattenCollision {
+    /// Leave colliding targets to `--collision-strategy`.
+    #[default]
+    Error,
+    /// Append each colliding file's position among those sharing its target (e.g., `-1`, `-2`)
+    /// before its extension.
+    Number,
+    /// Move each colliding file into a subdirectory of its target's directory named after the
+    /// file's own parent directory (e.g., `flat/a/img.jpg` for `a/img.jpg`). Requires `--mkdirs`
+    /// for directories that don't exist yet.
+    Subdir,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Case {
+    Lower,
+    Upper,
+    /// Leave target file names as they are.
+    #[default]
+    Preserve,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub enum SortBy {
+    /// `discovered`
     Discovered,
+    /// `lexicographical`
+    #[default]
     Lexicographical,
-    // TODO: add natural sort; probably use <https://docs.rs/lexical-sort/>
+    /// `match-order`: all matches of the first glob, then all of the second, etc., each sorted
+    /// lexicographically.
+    MatchOrder,
+    /// `sidecar=FIELD`: ascending by the number in `FIELD` of each file's JSON sidecar, e.g., the
+    /// file with the same stem and a `json` extension. Files without a sidecar, or whose sidecar
+    /// lacks `FIELD`, come after all those with one. Ties are sorted lexicographically.
+    Sidecar { field: String },
+    /// `modified-time`: by when each file was last modified, oldest first. Ties are sorted
+    /// lexicographically.
+    ModifiedTime,
+    /// `natural`: lexicographically, except that runs of digits are compared by their numeric
+    /// value, e.g., `img2.png` comes before `img10.png`.
+    Natural,
+}
+
+impl SortBy {
+    /// The syntax of every sort mode.
+    pub const SYNTAXES: &'static [&'static str] = &[
+        "discovered",
+        "lexicographical",
+        "match-order",
+        "sidecar=FIELD",
+        "natural",
+        "modified-time",
+    ];
+}
+
+impl FromStr for SortBy {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once('=') {
+            None if s == "discovered" => Self::Discovered,
+            None if s == "lexicographical" => Self::Lexicographical,
+            None if s == "match-order" => Self::MatchOrder,
+            None if s == "natural" => Self::Natural,
+            None if s == "modified-time" => Self::ModifiedTime,
+            Some(("sidecar", field)) if !field.is_empty() => Self::Sidecar {
+                field: field.to_owned(),
+            },
+            _ => bail!(
+                "unrecognized sort mode; expected one of {:?}",
+                Self::SYNTAXES
+            ),
+        })
+    }
 }
 
-impl Default for SortBy {
-    fn default() -> Self {
-        Self::Lexicographical
+impl Display for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Discovered => f.write_str("discovered"),
+            Self::Lexicographical => f.write_str("lexicographical"),
+            Self::MatchOrder => f.write_str("match-order"),
+            Self::Sidecar { field } => write!(f, "sidecar={field}"),
+            Self::Natural => f.write_str("natural"),
+            Self::ModifiedTime => f.write_str("modified-time"),
+        }
+    }
+}
+
+/// Compares `a` and `b` as described by [`SortBy::Natural`]. Numerically equal runs of digits are
+/// ordered by their number of leading zeroes, so that distinct names never compare equal.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn digit_run(s: &str) -> (&str, &str) {
+        s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+    }
+
+    let (mut a, mut b) = (a, b);
+    let mut by_leading_zeroes = Ordering::Equal;
+    loop {
+        let (Some(a_char), Some(b_char)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len()).then(by_leading_zeroes);
+        };
+        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let ((a_digits, a_rest), (b_digits, b_rest)) = (digit_run(a), digit_run(b));
+            let (a_num, b_num) = (
+                a_digits.trim_start_matches('0'),
+                b_digits.trim_start_matches('0'),
+            );
+            let by_value = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+            if by_value.is_ne() {
+                return by_value;
+            }
+            by_leading_zeroes = by_leading_zeroes.then(a_digits.len().cmp(&b_digits.len()));
+            (a, b) = (a_rest, b_rest);
+        } else {
+            let by_char = a_char.cmp(&b_char);
+            if by_char.is_ne() {
+                return by_char;
+            }
+            (a, b) = (&a[a_char.len_utf8()..], &b[b_char.len_utf8()..]);
+        }
     }
 }
 
+/// Sorts `files` as described by [`SortBy::Sidecar`].
+fn sort_by_sidecar(files: &mut [PathBuf], field: &str) -> eyre::Result<()> {
+    let mut errs = Vec::new();
+    let keys = files
+        .iter()
+        .map(|path| {
+            let key = sidecar_key(path, field);
+            key.unwrap_or_else(|e| {
+                errs.push(e);
+                None
+            })
+        })
+        .collect::<Vec<_>>();
+    if !errs.is_empty() {
+        return Err(errs.into_iter().fold(
+            eyre!("failed to read one or more sort sidecars"),
+            |report, e| report.error(e),
+        ));
+    }
+
+    let mut keyed = keys
+        .into_iter()
+        .zip(files.iter().cloned())
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a_key, a), (b_key, b)| {
+        let by_key = match (a_key, b_key) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        by_key.then_with(|| a.cmp(b))
+    });
+    for (slot, (_key, path)) in files.iter_mut().zip(keyed) {
+        *slot = path;
+    }
+    Ok(())
+}
+
+/// Reads the number in `field` of the JSON sidecar of `path`, if it has one.
+fn sidecar_key(path: &Path, field: &str) -> Result<Option<f64>, SortSidecarError> {
+    let sidecar = path.with_extension("json");
+    let contents = match fs::read(&sidecar) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(ReadSidecarSnafu { path: sidecar }),
+    };
+    let value: serde_json::Value =
+        serde_json::from_slice(&contents).context(ParseSidecarSnafu { path: &sidecar })?;
+    match value.get(field) {
+        None => Ok(None),
+        Some(key) => match key.as_f64() {
+            Some(key) => Ok(Some(key)),
+            None => NonNumericSidecarFieldSnafu {
+                path: sidecar,
+                field,
+            }
+            .fail(),
+        },
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum SortSidecarError {
+    #[snafu(display("failed to read sort sidecar {path:?}"))]
+    ReadSidecar { path: PathBuf, source: io::Error },
+    #[snafu(display("failed to parse sort sidecar {path:?}"))]
+    ParseSidecar {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[snafu(display("expected a number in field `{field}` of sort sidecar {path:?}"))]
+    NonNumericSidecarField { path: PathBuf, field: String },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("failed to read modification time of {path:?}"))]
+pub struct ReadModifiedTimeError {
+    path: PathBuf,
+    source: io::Error,
+}
+
 #[derive(Debug, Snafu)]
 #[snafu(display("failed to parse image pattern"))]
 pub struct CliGlobParseError {
@@ -0,0 +1,1531 @@
+use crate::{
+    cli::OnError,
+    hash::{hash_file, ContentHash},
+    plan::PlanCollector,
+    progress::PROGRESS,
+};
+use rename_seq::{RenderError, Visitor};
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, Write},
+    num::NonZeroUsize,
+    ops::{AddAssign, ControlFlow},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The file system operations that [`ZipVisitor`] performs, abstracted so that tests can inject
+/// fakes.
+pub trait Fs: Send + Sync {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Copies the contents (and permissions) of `from` to `to`, returning the number of bytes
+    /// copied.
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64>;
+    /// Sets the modification time of `to` to that of `from`.
+    fn copy_modified_time(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Creates a hard link at `link` to the file at `original`.
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()>;
+    /// Creates a symbolic link at `link` pointing to the file at `original`.
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn content_hash(&self, path: &Path) -> io::Result<ContentHash>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+}
+
+/// The [`Fs`] implementation backed by [`std::fs`].
+#[derive(Clone, Copy, Debug)]
+pub struct RealFs;
+
+impl Fs for RealFs {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        fs::copy(from, to)
+    }
+
+    fn copy_modified_time(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let modified = fs::metadata(from)?.modified()?;
+        fs::File::options()
+            .write(true)
+            .open(to)?
+            .set_modified(modified)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+
+    /// Points `link` at the absolute path of `original`, since a relative path would be resolved
+    /// against the directory of `link`, rather than the current directory.
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        let original = std::path::absolute(original)?;
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(original, link);
+        // Windows distinguishes links to files from links to directories.
+        #[cfg(windows)]
+        return if original.is_dir() {
+            std::os::windows::fs::symlink_dir(original, link)
+        } else {
+            std::os::windows::fs::symlink_file(original, link)
+        };
+        #[cfg(not(any(unix, windows)))]
+        return {
+            let _ = (original, link);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "symbolic links are not supported on this platform",
+            ))
+        };
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn content_hash(&self, path: &Path) -> io::Result<ContentHash> {
+        hash_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        path.try_exists()
+    }
+}
+
+/// How [`ZipVisitor`] puts each file at its target.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TransferMode {
+    /// Move the file, so that nothing is left at its source.
+    #[default]
+    Rename,
+    /// Copy the file, leaving its source in place.
+    Copy,
+    /// Create a hard link to the file, which must be on the same file system.
+    HardLink,
+    /// Create a symbolic link to the file.
+    Symlink,
+}
+
+impl TransferMode {
+    /// Describes this mode in progress messages, e.g., `renaming {from:?} to {to:?}`.
+    fn verb(self) -> &'static str {
+        match self {
+            Self::Rename => "renaming",
+            Self::Copy => "copying",
+            Self::HardLink => "hard linking",
+            Self::Symlink => "symlinking",
+        }
+    }
+}
+
+/// Options controlling how [`ZipVisitor`] performs renames.
+#[derive(Clone, Debug)]
+pub struct ExecOptions {
+    pub dry_run: bool,
+    pub mode: TransferMode,
+    pub on_error: OnError,
+    /// The maximum time to wait for a single file operation before treating it as failed.
+    ///
+    /// Operations like [`fs::rename`] can't be cancelled, so when a timeout elapses, the worker
+    /// thread performing the operation is leaked; it may still complete (or hang forever) in the
+    /// background.
+    pub op_timeout: Option<Duration>,
+    /// Whether to create missing parent directories of targets before renaming into them.
+    pub mkdirs: bool,
+    /// Whether to check that files copied between file systems match their sources before
+    /// removing the sources.
+    pub verify_copy: bool,
+    /// Whether to defer renames to [`ZipVisitor::finish`], which first moves every file to a
+    /// temporary name, and only then to its target.
+    pub two_phase: bool,
+    /// Appended to a target path, followed by the file's index, to form its temporary name in
+    /// [`Self::two_phase`] renaming.
+    pub tmp_suffix: OsString,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            mode: TransferMode::default(),
+            on_error: OnError::default(),
+            op_timeout: None,
+            mkdirs: false,
+            verify_copy: false,
+            two_phase: false,
+            tmp_suffix: DEFAULT_TMP_SUFFIX.into(),
+        }
+    }
+}
+
+pub const DEFAULT_TMP_SUFFIX: &str = ".rename-seq-tmp-";
+
+/// A decision made by a [`Prompt`] about a single rename.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    Rename,
+    Skip,
+    /// Skip this rename and every one after it.
+    Quit,
+}
+
+/// Asks for a [`Decision`] about each rename that [`ZipVisitor`] would perform.
+pub trait Prompt {
+    fn decide(&mut self, from: &Path, to: &Path) -> io::Result<Decision>;
+}
+
+/// A [`Prompt`] that writes a question to `output` and reads `y`, `n`, or `q` lines from `input`,
+/// asking again on anything else.
+///
+/// Reaching the end of `input` is treated as [`Decision::Quit`].
+pub struct LinePrompt<R, W> {
+    pub input: R,
+    pub output: W,
+}
+
+impl<R: BufRead, W: Write> LinePrompt<R, W> {
+    /// Writes `question` to `output`, then reads a line from `input`, returning it trimmed, or
+    /// `None` at the end of `input`.
+    pub fn ask(&mut self, question: &str) -> io::Result<Option<String>> {
+        let Self { input, output } = self;
+
+        write!(output, "{question} ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(None);
+        }
+        Ok(Some(line.trim().to_owned()))
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt for LinePrompt<R, W> {
+    fn decide(&mut self, from: &Path, to: &Path) -> io::Result<Decision> {
+        loop {
+            let Some(answer) = self.ask(&format!("rename {from:?} to {to:?}? [y/n/q]"))? else {
+                return Ok(Decision::Quit);
+            };
+            match answer.to_ascii_lowercase().as_str() {
+                "y" | "yes" => return Ok(Decision::Rename),
+                "n" | "no" => return Ok(Decision::Skip),
+                "q" | "quit" => return Ok(Decision::Quit),
+                _ => writeln!(self.output, "please answer `y`, `n`, or `q`")?,
+            }
+        }
+    }
+}
+
+/// Writes `Proceed? [y/N]` to `output` and reads a line from `input`, returning whether it was `y`
+/// or `yes`. Anything else, including reaching the end of `input`, is a refusal.
+pub fn confirm_proceed(mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+    write!(output, "Proceed? [y/N] ")?;
+    output.flush()?;
+
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        writeln!(output)?;
+        return Ok(false);
+    }
+    Ok(matches!(
+        line.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Counts of what a [`ZipVisitor`] has done so far.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RenameSummary {
+    /// Files renamed, or that would be renamed in a dry run.
+    pub renamed: usize,
+    /// Files left untouched because their rename was declined by a [`Prompt`].
+    pub skipped: usize,
+    /// Files that failed to be renamed.
+    pub failed: usize,
+    /// Bytes of file data copied by operations that move data, rather than just renaming files in
+    /// place. A plain rename within a file system transfers zero bytes.
+    pub bytes_transferred: u64,
+    /// Total time spent performing file operations.
+    pub elapsed: Duration,
+}
+
+impl AddAssign for RenameSummary {
+    fn add_assign(&mut self, other: Self) {
+        let Self {
+            renamed,
+            skipped,
+            failed,
+            bytes_transferred,
+            elapsed,
+        } = other;
+        self.renamed += renamed;
+        self.skipped += skipped;
+        self.failed += failed;
+        self.bytes_transferred += bytes_transferred;
+        self.elapsed += elapsed;
+    }
+}
+
+pub struct ZipVisitor {
+    opts: ExecOptions,
+    fs: Arc<dyn Fs>,
+    /// Renames deferred by [`ExecOptions::two_phase`], as `(idx, from, to)`.
+    pending: Vec<(usize, PathBuf, PathBuf)>,
+    prompt: Option<Box<dyn Prompt>>,
+    /// Whether the [`Prompt`] answered [`Decision::Quit`].
+    quit: bool,
+    summary: RenameSummary,
+    /// Each failure counted in the [`RenameSummary`], along with its causes.
+    failures: Vec<String>,
+    /// Every rename performed, as `(from, to)`, in the order they were completed.
+    completed: Vec<(PathBuf, PathBuf)>,
+}
+
+/// What one or more [`ZipVisitor`]s did, once they're done.
+#[derive(Clone, Debug, Default)]
+pub struct ExecOutcome {
+    pub summary: RenameSummary,
+    /// Describes each failure, including its causes (e.g., `failed to rename file "a" to "b": No
+    /// such file or directory`).
+    pub failures: Vec<String>,
+    /// Every rename performed, as `(from, to)`, in an order in which undoing them from last to
+    /// first restores the original names. Dry runs perform none.
+    pub completed: Vec<(PathBuf, PathBuf)>,
+}
+
+// [Workaround] for an upstream `tracing` issue where `tracing::event!(...)` only permits a constant
+// `$level` parameter.
+//
+// [Workaround]: https://github.com/tokio-rs/tracing/issues/372#issuecomment-762529515
+macro_rules! event {
+    ($level:expr, $($args:tt)*) => {{
+        use ::tracing::Level;
+
+        match $level {
+            Level::ERROR => ::tracing::event!(Level::ERROR, $($args)*),
+            Level::WARN => ::tracing::event!(Level::WARN, $($args)*),
+            Level::INFO => ::tracing::event!(Level::INFO, $($args)*),
+            Level::DEBUG => ::tracing::event!(Level::DEBUG, $($args)*),
+            Level::TRACE => ::tracing::event!(Level::TRACE, $($args)*),
+        }
+    }};
+}
+
+impl ZipVisitor {
+    pub fn new(opts: ExecOptions, fs: Arc<dyn Fs>) -> Self {
+        Self {
+            opts,
+            fs,
+            pending: Vec::new(),
+            prompt: None,
+            quit: false,
+            summary: RenameSummary::default(),
+            failures: Vec::new(),
+            completed: Vec::new(),
+        }
+    }
+
+    /// Asks `prompt` before performing each rename, skipping those it declines.
+    pub fn with_prompt(self, prompt: Box<dyn Prompt>) -> Self {
+        Self {
+            prompt: Some(prompt),
+            ..self
+        }
+    }
+
+    /// Returns everything this visitor did, once it's [finished](Self::finish).
+    pub fn into_outcome(self) -> ExecOutcome {
+        let Self {
+            summary,
+            failures,
+            completed,
+            ..
+        } = self;
+        ExecOutcome {
+            summary,
+            failures,
+            completed,
+        }
+    }
+
+    /// Counts and records the completed rename of `from` to `to`.
+    fn renamed(&mut self, from: &Path, to: &Path) {
+        PROGRESS.advance();
+        self.summary.renamed += 1;
+        self.completed.push((from.to_owned(), to.to_owned()));
+    }
+
+    /// Whether the rename of `from` to `to` should go ahead, according to the [`Prompt`], if any.
+    fn confirmed(&mut self, from: &Path, to: &Path) -> Result<bool, RenameError> {
+        let Some(prompt) = self.prompt.as_mut().filter(|_| !self.quit) else {
+            return Ok(!self.quit);
+        };
+        match prompt.decide(from, to).context(PromptSnafu { from })? {
+            Decision::Rename => Ok(true),
+            Decision::Skip => Ok(false),
+            Decision::Quit => {
+                self.quit = true;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Puts `from` at `to` as `mode` dictates, recording the time taken and any bytes transferred
+    /// in the [`RenameSummary`].
+    fn rename(&mut self, mode: TransferMode, from: &Path, to: &Path) -> Result<(), RenameError> {
+        let start = Instant::now();
+        let res = self.transfer(mode, from, to);
+        self.summary.elapsed += start.elapsed();
+        res.map(|bytes| self.summary.bytes_transferred += bytes)
+    }
+
+    /// Puts `from` at `to`, returning how many bytes of file data had to be copied to do so.
+    fn transfer(&self, mode: TransferMode, from: &Path, to: &Path) -> Result<u64, RenameError> {
+        let Self { opts, fs, .. } = self;
+
+        if opts.mkdirs {
+            // NOTE: `Path::parent` splits on all of the current platform's separators (e.g., both
+            // `/` and `\` on Windows), so we needn't handle them ourselves.
+            if let Some(parent) = to.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs.create_dir_all(parent)
+                    .context(CreateParentSnafu { parent, to })?;
+            }
+        }
+
+        let verify_copy = opts.verify_copy;
+        let Some(timeout) = opts.op_timeout else {
+            return transfer_file(&**fs, mode, from, to, verify_copy);
+        };
+
+        let (tx, rx) = mpsc::channel();
+        {
+            let fs = Arc::clone(fs);
+            let (from, to) = (from.to_owned(), to.to_owned());
+            thread::spawn(move || {
+                // The receiver may have given up on us already; nothing to do if so.
+                let _ = tx.send(transfer_file(&*fs, mode, &from, &to, verify_copy));
+            });
+        }
+        match rx.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => TimedOutSnafu { from, to, timeout }.fail(),
+            Err(RecvTimeoutError::Disconnected) => WorkerDiedSnafu { from, to }.fail(),
+        }
+    }
+
+    fn failed(&mut self, e: RenameError) -> ControlFlow<RenameError> {
+        PROGRESS.advance();
+        self.summary.failed += 1;
+        let mut failure = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            failure += &format!(": {cause}");
+            source = cause.source();
+        }
+        // Aborting reports `e` itself, with its causes.
+        if let OnError::Continue = self.opts.on_error {
+            tracing::error!("{failure}");
+        }
+        self.failures.push(failure);
+
+        match self.opts.on_error {
+            OnError::Continue => ControlFlow::Continue(()),
+            OnError::Abort => ControlFlow::Break(e),
+        }
+    }
+
+    /// Picks a temporary name for the file at `idx` being renamed to `to`, of the form
+    /// `<to><tmp_suffix><idx>`.
+    ///
+    /// If that name is `taken` or already exists, `-1`, `-2`, etc. are appended until a free name
+    /// is found.
+    fn temp_name(
+        &self,
+        idx: usize,
+        to: &Path,
+        taken: &HashSet<PathBuf>,
+    ) -> Result<PathBuf, RenameError> {
+        let mut base = to.as_os_str().to_owned();
+        base.push(&self.opts.tmp_suffix);
+        base.push(idx.to_string());
+
+        for attempt in 0usize.. {
+            let candidate = PathBuf::from(match attempt {
+                0 => base.clone(),
+                n => {
+                    let mut candidate = base.clone();
+                    candidate.push(format!("-{n}"));
+                    candidate
+                }
+            });
+            if taken.contains(&candidate) {
+                continue;
+            }
+            let exists = self
+                .fs
+                .exists(&candidate)
+                .context(CheckTempNameSnafu { path: &candidate })?;
+            if !exists {
+                return Ok(candidate);
+            }
+        }
+        unreachable!("ran out of temporary names")
+    }
+
+    /// Renames `from` to `to` by way of a [temporary name](Self::temp_name), so that case-only
+    /// renames take effect on case-insensitive file systems.
+    fn rename_via_temp(&mut self, idx: usize, from: &Path, to: &Path) -> Result<(), RenameError> {
+        let taken = HashSet::from([from.to_owned(), to.to_owned()]);
+        let tmp = self.temp_name(idx, to, &taken)?;
+        tracing::debug!("moving {from:?} to temporary name {tmp:?} to change only its case");
+        self.rename(TransferMode::Rename, from, &tmp)?;
+        self.rename(TransferMode::Rename, &tmp, to)
+    }
+
+    /// Performs any renames deferred by [`ExecOptions::two_phase`].
+    ///
+    /// Every file is first moved (or copied, per [`ExecOptions::mode`]) to a temporary name that
+    /// neither exists nor is a source, target, or other temporary name in this batch, and only then
+    /// to its target. This makes permutations
+    /// and rotations of names (e.g., `a` to `b` and `b` to `a`) safe.
+    ///
+    /// Files that fail to reach their targets are [put back](Self::restore) where possible. When
+    /// stopping due to [`OnError::Abort`], files that already reached their targets are moved back
+    /// to their temporary names first, and then every file is put back.
+    pub fn finish(&mut self) -> Result<(), RenameError> {
+        let pending = std::mem::take(&mut self.pending);
+
+        let mut taken = pending
+            .iter()
+            .flat_map(|(_idx, from, to)| [from.clone(), to.clone()])
+            .collect::<HashSet<_>>();
+
+        let mut moved = Vec::with_capacity(pending.len());
+        for (idx, from, to) in pending {
+            let _span = tracing::debug_span!("renaming file", rename_idx = idx,).entered();
+
+            let res = self.temp_name(idx, &to, &taken).and_then(|tmp| {
+                tracing::debug!("moving {from:?} to temporary name {tmp:?}");
+                self.rename(self.opts.mode, &from, &tmp)?;
+                Ok(tmp)
+            });
+            match res {
+                Ok(tmp) => {
+                    taken.insert(tmp.clone());
+                    moved.push((idx, from, tmp, to));
+                }
+                Err(e) => {
+                    if let ControlFlow::Break(e) = self.failed(e) {
+                        for (_idx, from, tmp, _to) in moved {
+                            self.restore(&tmp, &from);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut done = Vec::new();
+        let mut moved = moved.into_iter();
+        while let Some((idx, from, tmp, to)) = moved.next() {
+            let _span = tracing::debug_span!("renaming file", rename_idx = idx,).entered();
+
+            match self.rename(TransferMode::Rename, &tmp, &to) {
+                Ok(()) => {
+                    self.renamed(&from, &to);
+                    done.push((from, tmp, to));
+                }
+                Err(e) => {
+                    if let ControlFlow::Break(e) = self.failed(e) {
+                        // In reverse, so that targets are vacated before files are put back at
+                        // sources that other files were renamed to.
+                        for (from, tmp, to) in done.iter().rev() {
+                            self.unrename(from, tmp, to);
+                        }
+                        self.restore(&tmp, &from);
+                        for (from, tmp, _to) in done.iter().rev() {
+                            self.restore(tmp, from);
+                        }
+                        for (_idx, from, tmp, _to) in moved {
+                            self.restore(&tmp, &from);
+                        }
+                        return Err(e);
+                    }
+                    self.restore(&tmp, &from);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Undoes the second phase of a [two-phase](Self::finish) rename of `from` to `to`, moving it
+    /// back to `tmp`, and no longer counting it as renamed. Failures are only logged, leaving the
+    /// file at `to`.
+    fn unrename(&mut self, from: &Path, tmp: &Path, to: &Path) {
+        if let Err(e) = self.fs.rename(to, tmp) {
+            tracing::warn!("failed to move {to:?} back to temporary name {tmp:?}: {e}");
+            return;
+        }
+        self.summary.renamed -= 1;
+        if let Some(pos) = self
+            .completed
+            .iter()
+            .rposition(|(done_from, done_to)| (&**done_from, &**done_to) == (from, to))
+        {
+            self.completed.remove(pos);
+        }
+    }
+
+    /// Undoes the first phase of a [two-phase](Self::finish) rename of `from`, now at `tmp`: a
+    /// moved file is moved back, unless another file has taken its place since, and a copy or link
+    /// is removed. Failures are only logged, since there is nothing more to do about them.
+    fn restore(&self, tmp: &Path, from: &Path) {
+        let res = match self.opts.mode {
+            TransferMode::Rename => match self.fs.exists(from) {
+                Ok(false) => self.fs.rename(tmp, from),
+                Ok(true) => {
+                    tracing::warn!(
+                        "leaving {from:?} at temporary name {tmp:?}, since another file was \
+                        renamed to {from:?}"
+                    );
+                    return;
+                }
+                Err(e) => Err(e),
+            },
+            TransferMode::Copy | TransferMode::HardLink | TransferMode::Symlink => {
+                self.fs.remove_file(tmp)
+            }
+        };
+        match res {
+            Ok(()) => tracing::debug!("restored {from:?} from temporary name {tmp:?}"),
+            Err(e) => tracing::warn!("failed to restore {from:?} from temporary name {tmp:?}: {e}"),
+        }
+    }
+}
+
+/// Puts `from` at `to` as `mode` dictates, returning the number of bytes copied, if any.
+fn transfer_file(
+    fs: &dyn Fs,
+    mode: TransferMode,
+    from: &Path,
+    to: &Path,
+    verify_copy: bool,
+) -> Result<u64, RenameError> {
+    match mode {
+        TransferMode::Rename => move_file(fs, from, to, verify_copy),
+        TransferMode::Copy => fs.copy(from, to).context(CopyFileSnafu { from, to }),
+        TransferMode::HardLink => fs
+            .hard_link(from, to)
+            .context(HardLinkSnafu { from, to })
+            .map(|()| 0),
+        TransferMode::Symlink => fs
+            .symlink(from, to)
+            .context(SymlinkSnafu { from, to })
+            .map(|()| 0),
+    }
+}
+
+/// Renames `from` to `to`, falling back to copying `to` and removing `from` if they are on
+/// different file systems. Returns the number of bytes copied, if any.
+///
+/// Copies keep the permissions and modification time of their source. If copying fails, the source
+/// is kept, and whatever was copied is removed (unless `to` already existed). With `verify_copy`,
+/// the contents of a copy are compared to the source before it is removed, and a mismatched copy is
+/// removed instead.
+fn move_file(fs: &dyn Fs, from: &Path, to: &Path, verify_copy: bool) -> Result<u64, RenameError> {
+    match fs.rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            tracing::debug!("{from:?} and {to:?} are on different devices; copying instead");
+        }
+        res => return res.context(IoSnafu { from, to }).map(|()| 0),
+    }
+
+    let existed = fs.exists(to).unwrap_or(true);
+    let bytes = match fs.copy(from, to) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            if !existed {
+                match fs.remove_file(to) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        tracing::warn!("failed to remove partial copy {to:?}: {e}");
+                    }
+                    _ => (),
+                }
+            }
+            return Err(e).context(CopySnafu { from, to });
+        }
+    };
+    if verify_copy {
+        let hash = |path| fs.content_hash(path).context(HashSnafu { path });
+        if hash(from)? != hash(to)? {
+            if let Err(e) = fs.remove_file(to) {
+                tracing::warn!("failed to remove mismatched copy {to:?}: {e}");
+            }
+            return CopyMismatchSnafu { from, to }.fail();
+        }
+    }
+    if let Err(e) = fs.copy_modified_time(from, to) {
+        tracing::warn!("failed to keep modification time of {from:?} for {to:?}: {e}");
+    }
+    fs.remove_file(from)
+        .context(RemoveSourceSnafu { from, to })?;
+    Ok(bytes)
+}
+
+/// Whether the default file systems of this platform ignore case when comparing paths, so that a
+/// case-only rename (e.g., `Foo.txt` to `foo.txt`) may be a no-op, or fail.
+const CASE_INSENSITIVE_FS: bool = cfg!(any(windows, target_os = "macos"));
+
+/// Whether `from` and `to` differ only in the case of their letters.
+fn is_case_only(from: &Path, to: &Path) -> bool {
+    from != to && from.to_string_lossy().to_lowercase() == to.to_string_lossy().to_lowercase()
+}
+
+/// Whether renaming `from` to `to` would leave the file system unchanged, i.e., whether they're
+/// the same path once their parent directories are canonicalized (so that `d/../a` and `a` are).
+///
+/// Paths that differ only in case never are, since renaming between them changes the case of the
+/// name.
+pub fn is_noop(from: &Path, to: &Path) -> bool {
+    if from == to {
+        return true;
+    }
+    let canonical = |path: &Path| {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+        let parent = fs::canonicalize(parent.unwrap_or(Path::new("."))).ok()?;
+        Some(parent.join(path.file_name()?))
+    };
+    !is_case_only(from, to)
+        && matches!((canonical(from), canonical(to)), (Some(from), Some(to)) if from == to)
+}
+
+impl Visitor for ZipVisitor {
+    type Error = RenameError;
+
+    fn visit(&mut self, idx: usize, from: &Path, to: PathBuf) -> ControlFlow<Self::Error> {
+        let ExecOptions {
+            dry_run,
+            mode,
+            two_phase,
+            ..
+        } = self.opts;
+
+        let _span = tracing::debug_span!("renaming file", rename_idx = idx,).entered();
+
+        if is_noop(from, &to) {
+            tracing::debug!("skipping {from:?}, which already has its target name");
+            return ControlFlow::Continue(());
+        }
+
+        match self.confirmed(from, &to) {
+            Ok(true) => (),
+            Ok(false) => {
+                tracing::debug!("skipping {from:?}, whose rename was declined");
+                PROGRESS.advance();
+                self.summary.skipped += 1;
+                return ControlFlow::Continue(());
+            }
+            Err(e) => return self.failed(e),
+        }
+
+        let tracing_level = if dry_run {
+            tracing::Level::INFO
+        } else {
+            tracing::Level::DEBUG
+        };
+        event!(tracing_level, "{} {from:?} to {to:?}", mode.verb());
+
+        if dry_run {
+            self.summary.renamed += 1;
+        } else if two_phase {
+            self.pending.push((idx, from.to_owned(), to));
+        } else {
+            let res =
+                if mode == TransferMode::Rename && CASE_INSENSITIVE_FS && is_case_only(from, &to) {
+                    self.rename_via_temp(idx, from, &to)
+                } else {
+                    self.rename(mode, from, &to)
+                };
+            match res {
+                Ok(()) => self.renamed(from, &to),
+                Err(e) => return self.failed(e),
+            }
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn render_failed(
+        &mut self,
+        idx: usize,
+        from: &Path,
+        error: RenderError,
+    ) -> ControlFlow<Self::Error> {
+        let _span = tracing::debug_span!("renaming file", rename_idx = idx,).entered();
+
+        self.failed(RenameError::Render {
+            from: from.to_owned(),
+            source: error,
+        })
+    }
+}
+
+/// Performs `plan` with up to `jobs` threads, each with its own [`ZipVisitor`], returning the
+/// combined [`ExecOutcome`] of every thread along with the first error that stopped one. The
+/// [elapsed time](RenameSummary::elapsed) is that of the whole run, rather than of each thread.
+///
+/// The plan is [partitioned by directory](PlanCollector::partition_by_dir): parts run
+/// concurrently, but the renames within a part (including [`ExecOptions::two_phase`] renames)
+/// run in order on a single thread. With [`OnError::Abort`], no new parts are started once one
+/// fails.
+pub fn execute_parallel(
+    plan: PlanCollector,
+    jobs: NonZeroUsize,
+    opts: &ExecOptions,
+    fs: Arc<dyn Fs>,
+) -> (ExecOutcome, Result<(), RenameError>) {
+    let start = Instant::now();
+    let parts = plan.partition_by_dir();
+    let workers = jobs.get().min(parts.len());
+    let parts = Mutex::new(parts.into_iter());
+    let aborted = AtomicBool::new(false);
+    let outcomes = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while !aborted.load(Ordering::SeqCst) {
+                    let Some(part) = parts.lock().unwrap().next() else {
+                        break;
+                    };
+                    let mut visitor = ZipVisitor::new(opts.clone(), Arc::clone(&fs));
+                    let res = part.replay(&mut visitor).and_then(|()| visitor.finish());
+                    if res.is_err() {
+                        aborted.store(true, Ordering::SeqCst);
+                    }
+                    outcomes.lock().unwrap().push((visitor.into_outcome(), res));
+                }
+            });
+        }
+    });
+
+    combine_parts(outcomes.into_inner().unwrap(), start.elapsed())
+}
+
+/// Combines the outcomes of the parts of an [`execute_parallel`] run that took `elapsed`, which
+/// replaces the [elapsed times](RenameSummary::elapsed) of the parts. Parts run on overlapping
+/// threads, so their own elapsed times would add up to more than was actually spent.
+fn combine_parts(
+    parts: Vec<(ExecOutcome, Result<(), RenameError>)>,
+    elapsed: Duration,
+) -> (ExecOutcome, Result<(), RenameError>) {
+    // Parts touch separate directories, so concatenating their completed renames keeps them
+    // undoable.
+    let mut outcome = ExecOutcome::default();
+    let mut res = Ok(());
+    for (part, part_res) in parts {
+        outcome.summary += part.summary;
+        outcome.failures.extend(part.failures);
+        outcome.completed.extend(part.completed);
+        res = res.and(part_res);
+    }
+    outcome.summary.elapsed = elapsed;
+    (outcome, res)
+}
+
+#[derive(Debug, Snafu)]
+pub enum RenameError {
+    #[snafu(display("failed to determine target path of {from:?}"))]
+    Render { from: PathBuf, source: RenderError },
+    #[snafu(display("failed to rename file {from:?} to {to:?}"))]
+    Io {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("failed to copy file {from:?} to {to:?}"))]
+    CopyFile {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("failed to create hard link {to:?} to {from:?}"))]
+    HardLink {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("failed to create symbolic link {to:?} to {from:?}"))]
+    Symlink {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("failed to copy file {from:?} to {to:?} on another device"))]
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("failed to hash {path:?} while verifying a copy"))]
+    Hash { path: PathBuf, source: io::Error },
+    #[snafu(display(
+        "copy {to:?} of {from:?} does not match its source; leaving the source alone"
+    ))]
+    CopyMismatch { from: PathBuf, to: PathBuf },
+    #[snafu(display("copied {from:?} to {to:?}, but failed to remove the source"))]
+    RemoveSource {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display(
+        "timed out after {} renaming file {from:?} to {to:?}",
+        humantime::format_duration(*timeout)
+    ))]
+    TimedOut {
+        from: PathBuf,
+        to: PathBuf,
+        timeout: Duration,
+    },
+    #[snafu(display("failed to create parent directory {parent:?} of {to:?}"))]
+    CreateParent {
+        parent: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[snafu(display("failed to check whether temporary name {path:?} is free"))]
+    CheckTempName { path: PathBuf, source: io::Error },
+    #[snafu(display("failed to ask whether to rename {from:?}"))]
+    Prompt { from: PathBuf, source: io::Error },
+    #[snafu(display("worker thread renaming file {from:?} to {to:?} exited unexpectedly"))]
+    WorkerDied { from: PathBuf, to: PathBuf },
+}
+
+/// An in-memory [`Fs`], which records the operations performed on it.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct FakeFs {
+    pub files: std::sync::Mutex<std::collections::BTreeSet<PathBuf>>,
+    /// The contents of files, where they matter. Files without an entry are empty.
+    pub contents: std::sync::Mutex<std::collections::BTreeMap<PathBuf, Vec<u8>>>,
+    /// Whether renames should fail as if across devices.
+    pub cross_device: bool,
+    /// Whether copies should get their contents wrong.
+    pub corrupt_copies: bool,
+    /// Whether copies should fail after creating their target.
+    pub interrupt_copies: bool,
+    pub renames: std::sync::Mutex<Vec<(PathBuf, PathBuf)>>,
+    /// Targets that renames should fail to move files to.
+    pub unwritable: std::collections::BTreeSet<PathBuf>,
+    pub created_dirs: std::sync::Mutex<Vec<PathBuf>>,
+    pub rename_delay: Option<Duration>,
+}
+
+#[cfg(test)]
+impl FakeFs {
+    pub fn with_files<'a>(files: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            files: std::sync::Mutex::new(files.into_iter().map(PathBuf::from).collect()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+impl Fs for FakeFs {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if let Some(delay) = self.rename_delay {
+            thread::sleep(delay);
+        }
+        if self.cross_device {
+            return Err(io::ErrorKind::CrossesDevices.into());
+        }
+        if self.unwritable.contains(to) {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut files = self.files.lock().unwrap();
+        files.remove(from);
+        files.insert(to.to_owned());
+        let mut contents = self.contents.lock().unwrap();
+        match contents.remove(from) {
+            Some(moved) => contents.insert(to.to_owned(), moved),
+            None => contents.remove(to),
+        };
+        self.renames
+            .lock()
+            .unwrap()
+            .push((from.to_owned(), to.to_owned()));
+        Ok(())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        if self.unwritable.contains(to) {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut contents = self.contents.lock().unwrap();
+        let mut copied = contents.get(from).cloned().unwrap_or_default();
+        let len = copied.len() as u64;
+        if self.corrupt_copies {
+            copied.push(0);
+        }
+        contents.insert(to.to_owned(), copied);
+        self.files.lock().unwrap().insert(to.to_owned());
+        if self.interrupt_copies {
+            return Err(io::ErrorKind::StorageFull.into());
+        }
+        Ok(len)
+    }
+
+    fn copy_modified_time(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.copy(original, link).map(|_len| ())
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.copy(original, link).map(|_len| ())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.contents.lock().unwrap().remove(path);
+        match self.files.lock().unwrap().remove(path) {
+            true => Ok(()),
+            false => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn content_hash(&self, path: &Path) -> io::Result<ContentHash> {
+        let contents = self.contents.lock().unwrap();
+        Ok(ContentHash::of(contents.get(path).map_or(&[][..], |c| c)))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.created_dirs.lock().unwrap().push(path.to_owned());
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(self.files.lock().unwrap().contains(path))
+    }
+}
+
+#[cfg(test)]
+fn fake_visitor(fs: Arc<FakeFs>, on_error: OnError, op_timeout: Option<Duration>) -> ZipVisitor {
+    ZipVisitor::new(
+        ExecOptions {
+            on_error,
+            op_timeout,
+            ..Default::default()
+        },
+        fs,
+    )
+}
+
+#[test]
+fn op_timeout_fails_slow_rename() {
+    let fs = Arc::new(FakeFs {
+        rename_delay: Some(Duration::from_millis(500)),
+        ..Default::default()
+    });
+    let mut visitor = fake_visitor(fs.clone(), OnError::Abort, Some(Duration::from_millis(10)));
+
+    match visitor.visit(0, "a".as_ref(), "b".into()) {
+        ControlFlow::Break(RenameError::TimedOut { from, to, .. }) => {
+            assert_eq!(from, Path::new("a"));
+            assert_eq!(to, Path::new("b"));
+        }
+        other => panic!("expected a timeout, got {other:?}"),
+    }
+}
+
+#[test]
+fn op_timeout_continues_past_slow_rename() {
+    let fs = Arc::new(FakeFs {
+        rename_delay: Some(Duration::from_millis(500)),
+        ..Default::default()
+    });
+    let mut visitor = fake_visitor(
+        fs.clone(),
+        OnError::Continue,
+        Some(Duration::from_millis(10)),
+    );
+
+    assert!(matches!(
+        visitor.visit(0, "a".as_ref(), "b".into()),
+        ControlFlow::Continue(())
+    ));
+    assert!(fs.renames.lock().unwrap().is_empty());
+}
+
+#[test]
+fn op_timeout_permits_fast_rename() {
+    let fs = Arc::new(FakeFs::default());
+    let mut visitor = fake_visitor(fs.clone(), OnError::Abort, Some(Duration::from_secs(10)));
+
+    assert!(matches!(
+        visitor.visit(0, "a".as_ref(), "b".into()),
+        ControlFlow::Continue(())
+    ));
+    assert_eq!(
+        *fs.renames.lock().unwrap(),
+        [(PathBuf::from("a"), PathBuf::from("b"))]
+    );
+}
+
+#[test]
+fn mkdirs_creates_only_nonempty_parents() {
+    let fs = Arc::new(FakeFs::default());
+    let mut visitor = ZipVisitor::new(
+        ExecOptions {
+            mkdirs: true,
+            ..Default::default()
+        },
+        fs.clone(),
+    );
+
+    for (idx, to) in ["out/nested/b", "c"].into_iter().enumerate() {
+        assert!(matches!(
+            visitor.visit(idx, "a".as_ref(), to.into()),
+            ControlFlow::Continue(())
+        ));
+    }
+    assert_eq!(
+        *fs.created_dirs.lock().unwrap(),
+        [PathBuf::from("out/nested")]
+    );
+}
+
+#[test]
+fn two_phase_temp_names_avoid_existing_files() {
+    let fs = Arc::new(FakeFs::with_files(["a", "b", "b.rename-seq-tmp-0"]));
+    let mut visitor = ZipVisitor::new(
+        ExecOptions {
+            on_error: OnError::Abort,
+            two_phase: true,
+            ..Default::default()
+        },
+        fs.clone(),
+    );
+
+    for (idx, (from, to)) in [("a", "b"), ("b", "a")].into_iter().enumerate() {
+        assert!(matches!(
+            visitor.visit(idx, from.as_ref(), to.into()),
+            ControlFlow::Continue(())
+        ));
+    }
+    assert!(fs.renames.lock().unwrap().is_empty());
+    visitor.finish().unwrap();
+
+    assert_eq!(
+        *fs.renames.lock().unwrap(),
+        [
+            ("a", "b.rename-seq-tmp-0-1"),
+            ("b", "a.rename-seq-tmp-1"),
+            ("b.rename-seq-tmp-0-1", "b"),
+            ("a.rename-seq-tmp-1", "a"),
+        ]
+        .map(|(from, to)| (PathBuf::from(from), PathBuf::from(to)))
+    );
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        ["a", "b", "b.rename-seq-tmp-0"]
+            .map(PathBuf::from)
+            .into_iter()
+            .collect()
+    );
+}
+
+#[test]
+fn prompt_decides_which_files_are_renamed() {
+    let fs = Arc::new(FakeFs::with_files(["a", "b", "c", "d"]));
+    let prompt = LinePrompt {
+        input: "y\nwhat\nn\nq\n".as_bytes(),
+        output: Vec::new(),
+    };
+    let mut visitor = fake_visitor(fs.clone(), OnError::Abort, None).with_prompt(Box::new(prompt));
+
+    for (idx, from) in ["a", "b", "c", "d"].into_iter().enumerate() {
+        assert!(matches!(
+            visitor.visit(idx, from.as_ref(), format!("{from}2").into()),
+            ControlFlow::Continue(())
+        ));
+    }
+    visitor.finish().unwrap();
+
+    assert_eq!(
+        *fs.renames.lock().unwrap(),
+        [(PathBuf::from("a"), PathBuf::from("a2"))]
+    );
+    let summary = visitor.into_outcome().summary;
+    assert_eq!((summary.renamed, summary.skipped), (1, 3));
+    assert_eq!(summary.bytes_transferred, 0);
+}
+
+#[test]
+fn confirmation_requires_yes() {
+    let confirm = |input: &str| {
+        let mut output = Vec::new();
+        let confirmed = confirm_proceed(input.as_bytes(), &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("Proceed? [y/N] "));
+        confirmed
+    };
+    assert!(confirm("y\n"));
+    assert!(confirm(" YES \n"));
+    assert!(!confirm("n\n"));
+    assert!(!confirm("\n"));
+    assert!(!confirm("yep\n"));
+    assert!(!confirm(""));
+}
+
+#[test]
+fn detects_case_only_renames() {
+    assert!(is_case_only("Foo.txt".as_ref(), "foo.txt".as_ref()));
+    assert!(is_case_only("dir/Foo.TXT".as_ref(), "dir/foo.txt".as_ref()));
+    assert!(!is_case_only("foo.txt".as_ref(), "foo.txt".as_ref()));
+    assert!(!is_case_only("Foo.txt".as_ref(), "bar.txt".as_ref()));
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+#[test]
+fn case_only_rename_takes_effect() {
+    let dir = tempfile::tempdir().unwrap();
+    let (from, to) = (dir.path().join("Foo.txt"), dir.path().join("foo.txt"));
+    fs::write(&from, "").unwrap();
+
+    let mut visitor = ZipVisitor::new(
+        ExecOptions {
+            on_error: OnError::Abort,
+            ..Default::default()
+        },
+        Arc::new(RealFs),
+    );
+    assert!(matches!(
+        visitor.visit(0, &from, to),
+        ControlFlow::Continue(())
+    ));
+
+    let names = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["foo.txt"]);
+}
+
+#[test]
+fn verify_copy_preserves_source_of_bad_copy() {
+    let fs = Arc::new(FakeFs {
+        cross_device: true,
+        ..FakeFs::with_files(["a"])
+    });
+    fs.contents
+        .lock()
+        .unwrap()
+        .insert("a".into(), b"hello".to_vec());
+    let opts = ExecOptions {
+        on_error: OnError::Abort,
+        verify_copy: true,
+        ..Default::default()
+    };
+
+    let mut visitor = ZipVisitor::new(opts.clone(), fs.clone());
+    assert!(matches!(
+        visitor.visit(0, "a".as_ref(), "b".into()),
+        ControlFlow::Continue(())
+    ));
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        [PathBuf::from("b")].into_iter().collect()
+    );
+    assert_eq!(visitor.into_outcome().summary.bytes_transferred, 5);
+
+    let fs = Arc::new(FakeFs {
+        cross_device: true,
+        corrupt_copies: true,
+        ..FakeFs::with_files(["a"])
+    });
+    let mut visitor = ZipVisitor::new(opts, fs.clone());
+    assert!(matches!(
+        visitor.visit(0, "a".as_ref(), "b".into()),
+        ControlFlow::Break(RenameError::CopyMismatch { .. })
+    ));
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        [PathBuf::from("a")].into_iter().collect()
+    );
+}
+
+#[test]
+fn failed_cross_device_copy_preserves_source() {
+    let fs = Arc::new(FakeFs {
+        cross_device: true,
+        interrupt_copies: true,
+        ..FakeFs::with_files(["a", "existing"])
+    });
+    let opts = ExecOptions {
+        on_error: OnError::Abort,
+        ..Default::default()
+    };
+    for to in ["b", "existing"] {
+        let mut visitor = ZipVisitor::new(opts.clone(), fs.clone());
+        assert!(matches!(
+            visitor.visit(0, "a".as_ref(), to.into()),
+            ControlFlow::Break(RenameError::Copy { .. })
+        ));
+    }
+    // Only the partial copy that didn't replace an existing file is removed.
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        ["a", "existing"].map(PathBuf::from).into_iter().collect()
+    );
+}
+
+#[test]
+fn copies_keep_modification_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let (from, to) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&from, "a").unwrap();
+    let modified = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&from)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+
+    RealFs.copy(&from, &to).unwrap();
+    RealFs.copy_modified_time(&from, &to).unwrap();
+    assert_eq!(fs::metadata(&to).unwrap().modified().unwrap(), modified);
+}
+
+#[test]
+fn two_phase_restores_sources_on_abort() {
+    let names = ["a", "b"];
+    let fs = Arc::new(FakeFs {
+        unwritable: ["x".into()].into(),
+        ..FakeFs::with_files(names)
+    });
+    fs.contents
+        .lock()
+        .unwrap()
+        .extend(names.map(|name| (PathBuf::from(name), name.as_bytes().to_vec())));
+    let mut visitor = ZipVisitor::new(
+        ExecOptions {
+            on_error: OnError::Abort,
+            two_phase: true,
+            ..Default::default()
+        },
+        fs.clone(),
+    );
+
+    // The first rename fails once every file is at its temporary name.
+    for (idx, (from, to)) in [("a", "x"), ("b", "a")].into_iter().enumerate() {
+        assert!(matches!(
+            visitor.visit(idx, from.as_ref(), to.into()),
+            ControlFlow::Continue(())
+        ));
+    }
+    assert!(matches!(visitor.finish(), Err(RenameError::Io { .. })));
+
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        names.map(PathBuf::from).into_iter().collect()
+    );
+    let contents = fs.contents.lock().unwrap();
+    for name in names {
+        assert_eq!(contents[Path::new(name)], name.as_bytes());
+    }
+}
+
+#[test]
+fn two_phase_undoes_completed_renames_on_abort() {
+    let names = ["a", "b", "c"];
+    let fs = Arc::new(FakeFs {
+        unwritable: ["x".into()].into(),
+        ..FakeFs::with_files(names)
+    });
+    fs.contents
+        .lock()
+        .unwrap()
+        .extend(names.map(|name| (PathBuf::from(name), name.as_bytes().to_vec())));
+    let mut visitor = ZipVisitor::new(
+        ExecOptions {
+            on_error: OnError::Abort,
+            two_phase: true,
+            ..Default::default()
+        },
+        fs.clone(),
+    );
+
+    // The last rename fails after the others have taken the sources of `b` and `c`.
+    for (idx, (from, to)) in [("a", "b"), ("b", "c"), ("c", "x")].into_iter().enumerate() {
+        assert!(matches!(
+            visitor.visit(idx, from.as_ref(), to.into()),
+            ControlFlow::Continue(())
+        ));
+    }
+    assert!(matches!(visitor.finish(), Err(RenameError::Io { .. })));
+
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        names.map(PathBuf::from).into_iter().collect()
+    );
+    let contents = fs.contents.lock().unwrap();
+    for name in names {
+        assert_eq!(contents[Path::new(name)], name.as_bytes());
+    }
+    let outcome = visitor.into_outcome();
+    assert_eq!(outcome.summary.renamed, 0);
+    assert!(outcome.completed.is_empty());
+}
+
+#[test]
+fn copy_mode_leaves_sources() {
+    let fs = Arc::new(FakeFs::with_files(["a"]));
+    fs.contents
+        .lock()
+        .unwrap()
+        .insert("a".into(), b"hello".to_vec());
+    let mut visitor = ZipVisitor::new(
+        ExecOptions {
+            mode: TransferMode::Copy,
+            on_error: OnError::Abort,
+            ..Default::default()
+        },
+        fs.clone(),
+    );
+
+    assert!(matches!(
+        visitor.visit(0, "a".as_ref(), "b".into()),
+        ControlFlow::Continue(())
+    ));
+    assert!(fs.renames.lock().unwrap().is_empty());
+    assert_eq!(
+        *fs.files.lock().unwrap(),
+        ["a", "b"].map(PathBuf::from).into_iter().collect()
+    );
+    assert_eq!(fs.contents.lock().unwrap()[Path::new("b")], b"hello");
+    let summary = visitor.into_outcome().summary;
+    assert_eq!((summary.renamed, summary.bytes_transferred), (1, 5));
+}
+
+#[test]
+fn parallel_execution_keeps_cycles_within_a_directory() {
+    let names = ["a/1", "a/2", "a/3", "b/x"];
+    let fs = Arc::new(FakeFs::with_files(names));
+    fs.contents
+        .lock()
+        .unwrap()
+        .extend(names.map(|name| (PathBuf::from(name), name.as_bytes().to_vec())));
+
+    let mut plan = PlanCollector::default();
+    // A rotation in `a`, and an independent rename in `b`.
+    for (from, to) in [
+        ("a/1", "a/2"),
+        ("a/2", "a/3"),
+        ("a/3", "a/1"),
+        ("b/x", "b/y"),
+    ] {
+        plan.plan.push((from.into(), Ok(to.into())));
+    }
+    let opts = ExecOptions {
+        on_error: OnError::Abort,
+        two_phase: true,
+        ..Default::default()
+    };
+    let (outcome, res) = execute_parallel(plan, NonZeroUsize::new(4).unwrap(), &opts, fs.clone());
+    res.unwrap();
+    assert!(outcome.failures.is_empty());
+    assert_eq!(outcome.summary.renamed, 4);
+    assert_eq!(outcome.completed.len(), 4);
+
+    let contents = fs.contents.lock().unwrap();
+    for (path, original) in [
+        ("a/2", "a/1"),
+        ("a/3", "a/2"),
+        ("a/1", "a/3"),
+        ("b/y", "b/x"),
+    ] {
+        assert_eq!(contents[Path::new(path)], original.as_bytes(), "{path}");
+    }
+    assert!(!contents.contains_key(Path::new("b/x")));
+}
+
+#[test]
+fn parallel_elapsed_is_wall_clock_time() {
+    let part = |renamed| ExecOutcome {
+        summary: RenameSummary {
+            renamed,
+            elapsed: Duration::from_secs(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (outcome, res) = combine_parts(
+        vec![(part(1), Ok(())), (part(2), Ok(()))],
+        Duration::from_secs(4),
+    );
+    res.unwrap();
+    assert_eq!(outcome.summary.renamed, 3);
+    // Rather than the 6s that the parts spent in total.
+    assert_eq!(outcome.summary.elapsed, Duration::from_secs(4));
+}
+
+#[test]
+fn parallel_copies_report_each_failure() {
+    let names = (0..8)
+        .flat_map(|dir| (0..16).map(move |file| format!("{dir}/{file}")))
+        .collect::<Vec<_>>();
+    let mut fs = FakeFs::with_files(names.iter().map(|name| name.as_str()));
+    fs.unwritable.insert(PathBuf::from("3/copy-7"));
+    let fs = Arc::new(fs);
+
+    let mut plan = PlanCollector::default();
+    for name in &names {
+        let (dir, file) = name.split_once('/').unwrap();
+        plan.plan
+            .push((name.into(), Ok(format!("{dir}/copy-{file}").into())));
+    }
+    let opts = ExecOptions {
+        mode: TransferMode::Copy,
+        ..Default::default()
+    };
+    let (outcome, res) = execute_parallel(plan, NonZeroUsize::new(4).unwrap(), &opts, fs.clone());
+    res.unwrap();
+    assert_eq!(outcome.summary.renamed, 127);
+    assert_eq!(outcome.summary.failed, 1);
+    assert_eq!(outcome.failures.len(), 1);
+    assert!(
+        outcome.failures[0].contains("\"3/7\""),
+        "{}",
+        outcome.failures[0]
+    );
+
+    let files = fs.files.lock().unwrap();
+    for name in &names {
+        let (dir, file) = name.split_once('/').unwrap();
+        assert!(files.contains(Path::new(name)), "{name}");
+        let copied = files.contains(Path::new(&format!("{dir}/copy-{file}")));
+        assert_eq!(copied, name != "3/7", "{name}");
+    }
+}
@@ -0,0 +1,60 @@
+use color_eyre::eyre::{self, WrapErr};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A digest of a file's contents, suitable for comparing files for equality.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ContentHash([u8; 32]);
+
+#[cfg(test)]
+impl ContentHash {
+    pub fn of(bytes: &[u8]) -> Self {
+        Self(Sha256::digest(bytes).into())
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<ContentHash> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(ContentHash(hasher.finalize().into()))
+}
+
+/// Groups `files` with byte-identical contents, omitting files that are unique.
+///
+/// Groups are ordered by the position of their first member in `files`, and members retain their
+/// relative order from `files`.
+pub fn duplicate_groups<'a>(
+    files: impl IntoIterator<Item = &'a Path>,
+) -> eyre::Result<Vec<Vec<PathBuf>>> {
+    let mut group_idxs_by_hash = HashMap::new();
+    let mut groups = Vec::<Vec<PathBuf>>::new();
+    for path in files {
+        let hash = hash_file(path).wrap_err_with(|| format!("failed to hash {path:?}"))?;
+        let group_idx = *group_idxs_by_hash.entry(hash).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group_idx].push(path.to_owned());
+    }
+    groups.retain(|group| group.len() > 1);
+    Ok(groups)
+}
+
+#[test]
+fn groups_identical_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let [a, b, c] = ["a", "b", "c"].map(|name| dir.path().join(name));
+    std::fs::write(&a, "same").unwrap();
+    std::fs::write(&b, "unique").unwrap();
+    std::fs::write(&c, "same").unwrap();
+
+    assert_eq!(
+        duplicate_groups([&a, &b, &c].map(|p| p.as_path())).unwrap(),
+        [[a, c]]
+    );
+}
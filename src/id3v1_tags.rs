@@ -0,0 +1,132 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+};
+
+/// A field of the ID3v1 tag of an MP3 file, as read by [`read_tag`] for `{tag:NAME}`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Id3v1Tag {
+    Title,
+    Artist,
+    Album,
+    Year,
+    Comment,
+    Track,
+}
+
+impl Id3v1Tag {
+    /// Every tag, by the `NAME` written in `{tag:NAME}`.
+    pub const BY_NAME: &'static [(&'static str, Self)] = &[
+        ("title", Self::Title),
+        ("artist", Self::Artist),
+        ("album", Self::Album),
+        ("year", Self::Year),
+        ("comment", Self::Comment),
+        ("track", Self::Track),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        Self::BY_NAME
+            .iter()
+            .find(|(_name, tag)| tag == self)
+            .expect("tag is missing from `BY_NAME`")
+            .0
+    }
+}
+
+impl FromStr for Id3v1Tag {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::BY_NAME
+            .iter()
+            .find(|(name, _tag)| *name == s)
+            .map(|(_name, tag)| *tag)
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for Id3v1Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Reads `tag` from the ID3v1 tag at the end of the file at `path`, if it has one and `tag` is set.
+///
+/// Fields are decoded as Latin-1, with trailing padding removed. `Track` is only present in ID3v1.1
+/// tags.
+pub fn read_tag(path: &Path, tag: Id3v1Tag) -> io::Result<Option<String>> {
+    const TAG_LEN: u64 = 128;
+
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < TAG_LEN {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-(TAG_LEN as i64)))?;
+    let mut id3 = [0; TAG_LEN as usize];
+    file.read_exact(&mut id3)?;
+    if &id3[..3] != b"TAG" {
+        return Ok(None);
+    }
+
+    let text = |field: &[u8]| {
+        let text = field
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| char::from(b))
+            .collect::<String>();
+        let text = text.trim_end();
+        (!text.is_empty()).then(|| text.to_owned())
+    };
+    Ok(match tag {
+        Id3v1Tag::Title => text(&id3[3..33]),
+        Id3v1Tag::Artist => text(&id3[33..63]),
+        Id3v1Tag::Album => text(&id3[63..93]),
+        Id3v1Tag::Year => text(&id3[93..97]),
+        // In ID3v1.1, a zero byte before the last byte of the comment marks that byte as the track.
+        Id3v1Tag::Comment if id3[125] == 0 => text(&id3[97..125]),
+        Id3v1Tag::Comment => text(&id3[97..127]),
+        Id3v1Tag::Track => (id3[125] == 0 && id3[126] != 0).then(|| id3[126].to_string()),
+    })
+}
+
+/// Builds an ID3v1.1 tag with the given fields, for tests.
+#[cfg(test)]
+pub(crate) fn id3v1(title: &str, artist: &str, track: u8) -> Vec<u8> {
+    let mut id3 = vec![0; 128];
+    id3[..3].copy_from_slice(b"TAG");
+    id3[3..3 + title.len()].copy_from_slice(title.as_bytes());
+    id3[33..33 + artist.len()].copy_from_slice(artist.as_bytes());
+    id3[126] = track;
+    id3
+}
+
+#[test]
+fn reads_id3v1() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("song.mp3");
+    let mut contents = b"not really audio".to_vec();
+    contents.extend(id3v1("Title", "Artist", 7));
+    std::fs::write(&path, contents).unwrap();
+
+    assert_eq!(
+        read_tag(&path, Id3v1Tag::Title).unwrap().as_deref(),
+        Some("Title")
+    );
+    assert_eq!(
+        read_tag(&path, Id3v1Tag::Artist).unwrap().as_deref(),
+        Some("Artist")
+    );
+    assert_eq!(
+        read_tag(&path, Id3v1Tag::Track).unwrap().as_deref(),
+        Some("7")
+    );
+    assert_eq!(read_tag(&path, Id3v1Tag::Album).unwrap(), None);
+
+    std::fs::write(&path, [0; 200]).unwrap();
+    assert_eq!(read_tag(&path, Id3v1Tag::Title).unwrap(), None);
+}
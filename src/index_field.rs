@@ -0,0 +1,129 @@
+use std::ops::Range;
+
+/// A run of ASCII digits that varies between file names, like `1` and `10` in `a1b.txt` and
+/// `a10b.txt`, as found by [`IndexField::find`].
+///
+/// The [default](Default) is the first run of digits in a name.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IndexField {
+    /// Which run of digits in a name this field is, counting from zero.
+    position: usize,
+}
+
+impl IndexField {
+    /// Finds the run of digits that differs between `names`.
+    ///
+    /// Runs are compared by their position in each name. If several differ, the one with the
+    /// longest value is chosen, preferring later runs in a tie. Returns `None` if no run that all
+    /// names have differs between them.
+    pub fn find<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let names = names.into_iter().collect::<Vec<_>>();
+        let runs = names
+            .iter()
+            .map(|name| digit_runs(name).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let common_runs = runs.iter().map(|runs| runs.len()).min()?;
+
+        (0..common_runs)
+            .filter(|&position| {
+                let mut values = names
+                    .iter()
+                    .zip(&runs)
+                    .map(|(name, runs)| &name[runs[position].clone()]);
+                let first = values.next();
+                values.any(|value| Some(value) != first)
+            })
+            .max_by_key(|&position| runs.iter().map(|runs| runs[position].len()).max())
+            .map(|position| Self { position })
+    }
+
+    /// The byte range of this field in `name`, if `name` has it.
+    pub fn locate(&self, name: &str) -> Option<Range<usize>> {
+        digit_runs(name).nth(self.position)
+    }
+
+    /// The part of `name` after this field, without any separators (`_`, `-`, `.`, or spaces)
+    /// leading it, like `final` in `shot_001_final`. Empty if `name` doesn't have this field.
+    pub fn tail<'a>(&self, name: &'a str) -> &'a str {
+        match self.locate(name) {
+            Some(range) => name[range.end..].trim_start_matches(['_', '-', '.', ' ']),
+            None => "",
+        }
+    }
+
+    /// Rewrites this field in `name` with its value left-padded with zeroes to `width` digits.
+    ///
+    /// Excess leading zeroes are removed, but digits are never truncated: `007` repadded to a
+    /// `width` of 2 is `07`, but `123` stays as-is.
+    pub fn repad(&self, name: &str, width: usize) -> Option<String> {
+        let range = self.locate(name)?;
+        let value = &name[range.clone()];
+        let significant = value.trim_start_matches('0');
+        let significant = if significant.is_empty() {
+            "0"
+        } else {
+            significant
+        };
+        Some(format!(
+            "{}{significant:0>width$}{}",
+            &name[..range.start],
+            &name[range.end..]
+        ))
+    }
+}
+
+/// Iterates over the byte ranges of maximal runs of ASCII digits in `name`.
+fn digit_runs(name: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    let bytes = name.as_bytes();
+    let mut idx = 0;
+    std::iter::from_fn(move || {
+        let start = idx + bytes[idx..].iter().position(u8::is_ascii_digit)?;
+        let len = bytes[start..]
+            .iter()
+            .position(|b| !b.is_ascii_digit())
+            .unwrap_or(bytes.len() - start);
+        idx = start + len;
+        Some(start..idx)
+    })
+}
+
+#[test]
+fn finds_varying_field() {
+    let names = ["a1b.txt", "a10b.txt"];
+    let field = IndexField::find(names).unwrap();
+    assert_eq!(
+        names.map(|name| field.repad(name, 2).unwrap()),
+        ["a01b.txt", "a10b.txt"]
+    );
+
+    let names = [
+        "IMG_2023_1_v2.jpg",
+        "IMG_2023_007_v2.jpg",
+        "IMG_2023_12_v2.jpg",
+    ];
+    let field = IndexField::find(names).unwrap();
+    assert_eq!(field.locate(names[1]), Some(9..12));
+    assert_eq!(
+        names.map(|name| field.repad(name, 2).unwrap()),
+        [
+            "IMG_2023_01_v2.jpg",
+            "IMG_2023_07_v2.jpg",
+            "IMG_2023_12_v2.jpg"
+        ]
+    );
+    assert_eq!(
+        field.repad("x_1_2_345_6", 1).as_deref(),
+        Some("x_1_2_345_6")
+    );
+
+    assert_eq!(IndexField::find(["a1", "a1"]), None);
+    assert_eq!(IndexField::find(["a", "b1"]), None);
+}
+
+#[test]
+fn tails() {
+    let field = IndexField::default();
+    assert_eq!(field.tail("shot_001_final"), "final");
+    assert_eq!(field.tail("shot_002"), "");
+    assert_eq!(field.tail("shot"), "");
+}
@@ -1,73 +1,1260 @@
-use arrayvec::ArrayVec;
-use snafu::Snafu;
+#[cfg(feature = "id3v1-tags")]
+pub use self::id3v1_tags::{read_tag, Id3v1Tag};
+pub use self::{
+    index_field::IndexField,
+    order::{Order, ParseOrderError, ZigZag},
+    transform::{BuiltinTransform, ParseTransformError, Transform, TransformContext},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use regex::Regex;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use std::{
-    fmt,
-    ops::ControlFlow,
+    borrow::Cow,
+    collections::HashMap,
+    fmt::{self, Write as _},
+    fs, io, mem,
+    num::NonZeroUsize,
+    ops::{ControlFlow, Range},
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+#[cfg(feature = "id3v1-tags")]
+mod id3v1_tags;
+mod index_field;
+mod order;
+mod transform;
+
 /// A limited specification of replacement.
 ///
-/// Currently, only a single replacement group (`{}`)
+/// Literal text may be interleaved with any number of replacement groups (`{…}`). Literal curly
+/// braces are written as `{{` and `}}`.
 #[derive(Clone, Debug)]
 pub struct RenameSpec<'a> {
-    delimited: ArrayVec<(&'a str, DynamicRenameContent), 1>,
-    suffix: &'a str,
+    delimited: Vec<(Cow<'a, str>, DynamicRenameContent, Option<Case>)>,
+    suffix: Cow<'a, str>,
+    radix: u32,
+    uppercase_digits: bool,
+    keep_extension: bool,
+    seed: Option<u64>,
+    min_padding: usize,
+    pad_width: Option<usize>,
+    index_field: IndexField,
+    start_at: usize,
+    step: NonZeroUsize,
+    global_start: usize,
+    global_padding: usize,
+    max_path_len: Option<usize>,
+    capture_pattern: Option<Regex>,
+    #[cfg(feature = "id3v1-tags")]
+    missing_tag: Option<String>,
 }
 
 impl<'a> RenameSpec<'a> {
     pub fn new(s: &'a str) -> Result<Self, RenameSpecParseError> {
-        let mut delimited = ArrayVec::new();
-        let mut remaining = s;
+        Self::new_restricted(s, &[])
+    }
 
-        if let Some(idx) = s.find('{') {
-            let before = &s[..idx];
+    /// Like [`Self::new`], but rejects any replacement group whose name (e.g., `rand` for
+    /// `{rand:N}`) is in `disabled`.
+    pub fn new_restricted(s: &'a str, disabled: &[&str]) -> Result<Self, RenameSpecParseError> {
+        Self::parse(s, disabled, &[])
+    }
 
-            let after_brace_idx = idx + '{'.len_utf8();
-            remaining = &s[after_brace_idx..];
+    /// Like [`Self::new`], but also recognizes replacement groups named after each of `custom`.
+    /// Built-in replacement groups take precedence over custom ones with the same name.
+    ///
+    /// ```
+    /// use rename_seq::{CustomContent, RenameSpec};
+    /// use std::path::Path;
+    ///
+    /// let parent = CustomContent::new("parent", |ctx| {
+    ///     let parent = ctx.from().parent().and_then(|p| p.file_name()).unwrap_or_default();
+    ///     parent.to_string_lossy().into_owned()
+    /// });
+    /// let spec = RenameSpec::new_with_custom("{parent}-{padded_idx}.jpg", &[parent]).unwrap();
+    /// let to = spec.render(3, 10, Path::new("album/IMG_1234.jpg")).unwrap();
+    /// assert_eq!(to, "album-3.jpg");
+    /// ```
+    pub fn new_with_custom(
+        s: &'a str,
+        custom: &[CustomContent],
+    ) -> Result<Self, RenameSpecParseError> {
+        Self::parse(s, &[], custom)
+    }
 
-            if let Some(after) = remaining.strip_prefix("padded_idx}") {
-                delimited.push((before, DynamicRenameContent::PaddedInteger));
-                remaining = after;
+    fn parse(
+        s: &'a str,
+        disabled: &[&str],
+        custom: &[CustomContent],
+    ) -> Result<Self, RenameSpecParseError> {
+        /// Appends `s` to `literal`, borrowing instead of allocating while possible.
+        fn append<'a>(literal: &mut Cow<'a, str>, s: &'a str) {
+            if literal.is_empty() {
+                *literal = Cow::Borrowed(s);
             } else {
+                literal.to_mut().push_str(s);
+            }
+        }
+
+        let mut delimited = Vec::new();
+        let mut literal = Cow::Borrowed("");
+        let mut remaining = s;
+
+        while let Some(idx) = remaining.find(['{', '}']) {
+            let (before, after) = remaining.split_at(idx);
+            append(&mut literal, before);
+
+            if let Some(after) = after.strip_prefix("{{") {
+                append(&mut literal, "{");
+                remaining = after;
+                continue;
+            }
+            if let Some(after) = after.strip_prefix("}}") {
+                append(&mut literal, "}");
+                remaining = after;
+                continue;
+            }
+            if after.starts_with('}') {
+                return Err(RenameSpecParseError {
+                    idx: s.len() - after.len(),
+                    source: RenameSpecParseErrorKind::UnmatchedClosingCurlyBrace,
+                });
+            }
+
+            let after_brace_idx = '{'.len_utf8();
+            let group_start_idx = s.len() - after.len() + after_brace_idx;
+            remaining = &after[after_brace_idx..];
+
+            let Some(close_idx) = remaining.find('}') else {
+                return Err(RenameSpecParseError {
+                    idx: group_start_idx,
+                    source: RenameSpecParseErrorKind::UnclosedCurlyBrace,
+                });
+            };
+            let name = &remaining[..close_idx];
+            let lookup = |name: &str| match DynamicRenameContent::from_name(name) {
+                Err(RenameSpecParseErrorKind::UnrecognizedReplacementGroup { .. })
+                    if custom.iter().any(|c| c.name == name) =>
+                {
+                    let custom = custom.iter().find(|c| c.name == name).unwrap();
+                    Ok(DynamicRenameContent::Custom(custom.clone()))
+                }
+                dyn_content => dyn_content,
+            };
+            // The whole group is tried first, so that `{tag:title}` isn't taken as `{tag}`
+            // in title case.
+            let (dyn_content, case) = match (lookup(name), name.rsplit_once(':')) {
+                (Err(e), Some((base, case))) => match case.parse() {
+                    Ok(case) => (lookup(base), Some(case)),
+                    Err(()) => (Err(e), None),
+                },
+                (dyn_content, _) => (dyn_content, None),
+            };
+            let dyn_content = dyn_content.map_err(|source| RenameSpecParseError {
+                idx: group_start_idx,
+                source,
+            })?;
+            if disabled.contains(&dyn_content.name()) {
                 return Err(RenameSpecParseError {
-                    idx: after_brace_idx,
-                    source: RenameSpecParseErrorKind::UnexpectedAfterOpenCurlyBrace,
+                    idx: group_start_idx,
+                    source: RenameSpecParseErrorKind::DisabledReplacementGroup {
+                        name: dyn_content.name().to_owned(),
+                    },
                 });
             }
+            if let Some(case) = case {
+                if !dyn_content.is_text() {
+                    return Err(RenameSpecParseError {
+                        idx: group_start_idx,
+                        source: RenameSpecParseErrorKind::CaseOfNonText {
+                            name: dyn_content.name().to_owned(),
+                            case,
+                        },
+                    });
+                }
+            }
+            delimited.push((mem::take(&mut literal), dyn_content, case));
+            remaining = &remaining[close_idx + '}'.len_utf8()..];
+        }
+
+        append(&mut literal, remaining);
+        let suffix = literal;
+
+        Ok(Self {
+            delimited,
+            suffix,
+            radix: 10,
+            uppercase_digits: false,
+            keep_extension: false,
+            seed: None,
+            min_padding: 0,
+            pad_width: None,
+            index_field: IndexField::default(),
+            start_at: 0,
+            step: NonZeroUsize::MIN,
+            global_start: 0,
+            global_padding: 0,
+            max_path_len: None,
+            capture_pattern: None,
+            #[cfg(feature = "id3v1-tags")]
+            missing_tag: None,
+        })
+    }
+
+    /// Pads indices to at least `width` digits, even if fewer would suffice for the largest index.
+    ///
+    /// Defaults to `0`.
+    pub fn with_min_padding(self, width: usize) -> Self {
+        Self {
+            min_padding: width,
+            ..self
+        }
+    }
+
+    /// Uses `field` of source file names' stems to find where `{tail}` starts, as for fields
+    /// found by [`IndexField::find`] among the files being renamed.
+    ///
+    /// Defaults to the first run of digits.
+    pub fn with_index_field(self, field: IndexField) -> Self {
+        Self {
+            index_field: field,
+            ..self
+        }
+    }
+
+    /// Pads indices to exactly `width` digits, rather than to the width of the largest index.
+    /// Indices that need more digits are written in full, overflowing the padding.
+    ///
+    /// Defaults to `None`.
+    pub fn with_pad_width(self, width: Option<usize>) -> Self {
+        Self {
+            pad_width: width,
+            ..self
+        }
+    }
+
+    /// Numbers the first file `start`, the second `start + 1`, and so on, padding indices to the
+    /// width of the largest one actually written. See also [`Self::with_step`].
+    ///
+    /// Defaults to `0`.
+    pub fn with_start_at(self, start: usize) -> Self {
+        Self {
+            start_at: start,
+            ..self
+        }
+    }
+
+    /// Advances indices by `step` from one file to the next, so that the `n`th file (counting from
+    /// zero) is numbered `start + n * step`, where `start` is set by [`Self::with_start_at`].
+    ///
+    /// Defaults to `1`.
+    pub fn with_step(self, step: NonZeroUsize) -> Self {
+        Self { step, ..self }
+    }
+
+    /// Renders `{global_idx}` as `start` plus the position of each file among all files renamed,
+    /// regardless of groups, left-padded with zeroes to `padding` digits.
+    ///
+    /// Defaults to a `start` and `padding` of `0`.
+    pub fn with_global_counter(self, start: usize, padding: usize) -> Self {
+        Self {
+            global_start: start,
+            global_padding: padding,
+            ..self
+        }
+    }
+
+    /// Shortens `{name}` in rendered paths longer than `max` characters, by replacing characters
+    /// from its middle with [`TRIMMED_MARKER`]. Rendering fails if a path can't be shortened
+    /// enough (i.e., if it doesn't use `{name}`).
+    ///
+    /// Defaults to no limit.
+    pub fn with_max_path_len(self, max: Option<usize>) -> Self {
+        Self {
+            max_path_len: max,
+            ..self
+        }
+    }
+
+    /// Renders `{capN}` as the `N`th capture group of `pattern` in the file name of each source.
+    /// Rendering fails for sources that `pattern` doesn't match.
+    ///
+    /// Fails if the spec uses a capture group that `pattern` doesn't have.
+    pub fn with_capture_pattern(self, pattern: Regex) -> Result<Self, MissingCaptureGroupError> {
+        let groups = pattern.captures_len() - 1;
+        for content in self.dynamic_content() {
+            if let DynamicRenameContent::Capture { n } = content {
+                ensure!(
+                    n.get() <= groups,
+                    MissingCaptureGroupSnafu { n: n.get(), groups }
+                );
+            }
+        }
+        Ok(Self {
+            capture_pattern: Some(pattern),
+            ..self
+        })
+    }
+
+    /// Renders `{tag:NAME}` as `fallback` for files without the tag, rather than failing.
+    ///
+    /// Defaults to failing.
+    #[cfg(feature = "id3v1-tags")]
+    pub fn with_missing_tag_fallback(self, fallback: Option<String>) -> Self {
+        Self {
+            missing_tag: fallback,
+            ..self
+        }
+    }
+
+    /// Renders the target path of `from` as the file at `idx` of a sequence of `total` files, as
+    /// [`zip_single_side_scans`] would.
+    ///
+    /// `{padded_idx}` is padded to [`Self::padding_width`] of `total`: the number of digits of the
+    /// largest index written (accounting for [`Self::with_start_at`] and [`Self::with_step`]), or
+    /// [`Self::with_pad_width`], if set, but at least [`Self::with_min_padding`]. Each call
+    /// draws `{rand:N}` from a new random number generator, so a [seeded](Self::with_seed) spec
+    /// renders the same random characters for every `idx`. `{group}` fails, since there are no
+    /// groups, and `{dir_idx}` is the same as `{idx}`, as if every file were in one directory.
+    pub fn render(&self, idx: usize, total: usize, from: &Path) -> Result<String, RenderError> {
+        let ctx = RenameContext {
+            idx,
+            position: idx,
+            count: total,
+            from,
+            group: None,
+            dir_position: Some((idx, total)),
+        };
+        let mut to = String::new();
+        self.write(&ctx, total, &mut self.rng(), &mut to)?;
+        Ok(to)
+    }
+
+    /// The random number generator for `{rand:N}`, seeded as [configured](Self::with_seed).
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// The number of digits that indices of a sequence of `count` files are padded to.
+    pub fn padding_width(&self, count: usize) -> usize {
+        self.index_digits(count, 0).max(self.min_padding)
+    }
+
+    /// The number of digits needed to write the largest index of a sequence of `count` files,
+    /// plus `offset`.
+    fn index_digits(&self, count: usize, offset: usize) -> usize {
+        if let Some(width) = self.pad_width {
+            return width;
+        }
+        let largest = self.emitted_index(count.saturating_sub(1));
+        digits(largest.saturating_add(offset), self.radix)
+    }
+
+    /// The index written for the `idx`th file.
+    ///
+    /// Saturates rather than overflowing, since `idx` may come from an untrustworthy size hint.
+    fn emitted_index(&self, idx: usize) -> usize {
+        self.start_at
+            .saturating_add(idx.saturating_mul(self.step.get()))
+    }
+
+    /// Recovers the index that this spec would render as `path`, along with the number of digits
+    /// it was written with, if `{padded_idx}` is its only kind of replacement group.
+    ///
+    /// Extensions kept by [`Self::with_kept_extension`] are not accounted for.
+    pub fn parse_index(&self, path: &Path) -> Option<(usize, usize)> {
+        let mut remaining = path.to_str()?;
+        let mut parsed = None;
+        for (prefix, dyn_content, _case) in &self.delimited {
+            let DynamicRenameContent::PaddedInteger = dyn_content else {
+                return None;
+            };
+            remaining = remaining.strip_prefix(&**prefix)?;
+            let digits_len = remaining
+                .find(|c: char| !c.is_digit(self.radix))
+                .unwrap_or(remaining.len());
+            let (digits, rest) = remaining.split_at(digits_len);
+            let offset = usize::from_str_radix(digits, self.radix)
+                .ok()?
+                .checked_sub(self.start_at)?;
+            if offset % self.step.get() != 0 {
+                return None;
+            }
+            let idx = offset / self.step.get();
+            match parsed {
+                Some((prev, _)) if prev != idx => return None,
+                _ => parsed = Some((idx, digits.len())),
+            }
+            remaining = rest;
+        }
+        (remaining == self.suffix).then_some(parsed).flatten()
+    }
+
+    /// Seeds the random number generator used by `{rand:N}`, making its output reproducible.
+    ///
+    /// Defaults to a seed from the operating system's entropy source.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..self
         }
+    }
+
+    /// Appends the source file's extension (if it has one) to rendered names, unless this spec
+    /// [already ends with an extension](Self::ends_with_extension) or uses `{ext}`.
+    ///
+    /// Defaults to `false`.
+    pub fn with_kept_extension(self, keep_extension: bool) -> Self {
+        Self {
+            keep_extension,
+            ..self
+        }
+    }
+
+    /// Whether this spec ends with literal text that looks like a file extension: a `.`, followed
+    /// by one or more ASCII alphanumeric characters.
+    ///
+    /// For instance, `photo-{padded_idx}.jpg` ends with an extension, but `photo-{padded_idx}` and
+    /// `photo-{padded_idx}.` do not.
+    pub fn ends_with_extension(&self) -> bool {
+        let name = Path::new(&*self.suffix)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let name = if self.delimited.is_empty() || self.suffix.contains(std::path::is_separator) {
+            // A leading `.` makes a hidden file, not an extension.
+            name.strip_prefix('.').unwrap_or(name)
+        } else {
+            name
+        };
+        match name.rsplit_once('.') {
+            Some((_stem, ext)) => !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()),
+            None => false,
+        }
+    }
 
-        let suffix = remaining;
+    /// Renders indices in base `radix`, using lowercase letters for digits beyond `9` (unless
+    /// [`with_uppercase_digits`](Self::with_uppercase_digits)). Padding is computed in the same
+    /// base.
+    ///
+    /// Defaults to `10`.
+    pub fn with_radix(self, radix: u32) -> Result<Self, InvalidRadixError> {
+        if !(2..=36).contains(&radix) {
+            return InvalidRadixSnafu { radix }.fail();
+        }
+        Ok(Self { radix, ..self })
+    }
 
-        Ok(Self { delimited, suffix })
+    /// Writes digits beyond `9` in uppercase, e.g., `FF` rather than `ff` in base 16.
+    ///
+    /// Defaults to `false`.
+    pub fn with_uppercase_digits(self, uppercase_digits: bool) -> Self {
+        Self {
+            uppercase_digits,
+            ..self
+        }
     }
 
+    /// Whether this spec has any replacement groups, and so may render different names for
+    /// different files.
+    ///
+    /// Escaped braces (e.g., `name{{padded_idx}}.txt`) are literal text, and don't count.
     pub fn has_dynamic_content(&self) -> bool {
         !self.delimited.is_empty()
     }
 
-    fn write(&self, ctx: &RenameContext, mut f: impl fmt::Write) -> fmt::Result {
-        let Self { delimited, suffix } = self;
+    /// Iterates over the replacement groups in this spec, in order.
+    pub fn dynamic_content(&self) -> impl Iterator<Item = &DynamicRenameContent> {
+        self.delimited
+            .iter()
+            .map(|(_prefix, dyn_content, _case)| dyn_content)
+    }
+
+    /// Writes the target of `ctx` to `dest`, as one of `total` files being renamed together.
+    fn write(
+        &self,
+        ctx: &RenameContext,
+        total: usize,
+        rng: &mut StdRng,
+        mut dest: impl fmt::Write,
+    ) -> Result<(), RenderError> {
+        let Self {
+            delimited,
+            suffix,
+            radix,
+            uppercase_digits,
+            keep_extension,
+            seed: _,
+            min_padding,
+            pad_width: _,
+            index_field,
+            start_at: _,
+            step: _,
+            global_start,
+            global_padding,
+            max_path_len,
+            capture_pattern,
+            #[cfg(feature = "id3v1-tags")]
+            missing_tag,
+        } = self;
+        let &RenameContext { idx, count, .. } = ctx;
+
+        #[cfg(feature = "image")]
+        let mut image_dimensions = None;
+        #[cfg(feature = "image")]
+        let mut image_dimensions = || -> Result<(u32, u32), RenderError> {
+            if let Some(dims) = image_dimensions {
+                return Ok(dims);
+            }
+            let dims =
+                read_image_dimensions(ctx.from).context(ImageDimensionsSnafu { path: ctx.from })?;
+            Ok(*image_dimensions.insert(dims))
+        };
 
-        for (prefix, dyn_content) in delimited.iter() {
+        // Rendered in full first, since `{name}` may need to be shortened to fit `max_path_len`.
+        let mut f = String::new();
+        let mut names = Vec::new();
+        for (prefix, dyn_content, case) in delimited.iter() {
             write!(f, "{prefix}")?;
+            let start = f.len();
             match dyn_content {
                 DynamicRenameContent::PaddedInteger => {
-                    let RenameContext {
-                        idx,
-                        max_size_hint_digits,
-                    } = ctx;
-                    write!(f, "{idx:0padding$}", padding = max_size_hint_digits)?;
+                    let width = self.index_digits(count, 0).max(*min_padding);
+                    write_radix(
+                        &mut f,
+                        self.emitted_index(idx),
+                        *radix,
+                        *uppercase_digits,
+                        width,
+                    )?;
                 }
+                DynamicRenameContent::Integer => write_radix(
+                    &mut f,
+                    self.emitted_index(idx),
+                    *radix,
+                    *uppercase_digits,
+                    0,
+                )?,
+                DynamicRenameContent::OneBasedPaddedInteger => {
+                    let width = self.index_digits(count, 1).max(*min_padding);
+                    write_radix(
+                        &mut f,
+                        self.emitted_index(idx).saturating_add(1),
+                        *radix,
+                        *uppercase_digits,
+                        width,
+                    )?;
+                }
+                DynamicRenameContent::OneBasedInteger => write_radix(
+                    &mut f,
+                    self.emitted_index(idx).saturating_add(1),
+                    *radix,
+                    *uppercase_digits,
+                    0,
+                )?,
+                DynamicRenameContent::Alphabetic { uppercase } => {
+                    write_alphabetic(&mut f, self.emitted_index(idx), *uppercase)?
+                }
+                DynamicRenameContent::Roman { uppercase } => {
+                    let n = self.emitted_index(idx);
+                    ensure!(n != 0, RomanZeroSnafu);
+                    write_roman(&mut f, n, *uppercase)?
+                }
+                DynamicRenameContent::Total { padded } => {
+                    let width = match padded {
+                        true => self.index_digits(count, 0).max(*min_padding),
+                        false => 0,
+                    };
+                    write_radix(&mut f, total, *radix, *uppercase_digits, width)?;
+                }
+                DynamicRenameContent::DirIndex { padded } => {
+                    let (dir_idx, dir_count) = ctx
+                        .dir_position
+                        .expect("files weren't counted by directory for `{dir_idx}`");
+                    let width = match padded {
+                        true => self.index_digits(dir_count, 0).max(*min_padding),
+                        false => 0,
+                    };
+                    write_radix(
+                        &mut f,
+                        self.emitted_index(dir_idx),
+                        *radix,
+                        *uppercase_digits,
+                        width,
+                    )?;
+                }
+                DynamicRenameContent::Group => {
+                    f.write_str(ctx.group.ok_or(RenderError::NoGroup)?)?;
+                }
+                DynamicRenameContent::Capture { n } => {
+                    let pattern = capture_pattern
+                        .as_ref()
+                        .ok_or(RenderError::NoCapturePattern)?;
+                    let name = ctx.from.file_name().unwrap_or_default().to_string_lossy();
+                    let captures = pattern.captures(&name).context(UnmatchedSnafu {
+                        path: ctx.from,
+                        pattern: pattern.as_str(),
+                    })?;
+                    f.write_str(captures.get(n.get()).map_or("", |c| c.as_str()))?;
+                }
+                DynamicRenameContent::Name => {
+                    let stem = ctx.from.file_stem().unwrap_or_default();
+                    f.write_str(&stem.to_string_lossy())?;
+                }
+                DynamicRenameContent::Extension => match ctx.from.extension() {
+                    Some(ext) => f.write_str(&ext.to_string_lossy())?,
+                    // Don't leave a dangling `.` from `{name}.{ext}`.
+                    None if prefix.ends_with('.') => {
+                        f.pop();
+                    }
+                    None => (),
+                },
+                DynamicRenameContent::GlobalIndex => {
+                    let global_idx = global_start.checked_add(ctx.position).context(
+                        GlobalIndexOverflowSnafu {
+                            start: *global_start,
+                            position: ctx.position,
+                        },
+                    )?;
+                    write_radix(
+                        &mut f,
+                        global_idx,
+                        *radix,
+                        *uppercase_digits,
+                        *global_padding,
+                    )?;
+                }
+                DynamicRenameContent::Tail => {
+                    let stem = ctx.from.file_stem().unwrap_or_default().to_string_lossy();
+                    f.write_str(index_field.tail(&stem))?;
+                }
+                DynamicRenameContent::ModifiedDate { format } => {
+                    let mtime = fs::metadata(ctx.from)
+                        .and_then(|metadata| metadata.modified())
+                        .context(ReadModifiedTimeSnafu { path: ctx.from })?;
+                    let format = format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT);
+                    write_date(&mut f, mtime, format)
+                        .context(UnrepresentableTimeSnafu { path: ctx.from })?;
+                }
+                DynamicRenameContent::Random { len } => {
+                    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+                    for _ in 0..len.get() {
+                        f.write_char(CHARSET[rng.gen_range(0..CHARSET.len())].into())?;
+                    }
+                }
+                #[cfg(feature = "id3v1-tags")]
+                DynamicRenameContent::Tag { tag } => {
+                    let value =
+                        read_tag(ctx.from, *tag).context(ReadTagsSnafu { path: ctx.from })?;
+                    // A value of only characters that can't be in file names is as good as none.
+                    let value = value
+                        .map(|value| sanitize_file_name(&value))
+                        .filter(|value| !value.is_empty());
+                    let value = match (value, missing_tag) {
+                        (Some(value), _) => value,
+                        (None, Some(fallback)) => sanitize_file_name(fallback),
+                        (None, None) => {
+                            return MissingTagSnafu {
+                                path: ctx.from,
+                                tag: *tag,
+                            }
+                            .fail()
+                        }
+                    };
+                    f.write_str(&value)?;
+                }
+                #[cfg(feature = "image")]
+                DynamicRenameContent::ImageWidth => write!(f, "{}", image_dimensions()?.0)?,
+                #[cfg(feature = "image")]
+                DynamicRenameContent::ImageHeight => write!(f, "{}", image_dimensions()?.1)?,
+                DynamicRenameContent::Custom(custom) => f.write_str(&(custom.render)(ctx))?,
+            }
+            // `{ext}` may have removed the `.` before it, leaving nothing to change the case of.
+            if let (Some(case), Some(text)) = (case, f.get(start..)) {
+                let cased = case.apply(text);
+                f.truncate(start);
+                f.push_str(&cased);
+            }
+            if let DynamicRenameContent::Name = dyn_content {
+                names.push(start..f.len());
             }
         }
-        f.write_str(suffix)
+        f.write_str(suffix)?;
+        let uses_ext = delimited
+            .iter()
+            .any(|(_prefix, content, _case)| matches!(content, DynamicRenameContent::Extension));
+        if *keep_extension && !uses_ext && !self.ends_with_extension() {
+            if let Some(ext) = ctx.from.extension() {
+                write!(f, ".{}", ext.to_string_lossy())?;
+            }
+        }
+
+        if let Some(max) = *max_path_len {
+            f = trim_names(&f, &names, max)?;
+        }
+        ensure!(
+            !f.ends_with(std::path::is_separator) && Path::new(&f).file_name().is_some(),
+            NoFileNameSnafu { path: f }
+        );
+        dest.write_str(&f)?;
+        Ok(())
+    }
+}
+
+/// Replaces each character of `name` that isn't permitted in file names on common platforms (e.g.,
+/// `/`, `:`, or control characters) with `_`, and removes trailing dots and spaces, which Windows
+/// drops.
+pub fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if WINDOWS_ILLEGAL_CHARS.contains(&c) || c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_owned()
+}
+
+/// Characters that Windows doesn't permit in file names, besides control characters.
+const WINDOWS_ILLEGAL_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Names of devices that Windows reserves, with or without an extension, in any case.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks that `name` can be used as a file name on the current platform, or on Windows, which is
+/// the strictest, if `windows_rules` is set.
+pub fn check_file_name(name: &str, windows_rules: bool) -> Result<(), FileNameError> {
+    let windows_rules = windows_rules || cfg!(windows);
+    if let Some(c) = name.chars().find(|&c| {
+        c == '/'
+            || c == '\0'
+            || (windows_rules && (WINDOWS_ILLEGAL_CHARS.contains(&c) || c.is_control()))
+    }) {
+        return IllegalCharSnafu { c }.fail();
+    }
+    if windows_rules {
+        ensure!(!name.ends_with(['.', ' ']), TrailingDotOrSpaceSnafu);
+        let device = name.split('.').next().unwrap_or_default().trim_end();
+        ensure!(
+            !WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(device)),
+            ReservedNameSnafu { device }
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+pub enum FileNameError {
+    #[snafu(display("{c:?} isn't permitted in file names"))]
+    IllegalChar { c: char },
+    #[snafu(display("file names can't end with `.` or ` ` on Windows"))]
+    TrailingDotOrSpace,
+    #[snafu(display("`{device}` is a reserved device name on Windows"))]
+    ReservedName { device: String },
+}
+
+/// Replaces characters removed from the middle of `{name}` by [`RenameSpec::with_max_path_len`].
+pub const TRIMMED_MARKER: &str = "~";
+
+/// Shortens each of the `names` ranges of `path` evenly, from their middles, until `path` is no
+/// longer than `max` characters.
+fn trim_names(path: &str, names: &[Range<usize>], max: usize) -> Result<String, RenderError> {
+    let len = path.chars().count();
+    let too_long = || PathTooLongSnafu { len, max }.fail();
+    if len <= max {
+        return Ok(path.to_owned());
+    }
+    if names.is_empty() {
+        return too_long();
+    }
+
+    let marker_len = TRIMMED_MARKER.chars().count();
+    let cut_each = (len - max).div_ceil(names.len()) + marker_len;
+    let mut trimmed = String::new();
+    let mut end = 0;
+    for range in names {
+        let name = &path[range.clone()];
+        // Keep at least one character on either side of the marker.
+        let Some(kept) = name
+            .chars()
+            .count()
+            .checked_sub(cut_each)
+            .filter(|&kept| kept >= 2)
+        else {
+            return too_long();
+        };
+        let (head, tail) = (kept.div_ceil(2), kept / 2);
+        trimmed.push_str(&path[end..range.start]);
+        trimmed.extend(name.chars().take(head));
+        trimmed.push_str(TRIMMED_MARKER);
+        trimmed.extend(name.chars().skip(name.chars().count() - tail));
+        end = range.end;
     }
+    trimmed.push_str(&path[end..]);
+    Ok(trimmed)
 }
 
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum DynamicRenameContent {
+    /// `{padded_idx}`: the index of the file, zero-padded to the width of the largest index.
     PaddedInteger,
+    /// `{idx}`: the index of the file, without any padding.
+    Integer,
+    /// `{padded_idx1}`: one more than `{padded_idx}`, i.e., counting from one, zero-padded to the
+    /// width of the largest such index.
+    OneBasedPaddedInteger,
+    /// `{idx1}`: one more than `{idx}`, i.e., counting from one, without any padding.
+    OneBasedInteger,
+    /// `{alpha}`, or `{ALPHA}` if `uppercase`: the index of the file in spreadsheet-style letters,
+    /// i.e., `a`, `b`, …, `z`, `aa`, `ab`, and so on.
+    Alphabetic { uppercase: bool },
+    /// `{roman}`, or `{roman_lower}` unless `uppercase`: the index of the file in Roman numerals,
+    /// which fails for index 0, so [counting](RenameSpec::with_start_at) should start from 1.
+    Roman { uppercase: bool },
+    /// `{total}`, or `{padded_total}` if `padded`: the number of files being renamed together
+    /// (even if they are numbered in separate groups), zero-padded to the width of
+    /// `{padded_idx}`.
+    Total { padded: bool },
+    /// `{dir_idx}`, or `{padded_dir_idx}` if `padded`: the index of the file among the files
+    /// being renamed that are in the same directory, zero-padded to the width of the largest such
+    /// index in that directory.
+    DirIndex { padded: bool },
+    /// `{group}`: the key of the group that the file was assigned to, as with [`zip_grouped`].
+    Group,
+    /// `{capN}`: the `n`th capture group of the [capture pattern](RenameSpec::with_capture_pattern)
+    /// in the source's file name, or nothing if the group didn't participate in the match.
+    Capture { n: NonZeroUsize },
+    /// `{name}`: the file name of the source, without its extension (which
+    /// [`RenameSpec::with_kept_extension`] can add back). Dotfiles like `.bashrc` have no
+    /// extension, so their whole name is used.
+    Name,
+    /// `{ext}`: the extension of the source, without its leading `.`. If it has none, this is
+    /// empty, and a `.` written just before it is removed.
+    Extension,
+    /// `{global_idx}`: the position of the file among all files renamed, offset by the start of a
+    /// [counter](RenameSpec::with_global_counter) kept across runs.
+    GlobalIndex,
+    /// `{tail}`: the part of the source's stem after its [index field](RenameSpec::with_index_field),
+    /// without leading separators, or nothing if its stem doesn't have the field.
+    Tail,
+    /// `{date}`, or `{mtime:FORMAT}` with a `format`: the modification time of the source, in UTC.
+    /// `FORMAT` may contain `%Y`, `%m`, `%d`, `%H`, `%M`, and `%S` for the year, month, day, hour,
+    /// minute, and second, and `%%` for a literal `%`. Without one, the date is written as
+    /// `YYYYMMDD`.
+    ModifiedDate { format: Option<String> },
+    /// `{rand:N}`: `len` random lowercase ASCII letters and digits, drawn separately for each file.
+    Random { len: NonZeroUsize },
+    /// `{tag:NAME}`: the value of a field of the ID3v1 tag of the source file, with characters not
+    /// permitted in file names [replaced](sanitize_file_name).
+    #[cfg(feature = "id3v1-tags")]
+    Tag { tag: Id3v1Tag },
+    /// `{width}`: the width, in pixels, of the source image.
+    #[cfg(feature = "image")]
+    ImageWidth,
+    /// `{height}`: the height, in pixels, of the source image.
+    #[cfg(feature = "image")]
+    ImageHeight,
+    /// A replacement group supplied by the user of this crate, as with
+    /// [`RenameSpec::new_with_custom`].
+    Custom(CustomContent),
+}
+
+impl FromStr for DynamicRenameContent {
+    type Err = ParseDynamicRenameContentError;
+
+    /// Parses a built-in replacement group, as written between `{` and `}`, e.g., `padded_idx` or
+    /// `rand:4`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_name(s).context(ParseDynamicRenameContentSnafu)
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("failed to parse replacement group"))]
+pub struct ParseDynamicRenameContentError {
+    source: RenameSpecParseErrorKind,
+}
+
+/// A replacement group that renders file names with a closure, for embedders to add replacement
+/// groups that this crate doesn't have.
+#[derive(Clone)]
+pub struct CustomContent {
+    name: String,
+    render: Arc<dyn Fn(&RenameContext) -> String + Send + Sync>,
+}
+
+impl CustomContent {
+    /// Names a replacement group written as `{name}`, which is rendered by `render`.
+    pub fn new(
+        name: impl Into<String>,
+        render: impl Fn(&RenameContext) -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            render: Arc::new(render),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for CustomContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomContent")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DynamicRenameContent {
+    /// Every replacement group supported by this build, by the name written between `{` and `}`.
+    pub const BY_NAME: &'static [(&'static str, Self)] = &[
+        ("padded_idx", Self::PaddedInteger),
+        ("idx", Self::Integer),
+        ("padded_idx1", Self::OneBasedPaddedInteger),
+        ("idx1", Self::OneBasedInteger),
+        ("alpha", Self::Alphabetic { uppercase: false }),
+        ("ALPHA", Self::Alphabetic { uppercase: true }),
+        ("roman", Self::Roman { uppercase: true }),
+        ("roman_lower", Self::Roman { uppercase: false }),
+        ("total", Self::Total { padded: false }),
+        ("padded_total", Self::Total { padded: true }),
+        ("padded_dir_idx", Self::DirIndex { padded: true }),
+        ("dir_idx", Self::DirIndex { padded: false }),
+        ("group", Self::Group),
+        ("name", Self::Name),
+        ("ext", Self::Extension),
+        ("tail", Self::Tail),
+        ("global_idx", Self::GlobalIndex),
+        ("date", Self::ModifiedDate { format: None }),
+        #[cfg(feature = "image")]
+        ("width", Self::ImageWidth),
+        #[cfg(feature = "image")]
+        ("height", Self::ImageHeight),
+    ];
+
+    /// Replacement groups that take an argument, written as `{name:ARG}`, by their syntax.
+    pub const WITH_ARGUMENT: &'static [&'static str] = &[
+        "mtime:FORMAT",
+        "rand:N",
+        #[cfg(feature = "id3v1-tags")]
+        "tag:NAME",
+    ];
+
+    /// Replacement groups that are numbered, written as `{nameN}`, by their syntax.
+    pub const NUMBERED: &'static [&'static str] = &["capN"];
+
+    /// The names of every replacement group supported by this build, without arguments or
+    /// numbers.
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        Self::BY_NAME
+            .iter()
+            .map(|(name, _content)| *name)
+            .chain(
+                Self::WITH_ARGUMENT
+                    .iter()
+                    .map(|syntax| syntax.split_once(':').map_or(*syntax, |(name, _arg)| name)),
+            )
+            .chain(
+                Self::NUMBERED
+                    .iter()
+                    .map(|syntax| syntax.strip_suffix('N').unwrap_or(syntax)),
+            )
+    }
+
+    /// The name of this replacement group, without any argument.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Custom(custom) => custom.name(),
+            Self::Alphabetic { uppercase: false } => "alpha",
+            Self::Alphabetic { uppercase: true } => "ALPHA",
+            Self::Roman { uppercase: true } => "roman",
+            Self::Roman { uppercase: false } => "roman_lower",
+            Self::Total { padded: false } => "total",
+            Self::Total { padded: true } => "padded_total",
+            Self::DirIndex { padded: true } => "padded_dir_idx",
+            Self::DirIndex { padded: false } => "dir_idx",
+            Self::Capture { .. } => "cap",
+            Self::ModifiedDate { format: Some(_) } => "mtime",
+            Self::Random { .. } => "rand",
+            #[cfg(feature = "id3v1-tags")]
+            Self::Tag { .. } => "tag",
+            _ => {
+                Self::BY_NAME
+                    .iter()
+                    .find(|(_name, content)| mem::discriminant(content) == mem::discriminant(self))
+                    .expect("replacement group is missing from `BY_NAME`")
+                    .0
+            }
+        }
+    }
+
+    /// Whether this replacement group renders text taken from the source or from elsewhere,
+    /// rather than a number or a date, so that a [`Case`] can be applied to it.
+    pub fn is_text(&self) -> bool {
+        match self {
+            Self::Group
+            | Self::Capture { .. }
+            | Self::Name
+            | Self::Extension
+            | Self::Tail
+            | Self::Custom(_) => true,
+            #[cfg(feature = "id3v1-tags")]
+            Self::Tag { .. } => true,
+            _ => false,
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, RenameSpecParseErrorKind> {
+        if let Some(format) = name.strip_prefix("mtime:") {
+            write_date(&mut String::new(), UNIX_EPOCH, format).map_err(|_| {
+                RenameSpecParseErrorKind::InvalidDateFormat {
+                    format: format.to_owned(),
+                }
+            })?;
+            return Ok(Self::ModifiedDate {
+                format: Some(format.to_owned()),
+            });
+        }
+        if let Some(n) = name
+            .strip_prefix("cap")
+            .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|n| n.parse().ok())
+        {
+            return Ok(Self::Capture { n });
+        }
+        if let Some(len) = name.strip_prefix("rand:") {
+            return match len.parse() {
+                Ok(len) => Ok(Self::Random { len }),
+                Err(_) => Err(RenameSpecParseErrorKind::InvalidRandomLength {
+                    len: len.to_owned(),
+                }),
+            };
+        }
+        #[cfg(feature = "id3v1-tags")]
+        if let Some(tag) = name.strip_prefix("tag:") {
+            return match tag.parse() {
+                Ok(tag) => Ok(Self::Tag { tag }),
+                Err(()) => Err(RenameSpecParseErrorKind::UnrecognizedTag {
+                    tag: tag.to_owned(),
+                }),
+            };
+        }
+        Self::BY_NAME
+            .iter()
+            .find(|(n, _content)| *n == name)
+            .map(|(_name, content)| content.clone())
+            .ok_or_else(|| RenameSpecParseErrorKind::UnrecognizedReplacementGroup {
+                name: name.to_owned(),
+            })
+    }
+}
+
+/// The case that [text](DynamicRenameContent::is_text) rendered by a replacement group is
+/// changed to, written after a `:` at the end of the group, e.g., `{name:lower}`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Case {
+    /// `lower`: every letter is lowercase.
+    Lower,
+    /// `upper`: every letter is uppercase.
+    Upper,
+    /// `title`: the first letter of each word is uppercase, and the rest are lowercase. Words are
+    /// separated by anything other than letters and digits, like spaces, `-`, and `_`.
+    Title,
+}
+
+impl Case {
+    /// Every case, by the name written after the `:`.
+    pub const BY_NAME: &'static [(&'static str, Self)] = &[
+        ("lower", Self::Lower),
+        ("upper", Self::Upper),
+        ("title", Self::Title),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        Self::BY_NAME
+            .iter()
+            .find(|(_name, case)| case == self)
+            .expect("case is missing from `BY_NAME`")
+            .0
+    }
+
+    /// Changes `text` to this case.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Self::Lower => text.to_lowercase(),
+            Self::Upper => text.to_uppercase(),
+            Self::Title => {
+                let mut cased = String::with_capacity(text.len());
+                let mut word_start = true;
+                for c in text.chars() {
+                    if word_start {
+                        cased.extend(c.to_uppercase());
+                    } else {
+                        cased.extend(c.to_lowercase());
+                    }
+                    word_start = !c.is_alphanumeric();
+                }
+                cased
+            }
+        }
+    }
+}
+
+impl FromStr for Case {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::BY_NAME
+            .iter()
+            .find(|(name, _case)| *name == s)
+            .map(|(_name, case)| *case)
+            .ok_or(())
+    }
+}
+
+/// Writes `n` in base `radix`, left-padded with zeroes to `width` digits.
+fn write_radix(
+    mut f: impl fmt::Write,
+    n: usize,
+    radix: u32,
+    uppercase: bool,
+    width: usize,
+) -> fmt::Result {
+    if radix == 10 {
+        return write!(f, "{n:0width$}");
+    }
+    let mut digits = Vec::new();
+    let mut remaining = n;
+    loop {
+        let digit = u32::try_from(remaining % radix as usize).unwrap();
+        let digit = char::from_digit(digit, radix).unwrap();
+        digits.push(if uppercase {
+            digit.to_ascii_uppercase()
+        } else {
+            digit
+        });
+        remaining /= radix as usize;
+        if remaining == 0 {
+            break;
+        }
+    }
+    for _ in digits.len()..width {
+        f.write_char('0')?;
+    }
+    digits.iter().rev().try_for_each(|&c| f.write_char(c))
+}
+
+/// The format of `{date}`, as in `{mtime:FORMAT}`.
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d";
+
+/// Writes `time`, in UTC, according to `format`, as described by
+/// [`DynamicRenameContent::ModifiedDate`]. Fails if `format` has an unsupported `%` specifier, or
+/// `time` isn't from the years 1970 to 9999.
+fn write_date(mut f: impl fmt::Write, time: SystemTime, format: &str) -> fmt::Result {
+    // `10000-01-01T00:00:00Z`, the first time that RFC 3339 timestamps can't represent.
+    const MAX_SECS: u64 = 253_402_300_800;
+    let since_epoch = time.duration_since(UNIX_EPOCH).map_err(|_| fmt::Error)?;
+    if since_epoch.as_secs() >= MAX_SECS {
+        return Err(fmt::Error);
+    }
+    // I.e., `YYYY-MM-DDTHH:MM:SSZ`.
+    let stamp = humantime::format_rfc3339_seconds(time).to_string();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            f.write_char(c)?;
+            continue;
+        }
+        let field = match chars.next().ok_or(fmt::Error)? {
+            'Y' => &stamp[0..4],
+            'm' => &stamp[5..7],
+            'd' => &stamp[8..10],
+            'H' => &stamp[11..13],
+            'M' => &stamp[14..16],
+            'S' => &stamp[17..19],
+            '%' => "%",
+            _ => return Err(fmt::Error),
+        };
+        f.write_str(field)?;
+    }
+    Ok(())
+}
+
+/// Writes `n` in bijective base 26, where `a` (or `A`, if `uppercase`) through `z` are the digits
+/// one through 26, such that `0` is `a`, `25` is `z`, and `26` is `aa`.
+fn write_alphabetic(mut f: impl fmt::Write, n: usize, uppercase: bool) -> fmt::Result {
+    let first = if uppercase { b'A' } else { b'a' };
+    let mut letters = Vec::new();
+    let mut remaining = n;
+    loop {
+        letters.push(char::from(first + u8::try_from(remaining % 26).unwrap()));
+        if remaining < 26 {
+            break;
+        }
+        remaining = remaining / 26 - 1;
+    }
+    letters.iter().rev().try_for_each(|&c| f.write_char(c))
+}
+
+/// Writes `n` in Roman numerals, in lowercase unless `uppercase`. Beyond 3999 (`MMMCMXCIX`), each
+/// additional thousand is another `M`.
+fn write_roman(mut f: impl fmt::Write, n: usize, uppercase: bool) -> fmt::Result {
+    const NUMERALS: &[(usize, &str)] = &[
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut remaining = n;
+    for &(value, numeral) in NUMERALS {
+        while remaining >= value {
+            if uppercase {
+                f.write_str(numeral)?;
+            } else {
+                f.write_str(&numeral.to_ascii_lowercase())?;
+            }
+            remaining -= value;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("radix {radix} is not in the supported range of 2 to 36"))]
+pub struct InvalidRadixError {
+    radix: u32,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("`{{cap{n}}}` was used, but the capture pattern only has {groups} group(s)"))]
+pub struct MissingCaptureGroupError {
+    n: usize,
+    groups: usize,
 }
 
 #[derive(Debug, Snafu)]
@@ -79,53 +1266,346 @@ pub struct RenameSpecParseError {
 
 #[derive(Debug, Snafu)]
 enum RenameSpecParseErrorKind {
+    #[snafu(display("found opening `{{` without a matching closing `}}`"))]
+    UnclosedCurlyBrace,
+    #[snafu(display(
+        "found closing `}}` without a matching opening `{{`; write `}}}}` for a literal `}}`"
+    ))]
+    UnmatchedClosingCurlyBrace,
+    #[snafu(display("unrecognized replacement group `{{{name}}}`"))]
+    UnrecognizedReplacementGroup { name: String },
+    #[snafu(display("replacement group `{name}` is disabled"))]
+    DisabledReplacementGroup { name: String },
     #[snafu(display(
-        "expected replacement group `{{padded_idx}}`, but content after opening `{{` does not match"
+        "replacement group `{name}` doesn't render text, so `:{}` can't change its case",
+        case.name()
     ))]
-    UnexpectedAfterOpenCurlyBrace,
+    CaseOfNonText { name: String, case: Case },
+    #[snafu(display("expected a positive number of characters in `{{rand:N}}`, got `{len}`"))]
+    InvalidRandomLength { len: String },
+    #[snafu(display(
+        "invalid format `{format}` in `{{mtime:FORMAT}}`; expected only `%Y`, `%m`, `%d`, `%H`, \
+        `%M`, `%S`, or `%%` after `%`"
+    ))]
+    InvalidDateFormat { format: String },
+    #[cfg(feature = "id3v1-tags")]
+    #[snafu(display(
+        "unrecognized tag `{tag}` in `{{tag:NAME}}`; expected one of {:?}",
+        Id3v1Tag::BY_NAME.iter().map(|(name, _tag)| name).collect::<Vec<_>>()
+    ))]
+    UnrecognizedTag { tag: String },
+}
+
+/// An error encountered while rendering the target path of a single file.
+#[derive(Debug, Snafu)]
+pub enum RenderError {
+    #[snafu(context(false), display("failed to format target path"))]
+    Fmt { source: fmt::Error },
+    #[snafu(display("`{{group}}` was used, but files were not grouped"))]
+    NoGroup,
+    #[snafu(display("`{{capN}}` was used, but no pattern to capture from was given"))]
+    NoCapturePattern,
+    #[snafu(display("file name of {path:?} doesn't match `{pattern}`"))]
+    Unmatched { path: PathBuf, pattern: String },
+    #[snafu(display("index 0 can't be written in Roman numerals; start counting from 1 instead"))]
+    RomanZero,
+    #[snafu(display(
+        "target path is {len} characters long, and can't be shortened to {max} by trimming \
+        `{{name}}`"
+    ))]
+    PathTooLong { len: usize, max: usize },
+    #[snafu(display("target path {path:?} has no file name"))]
+    NoFileName { path: String },
+    #[snafu(display("`{{global_idx}}` of file {position} overflows, counting from {start}"))]
+    GlobalIndexOverflow { start: usize, position: usize },
+    #[snafu(display("failed to read modification time of {path:?}"))]
+    ReadModifiedTime { path: PathBuf, source: io::Error },
+    #[snafu(display("modification time of {path:?} is not between the years 1970 and 9999"))]
+    UnrepresentableTime { path: PathBuf, source: fmt::Error },
+    #[cfg(feature = "id3v1-tags")]
+    #[snafu(display("failed to read tags of {path:?}"))]
+    ReadTags { path: PathBuf, source: io::Error },
+    #[cfg(feature = "id3v1-tags")]
+    #[snafu(display("{path:?} has no `{tag}` tag"))]
+    MissingTag { path: PathBuf, tag: Id3v1Tag },
+    #[cfg(feature = "image")]
+    #[snafu(display("failed to read image dimensions of {path:?}"))]
+    ImageDimensions {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+/// Reads the dimensions of the image at `path`, without decoding the entire image.
+#[cfg(feature = "image")]
+pub fn read_image_dimensions(path: &Path) -> image::ImageResult<(u32, u32)> {
+    image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_dimensions()
 }
 
-struct RenameContext {
+/// What a file is being renamed as part of, for [`CustomContent`] to render.
+pub struct RenameContext<'a> {
     idx: usize,
-    max_size_hint_digits: usize,
+    /// The position of `from` among all files being renamed, which differs from `idx` for
+    /// [grouped](zip_grouped), [resetting](zip_resetting), or [indexed](zip_indexed) sequences.
+    position: usize,
+    /// The number of indices in the sequence that `idx` belongs to, which determines padding.
+    count: usize,
+    from: &'a Path,
+    group: Option<&'a str>,
+    /// The index of `from` among the files in its directory, and the number of them, which
+    /// [`visit_all`] only counts for specs that use `{dir_idx}`.
+    dir_position: Option<(usize, usize)>,
 }
 
+impl<'a> RenameContext<'a> {
+    /// The position of the file in its sequence, counting from 0, before any
+    /// [offset](RenameSpec::with_start_at) or [step](RenameSpec::with_step).
+    pub fn idx(&self) -> usize {
+        self.idx
+    }
+
+    /// The position of the file among all files being renamed, counting from 0, even if it
+    /// belongs to a group.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The number of files in the sequence that the file belongs to.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The current path of the file.
+    pub fn from(&self) -> &'a Path {
+        self.from
+    }
+
+    /// The key of the group that the file was assigned to, if files were
+    /// [grouped](zip_grouped).
+    pub fn group(&self) -> Option<&'a str> {
+        self.group
+    }
+}
+
+/// The number of digits needed to write `n` in base `radix`.
+fn digits(n: usize, radix: u32) -> usize {
+    let mut digits = 1;
+    let mut remaining = n / radix as usize;
+    while remaining > 0 {
+        digits += 1;
+        remaining /= radix as usize;
+    }
+    digits
+}
+
+/// Renders the target of each of `files`, in order, and visits it with `visitor`.
+///
+/// Indices are padded for as many files as the upper bound of the [size hint](Iterator::size_hint)
+/// of `files`, or its lower bound if there is none, so iterators with loose hints should be
+/// [counted](zip_counted) instead.
 pub fn zip_single_side_scans<'a, V>(
     files: impl Iterator<Item = &'a Path>,
     rename_spec: RenameSpec,
+    visitor: V,
+) -> Result<(), V::Error>
+where
+    V: Visitor,
+{
+    let count = {
+        let (min, max) = files.size_hint();
+        max.unwrap_or(min)
+    };
+    zip_counted(files, count, rename_spec, visitor)
+}
+
+/// Like [`zip_single_side_scans`], but indices are padded for `count` files, regardless of the
+/// size hint of `files`.
+pub fn zip_counted<'a, V>(
+    files: impl Iterator<Item = &'a Path>,
+    count: usize,
+    rename_spec: RenameSpec,
+    visitor: V,
+) -> Result<(), V::Error>
+where
+    V: Visitor,
+{
+    visit_all(
+        files.enumerate().map(|(idx, from)| RenameContext {
+            idx,
+            position: idx,
+            count,
+            from,
+            group: None,
+            dir_position: None,
+        }),
+        &rename_spec,
+        visitor,
+    )
+}
+
+/// Like [`zip_single_side_scans`], but indices start over from 0 after every `every` files, and
+/// are padded as if there were `every` files, even in a last run of fewer.
+pub fn zip_resetting<'a, V>(
+    files: impl Iterator<Item = &'a Path>,
+    every: NonZeroUsize,
+    rename_spec: RenameSpec,
+    visitor: V,
+) -> Result<(), V::Error>
+where
+    V: Visitor,
+{
+    visit_all(
+        files.enumerate().map(|(position, from)| RenameContext {
+            idx: position % every,
+            position,
+            count: every.get(),
+            from,
+            group: None,
+            dir_position: None,
+        }),
+        &rename_spec,
+        visitor,
+    )
+}
+
+/// Pairs each of `files` with the target that [`zip_single_side_scans`] would visit it with,
+/// without needing a [`Visitor`]. Fails with the first target that can't be rendered.
+pub fn plan<'a>(
+    files: impl Iterator<Item = &'a Path>,
+    rename_spec: RenameSpec,
+) -> Result<Vec<(PathBuf, PathBuf)>, RenderError> {
+    struct Pairs(Vec<(PathBuf, PathBuf)>);
+
+    impl Visitor for Pairs {
+        type Error = RenderError;
+
+        fn visit(&mut self, _idx: usize, from: &Path, to: PathBuf) -> ControlFlow<Self::Error> {
+            self.0.push((from.to_owned(), to));
+            ControlFlow::Continue(())
+        }
+
+        fn render_failed(
+            &mut self,
+            _idx: usize,
+            _from: &Path,
+            error: RenderError,
+        ) -> ControlFlow<Self::Error> {
+            ControlFlow::Break(error)
+        }
+    }
+
+    let mut pairs = Pairs(Vec::new());
+    zip_single_side_scans(files, rename_spec, &mut pairs)?;
+    Ok(pairs.0)
+}
+
+/// Like [`zip_single_side_scans`], but each of `files` is paired with the key of a group, and
+/// indices (and their padding) are counted separately within each group.
+///
+/// Files are still visited in the order given, rather than group by group. The key of a file's
+/// group is available to rename specs via `{group}`.
+pub fn zip_grouped<'a, V>(
+    files: &[(&'a Path, &'a str)],
+    rename_spec: RenameSpec,
+    visitor: V,
+) -> Result<(), V::Error>
+where
+    V: Visitor,
+{
+    let mut group_sizes = HashMap::<&str, usize>::new();
+    for &(_from, group) in files {
+        *group_sizes.entry(group).or_default() += 1;
+    }
+
+    let mut next_idxs = HashMap::<&str, usize>::new();
+    visit_all(
+        files.iter().enumerate().map(|(position, &(from, group))| {
+            let next_idx = next_idxs.entry(group).or_default();
+            let idx = *next_idx;
+            *next_idx += 1;
+            RenameContext {
+                idx,
+                position,
+                count: group_sizes[group],
+                from,
+                group: Some(group),
+                dir_position: None,
+            }
+        }),
+        &rename_spec,
+        visitor,
+    )
+}
+
+/// Like [`zip_single_side_scans`], but each of `files` is paired with the index it should be
+/// numbered with, rather than being numbered in order. Padding accommodates the largest index.
+pub fn zip_indexed<V>(
+    files: &[(&Path, usize)],
+    rename_spec: RenameSpec,
+    visitor: V,
+) -> Result<(), V::Error>
+where
+    V: Visitor,
+{
+    let max_idx = files.iter().map(|&(_from, idx)| idx).max().unwrap_or(0);
+    let count = max_idx + 1;
+    visit_all(
+        files
+            .iter()
+            .enumerate()
+            .map(|(position, &(from, idx))| RenameContext {
+                idx,
+                position,
+                count,
+                from,
+                group: None,
+                dir_position: None,
+            }),
+        &rename_spec,
+        visitor,
+    )
+}
+
+fn visit_all<'a, V>(
+    contexts: impl Iterator<Item = RenameContext<'a>>,
+    rename_spec: &RenameSpec,
     mut visitor: V,
 ) -> Result<(), V::Error>
 where
     V: Visitor,
 {
-    let max_size_hint_digits = {
-        let max_hinted_size = {
-            let (min, max) = files.size_hint();
-            max.unwrap_or(min)
-        };
-        match max_hinted_size {
-            0 => 1,
-            n => (f64::try_from(u32::try_from(n).unwrap()).unwrap().log10() as usize)
-                .checked_add(1)
-                .unwrap(),
+    let mut contexts = contexts.collect::<Vec<_>>();
+    if rename_spec
+        .dynamic_content()
+        .any(|c| matches!(c, DynamicRenameContent::DirIndex { .. }))
+    {
+        let mut dir_sizes = HashMap::<Option<&Path>, usize>::new();
+        for ctx in &contexts {
+            *dir_sizes.entry(ctx.from.parent()).or_default() += 1;
         }
-    };
-    for (idx, from) in files.enumerate() {
-        let to = {
-            let mut to = String::new();
-            rename_spec
-                .write(
-                    &RenameContext {
-                        idx,
-                        max_size_hint_digits,
-                    },
-                    &mut to,
-                )
-                .unwrap();
-            PathBuf::from(to)
-        };
+        let mut next_idxs = HashMap::<Option<&Path>, usize>::new();
+        for ctx in &mut contexts {
+            let dir = ctx.from.parent();
+            let next_idx = next_idxs.entry(dir).or_default();
+            ctx.dir_position = Some((*next_idx, dir_sizes[&dir]));
+            *next_idx += 1;
+        }
+    }
+
+    let total = contexts.len();
+    let mut rng = rename_spec.rng();
+    for (idx, ctx) in contexts.into_iter().enumerate() {
+        let mut to = String::new();
+        let rendered = rename_spec.write(&ctx, total, &mut rng, &mut to);
 
-        match visitor.visit(idx, from, to) {
+        let from = ctx.from;
+        let flow = match rendered {
+            Ok(()) => visitor.visit(idx, from, PathBuf::from(to)),
+            Err(e) => visitor.render_failed(idx, from, e),
+        };
+        match flow {
             ControlFlow::Continue(()) => (),
             ControlFlow::Break(e) => return Err(e),
         }
@@ -137,14 +1617,976 @@ pub trait Visitor {
     type Error;
 
     fn visit(&mut self, idx: usize, from: &Path, to: PathBuf) -> ControlFlow<Self::Error>;
+
+    /// Called instead of [`Self::visit`] when the target path of `from` could not be rendered.
+    ///
+    /// Defaults to logging `error` and moving on to the next file.
+    fn render_failed(
+        &mut self,
+        idx: usize,
+        from: &Path,
+        error: RenderError,
+    ) -> ControlFlow<Self::Error> {
+        tracing::error!("failed to determine target path of {from:?} (index {idx}): {error}");
+        ControlFlow::Continue(())
+    }
+}
+
+impl<V> Visitor for &mut V
+where
+    V: Visitor + ?Sized,
+{
+    type Error = V::Error;
+
+    fn visit(&mut self, idx: usize, from: &Path, to: PathBuf) -> ControlFlow<Self::Error> {
+        (**self).visit(idx, from, to)
+    }
+
+    fn render_failed(
+        &mut self,
+        idx: usize,
+        from: &Path,
+        error: RenderError,
+    ) -> ControlFlow<Self::Error> {
+        (**self).render_failed(idx, from, error)
+    }
+}
+
+#[cfg(test)]
+struct CollectTargets(Vec<PathBuf>);
+
+#[cfg(test)]
+impl Visitor for CollectTargets {
+    type Error = std::convert::Infallible;
+
+    fn visit(&mut self, _idx: usize, _from: &Path, to: PathBuf) -> ControlFlow<Self::Error> {
+        self.0.push(to);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+fn targets(spec: &str, count: usize) -> Vec<PathBuf> {
+    let files = (0..count)
+        .map(|idx| PathBuf::from(format!("file-{idx}")))
+        .collect::<Vec<_>>();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().map(|p| p.as_path()),
+        RenameSpec::new(spec).unwrap(),
+        &mut visitor,
+    )
+    .unwrap();
+    visitor.0
 }
 
 #[test]
 fn no_replacement() {
-    todo!()
+    let spec = RenameSpec::new("asdf.txt").unwrap();
+    assert!(!spec.has_dynamic_content());
+    assert_eq!(targets("asdf.txt", 3), ["asdf.txt"; 3].map(PathBuf::from));
+}
+
+#[test]
+fn unpadded_idx() {
+    let eleven = targets("track-{idx}.flac", 11);
+    assert_eq!(eleven[0], Path::new("track-0.flac"));
+    assert_eq!(eleven[10], Path::new("track-10.flac"));
+
+    let both = targets("{idx}-{padded_idx}", 11);
+    assert_eq!(both[1], Path::new("1-01"));
+    assert!(RenameSpec::new("{idx_}").is_err());
+    assert!(RenameSpec::new("{padded_id}").is_err());
+}
+
+#[test]
+fn ext() {
+    let spec = RenameSpec::new("shot-{padded_idx}.{ext}").unwrap();
+    let files = ["a.jpeg", "b.png", "c"].map(PathBuf::from);
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().map(|p| p.as_path()),
+        spec.with_kept_extension(true),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(
+        visitor.0,
+        ["shot-0.jpeg", "shot-1.png", "shot-2"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn name_of_dotfiles() {
+    let spec = RenameSpec::new("{padded_idx}-{name}.{ext}").unwrap();
+    let files = [".bashrc", "archive.tar.gz", "dir/.hidden.txt", "README"].map(PathBuf::from);
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(files.iter().map(|p| p.as_path()), spec, &mut visitor).unwrap();
+    assert_eq!(
+        visitor.0,
+        ["0-.bashrc", "1-archive.tar.gz", "2-.hidden.txt", "3-README"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn multiple_groups() {
+    let files = ["clip.mp4", "song.ogg"].map(PathBuf::from);
+    let render = |spec| {
+        let mut visitor = CollectTargets(Vec::new());
+        zip_single_side_scans(
+            files.iter().map(|p| p.as_path()),
+            RenameSpec::new(spec).unwrap(),
+            &mut visitor,
+        )
+        .unwrap();
+        visitor.0
+    };
+    assert_eq!(
+        render("{name}-{padded_idx}.{ext}"),
+        ["clip-0.mp4", "song-1.ogg"].map(PathBuf::from)
+    );
+    assert_eq!(
+        render("[{idx}] {name} ({ext}).bak"),
+        ["[0] clip (mp4).bak", "[1] song (ogg).bak"].map(PathBuf::from)
+    );
+    assert_eq!(
+        render("{padded_idx}{name}{ext}"),
+        ["0clipmp4", "1songogg"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn pad_width() {
+    let spec = RenameSpec::new("{padded_idx}")
+        .unwrap()
+        .with_pad_width(Some(1));
+    let files = (0..11)
+        .map(|idx| PathBuf::from(idx.to_string()))
+        .collect::<Vec<_>>();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(files.iter().map(|p| p.as_path()), spec, &mut visitor).unwrap();
+    assert_eq!(visitor.0[9], Path::new("9"));
+    assert_eq!(visitor.0[10], Path::new("10"));
+}
+
+#[test]
+fn one_based() {
+    let nine = targets("{padded_idx1}", 9);
+    assert_eq!(nine.first().unwrap(), Path::new("1"));
+    assert_eq!(nine.last().unwrap(), Path::new("9"));
+
+    let ten = targets("{padded_idx1}", 10);
+    assert_eq!(ten.first().unwrap(), Path::new("01"));
+    assert_eq!(ten.last().unwrap(), Path::new("10"));
+
+    assert_eq!(
+        targets("{idx1}-{padded_idx}", 10)[..2],
+        ["1-0", "2-1"].map(PathBuf::from)
+    );
+
+    for spec in ["{idx1}", "{padded_idx1}"] {
+        let spec = RenameSpec::new(spec).unwrap().with_start_at(usize::MAX);
+        assert_eq!(
+            spec.render(0, 1, Path::new("a")).unwrap(),
+            usize::MAX.to_string()
+        );
+    }
+}
+
+#[test]
+fn alphabetic() {
+    let alpha = |n, uppercase| {
+        let mut s = String::new();
+        write_alphabetic(&mut s, n, uppercase).unwrap();
+        s
+    };
+    assert_eq!(alpha(0, false), "a");
+    assert_eq!(alpha(25, false), "z");
+    assert_eq!(alpha(26, false), "aa");
+    assert_eq!(alpha(51, false), "az");
+    assert_eq!(alpha(52, false), "ba");
+    assert_eq!(alpha(701, false), "zz");
+    assert_eq!(alpha(702, false), "aaa");
+    assert_eq!(alpha(usize::MAX, false).len(), 14);
+    assert_eq!(alpha(27, true), "AB");
+
+    assert_eq!(
+        targets("fig-{alpha}", 27)[25..],
+        ["fig-z", "fig-aa"].map(PathBuf::from)
+    );
+    assert_eq!(
+        RenameSpec::new("{ALPHA}")
+            .unwrap()
+            .dynamic_content()
+            .map(DynamicRenameContent::name)
+            .collect::<Vec<_>>(),
+        ["ALPHA"]
+    );
+}
+
+#[test]
+fn roman() {
+    let roman = |n, uppercase| {
+        let mut s = String::new();
+        write_roman(&mut s, n, uppercase).unwrap();
+        s
+    };
+    assert_eq!(roman(1, true), "I");
+    assert_eq!(roman(4, true), "IV");
+    assert_eq!(roman(9, true), "IX");
+    assert_eq!(roman(14, true), "XIV");
+    assert_eq!(roman(40, true), "XL");
+    assert_eq!(roman(90, true), "XC");
+    assert_eq!(roman(400, true), "CD");
+    assert_eq!(roman(1994, true), "MCMXCIV");
+    assert_eq!(roman(2024, false), "mmxxiv");
+    assert_eq!(roman(3999, true), "MMMCMXCIX");
+    assert_eq!(roman(5001, true), "MMMMMI");
+
+    let spec = RenameSpec::new("Disc {roman}").unwrap();
+    let from = Path::new("a.flac");
+    assert!(matches!(
+        spec.render(0, 3, from),
+        Err(RenderError::RomanZero)
+    ));
+    let spec = spec.with_start_at(1);
+    let discs = (0..3)
+        .map(|idx| spec.render(idx, 3, from).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(discs, ["Disc I", "Disc II", "Disc III"]);
+}
+
+#[test]
+fn modified_date() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("photo.jpg");
+    let file = fs::File::create(&path).unwrap();
+    // I.e., 2023-11-14T22:13:20Z.
+    let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    file.set_modified(mtime).unwrap();
+
+    let render = |spec| RenameSpec::new(spec).unwrap().render(0, 1, &path);
+    assert_eq!(render("{date}-{padded_idx}.jpg").unwrap(), "20231114-0.jpg");
+    assert_eq!(
+        render("{mtime:%Y-%m-%d_%H%M%S}").unwrap(),
+        "2023-11-14_221320"
+    );
+    assert_eq!(render("{mtime:100%%}").unwrap(), "100%");
+
+    assert!(RenameSpec::new("{mtime:%q}").is_err());
+    assert!(RenameSpec::new("{mtime:%}").is_err());
+
+    let res = RenameSpec::new("{date}")
+        .unwrap()
+        .render(0, 1, &dir.path().join("missing"));
+    assert!(matches!(res, Err(RenderError::ReadModifiedTime { .. })));
+
+    let date = |secs| {
+        let mut date = String::new();
+        write_date(
+            &mut date,
+            UNIX_EPOCH + std::time::Duration::from_secs(secs),
+            "%Y",
+        )
+        .map(|()| date)
+    };
+    assert_eq!(date(253_402_300_799).unwrap(), "9999");
+    assert!(date(253_402_300_800).is_err());
+}
+
+#[test]
+fn start_at() {
+    let spec = RenameSpec::new("photo-{padded_idx}.jpg")
+        .unwrap()
+        .with_start_at(1);
+    let files = (0..10)
+        .map(|idx| PathBuf::from(idx.to_string()))
+        .collect::<Vec<_>>();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().map(|p| p.as_path()),
+        spec.clone(),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(visitor.0[0], Path::new("photo-01.jpg"));
+    assert_eq!(visitor.0[9], Path::new("photo-10.jpg"));
+    assert_eq!(spec.parse_index("photo-10.jpg".as_ref()), Some((9, 2)));
+    assert_eq!(spec.parse_index("photo-00.jpg".as_ref()), None);
+
+    // The last of 10 files starting at 95 is 104, which needs an extra digit.
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().map(|p| p.as_path()),
+        spec.with_start_at(95),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(visitor.0[0], Path::new("photo-095.jpg"));
+    assert_eq!(visitor.0[9], Path::new("photo-104.jpg"));
+}
+
+#[test]
+fn step() {
+    let spec = RenameSpec::new("frame-{padded_idx}.png")
+        .unwrap()
+        .with_step(NonZeroUsize::new(2).unwrap());
+    let files = (0..6)
+        .map(|idx| PathBuf::from(idx.to_string()))
+        .collect::<Vec<_>>();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().map(|p| p.as_path()),
+        spec.clone(),
+        &mut visitor,
+    )
+    .unwrap();
+    // The last index is 10, which needs an extra digit even though there are only 6 files.
+    assert_eq!(
+        visitor.0,
+        [
+            "frame-00.png",
+            "frame-02.png",
+            "frame-04.png",
+            "frame-06.png",
+            "frame-08.png",
+            "frame-10.png",
+        ]
+        .map(PathBuf::from)
+    );
+    assert_eq!(spec.parse_index("frame-10.png".as_ref()), Some((5, 2)));
+    assert_eq!(spec.parse_index("frame-03.png".as_ref()), None);
+
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().take(4).map(|p| p.as_path()),
+        spec.with_start_at(4)
+            .with_step(NonZeroUsize::new(2).unwrap()),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(visitor.0[0], Path::new("frame-04.png"));
+    assert_eq!(visitor.0[3], Path::new("frame-10.png"));
+}
+
+#[test]
+fn plan_pairs_sources_with_targets() {
+    let files = ["b.png", "a.png"].map(PathBuf::from);
+    let spec = RenameSpec::new("img-{padded_idx}.{ext}").unwrap();
+    assert_eq!(
+        plan(files.iter().map(|p| p.as_path()), spec).unwrap(),
+        [("b.png", "img-0.png"), ("a.png", "img-1.png")]
+            .map(|(from, to)| (PathBuf::from(from), PathBuf::from(to)))
+    );
+
+    let spec = RenameSpec::new("{group}").unwrap();
+    assert!(matches!(
+        plan(files.iter().map(|p| p.as_path()), spec),
+        Err(RenderError::NoGroup)
+    ));
+}
+
+#[test]
+fn render() {
+    let spec = RenameSpec::new("{padded_idx}-{name}.{ext}").unwrap();
+    let from = Path::new("dir/song.mp3");
+    assert_eq!(spec.render(0, 1, from).unwrap(), "0-song.mp3");
+    assert_eq!(spec.render(9, 10, from).unwrap(), "9-song.mp3");
+    assert_eq!(spec.render(3, 11, from).unwrap(), "03-song.mp3");
+
+    let spec = spec.with_start_at(1).with_min_padding(3);
+    assert_eq!(spec.render(0, 10, from).unwrap(), "001-song.mp3");
+    let spec = RenameSpec::new("{padded_idx}")
+        .unwrap()
+        .with_pad_width(Some(1));
+    assert_eq!(spec.render(20, 21, from).unwrap(), "20");
+
+    assert!(matches!(
+        RenameSpec::new("{group}").unwrap().render(0, 1, from),
+        Err(RenderError::NoGroup)
+    ));
 }
 
 #[test]
 fn correct_padding() {
-    todo!()
+    assert!(RenameSpec::new("photo-{padded_idx}.jpg")
+        .unwrap()
+        .has_dynamic_content());
+    assert_eq!(
+        targets("photo-{padded_idx}.jpg", 3),
+        ["photo-0.jpg", "photo-1.jpg", "photo-2.jpg"].map(PathBuf::from)
+    );
+
+    let eleven = targets("photo-{padded_idx}.jpg", 11);
+    assert_eq!(eleven[0], Path::new("photo-00.jpg"));
+    assert_eq!(eleven[10], Path::new("photo-10.jpg"));
+}
+
+#[test]
+fn padding_follows_largest_index() {
+    for (count, width) in [
+        (1, 1),
+        (9, 1),
+        (10, 1),
+        (11, 2),
+        (99, 2),
+        (100, 2),
+        (101, 3),
+    ] {
+        let last = targets("{padded_idx}", count).pop().unwrap();
+        assert_eq!(
+            last,
+            Path::new(&format!("{:0width$}", count - 1)),
+            "{count} files"
+        );
+        assert_eq!(last.as_os_str().len(), width, "{count} files");
+    }
+}
+
+#[test]
+fn huge_size_hint() {
+    /// Yields a single file, while claiming that there may be as many as fit in a `usize`.
+    struct Boastful(Option<&'static Path>);
+
+    impl Iterator for Boastful {
+        type Item = &'static Path;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.take()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (0, Some(usize::MAX))
+        }
+    }
+
+    let spec = RenameSpec::new("{padded_idx}")
+        .unwrap()
+        .with_start_at(1)
+        .with_step(NonZeroUsize::new(2).unwrap());
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(Boastful(Some(Path::new("a"))), spec, &mut visitor).unwrap();
+    let width = usize::MAX.to_string().len();
+    assert_eq!(visitor.0, [PathBuf::from(format!("{:0width$}", 1))]);
+}
+
+#[test]
+fn explicit_count() {
+    /// Yields `remaining` files, while claiming that there may be none.
+    struct Modest(usize);
+
+    impl Iterator for Modest {
+        type Item = &'static Path;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0 = self.0.checked_sub(1)?;
+            Some(Path::new("a"))
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+
+    let spec = RenameSpec::new("{padded_idx}").unwrap();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(Modest(11), spec.clone(), &mut visitor).unwrap();
+    assert_eq!(visitor.0[10], Path::new("10"));
+    assert_eq!(visitor.0[0], Path::new("0"));
+
+    let mut visitor = CollectTargets(Vec::new());
+    zip_counted(Modest(11), 11, spec, &mut visitor).unwrap();
+    assert_eq!(visitor.0[0], Path::new("00"));
+    assert_eq!(visitor.0[10], Path::new("10"));
+}
+
+#[test]
+fn grouped_padding() {
+    let files = (0..11)
+        .map(|idx| (PathBuf::from(format!("cat-{idx}")), "cat"))
+        .chain([(PathBuf::from("dog"), "dog")])
+        .collect::<Vec<_>>();
+    let files = files
+        .iter()
+        .map(|(path, group)| (path.as_path(), *group))
+        .collect::<Vec<_>>();
+
+    let mut visitor = CollectTargets(Vec::new());
+    zip_grouped(
+        &files,
+        RenameSpec::new("{group}-{padded_idx}").unwrap(),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(visitor.0[0], Path::new("cat-00"));
+    assert_eq!(visitor.0[10], Path::new("cat-10"));
+    assert_eq!(visitor.0[11], Path::new("dog-0"));
+}
+
+#[test]
+fn radix_padding() {
+    let files = (0..37)
+        .map(|idx| PathBuf::from(idx.to_string()))
+        .collect::<Vec<_>>();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.iter().map(|p| p.as_path()),
+        RenameSpec::new("{padded_idx}")
+            .unwrap()
+            .with_radix(36)
+            .unwrap(),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(visitor.0[0], Path::new("00"));
+    assert_eq!(visitor.0[35], Path::new("0z"));
+    assert_eq!(visitor.0[36], Path::new("10"));
+
+    let hex = |count, uppercase| {
+        let files = (0..count)
+            .map(|idx| PathBuf::from(idx.to_string()))
+            .collect::<Vec<_>>();
+        let mut visitor = CollectTargets(Vec::new());
+        zip_single_side_scans(
+            files.iter().map(|p| p.as_path()),
+            RenameSpec::new("{padded_idx}")
+                .unwrap()
+                .with_radix(16)
+                .unwrap()
+                .with_uppercase_digits(uppercase),
+            &mut visitor,
+        )
+        .unwrap();
+        visitor.0
+    };
+    assert_eq!(hex(16, false)[15], Path::new("f"));
+    assert_eq!(hex(17, false)[..2], ["00", "01"].map(PathBuf::from));
+    assert_eq!(hex(17, false)[15..], ["0f", "10"].map(PathBuf::from));
+    assert_eq!(hex(256, false)[255], Path::new("ff"));
+    assert_eq!(hex(256, true)[255], Path::new("FF"));
+    assert_eq!(hex(257, false)[255..], ["0ff", "100"].map(PathBuf::from));
+
+    assert!(RenameSpec::new("").unwrap().with_radix(1).is_err());
+    assert!(RenameSpec::new("").unwrap().with_radix(37).is_err());
+}
+
+#[test]
+fn keep_extension() {
+    let files = ["a.jpeg", "b.png", "c"].map(PathBuf::from);
+    let render = |spec: &str| {
+        let mut visitor = CollectTargets(Vec::new());
+        zip_single_side_scans(
+            files.iter().map(|p| p.as_path()),
+            RenameSpec::new(spec).unwrap().with_kept_extension(true),
+            &mut visitor,
+        )
+        .unwrap();
+        visitor.0
+    };
+
+    assert_eq!(
+        render("shot-{padded_idx}"),
+        ["shot-0.jpeg", "shot-1.png", "shot-2"].map(PathBuf::from)
+    );
+    assert_eq!(
+        render("shot-{padded_idx}.gif"),
+        ["shot-0.gif", "shot-1.gif", "shot-2.gif"].map(PathBuf::from)
+    );
+    assert_eq!(
+        render("{padded_idx}.d/.hidden"),
+        ["0.d/.hidden.jpeg", "1.d/.hidden.png", "2.d/.hidden"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn seeded_random() {
+    let files = ["a", "b", "c"].map(PathBuf::from);
+    let render = |seed| {
+        let mut visitor = CollectTargets(Vec::new());
+        zip_single_side_scans(
+            files.iter().map(|p| p.as_path()),
+            RenameSpec::new("asset-{rand:8}.bin")
+                .unwrap()
+                .with_seed(seed),
+            &mut visitor,
+        )
+        .unwrap();
+        visitor.0
+    };
+
+    let targets = render(42);
+    assert_eq!(targets, render(42));
+    assert_ne!(targets, render(43));
+    for target in &targets {
+        let token = target
+            .to_str()
+            .unwrap()
+            .strip_prefix("asset-")
+            .and_then(|t| t.strip_suffix(".bin"))
+            .unwrap();
+        assert_eq!(token.len(), 8);
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()));
+    }
+    assert_ne!(targets[0], targets[1]);
+
+    assert!(RenameSpec::new("{rand:0}").is_err());
+    assert!(RenameSpec::new("{rand:}").is_err());
+    assert!(RenameSpec::new("{rand:x}").is_err());
+}
+
+#[test]
+fn escaped_braces() {
+    let literal = RenameSpec::new("name{{padded_idx}}.txt").unwrap();
+    assert!(!literal.has_dynamic_content());
+    assert_eq!(
+        targets("name{{padded_idx}}.txt", 2),
+        ["name{padded_idx}.txt"; 2].map(PathBuf::from)
+    );
+
+    assert!(RenameSpec::new("{{x}}-{padded_idx}")
+        .unwrap()
+        .has_dynamic_content());
+    assert_eq!(
+        targets("{{x}}-{padded_idx}", 2),
+        ["{x}-0", "{x}-1"].map(PathBuf::from)
+    );
+
+    assert!(RenameSpec::new("{{{padded_idx}}}")
+        .unwrap()
+        .has_dynamic_content());
+    assert_eq!(targets("{{{padded_idx}}}", 1), ["{0}"].map(PathBuf::from));
+    assert_eq!(
+        targets("set {{1}}-{padded_idx}", 2),
+        ["set {1}-0", "set {1}-1"].map(PathBuf::from)
+    );
+
+    let lone = RenameSpec::new("set 1}-{padded_idx}").unwrap_err();
+    assert_eq!(lone.idx, 5);
+    assert!(matches!(
+        lone.source,
+        RenameSpecParseErrorKind::UnmatchedClosingCurlyBrace
+    ));
+    assert!(RenameSpec::new("{padded_idx}}").is_err());
+}
+
+#[test]
+fn disabled_placeholders() {
+    let disabled = ["rand", "group"];
+    assert!(RenameSpec::new_restricted("{rand:4}", &disabled).is_err());
+    assert!(RenameSpec::new_restricted("x-{group}", &disabled).is_err());
+    assert!(RenameSpec::new_restricted("{padded_idx}-{{rand:4}}", &disabled).is_ok());
+    assert!(RenameSpec::new_restricted("{rand:4}", &["padded_idx"]).is_ok());
+
+    for name in DynamicRenameContent::names() {
+        assert!(!name.contains(':'));
+    }
+}
+
+#[test]
+fn parse_index() {
+    let spec = RenameSpec::new("img-{padded_idx}.jpg").unwrap();
+    assert_eq!(spec.parse_index("img-003.jpg".as_ref()), Some((3, 3)));
+    assert_eq!(spec.parse_index("img-3.jpg".as_ref()), Some((3, 1)));
+    assert_eq!(spec.parse_index("img-.jpg".as_ref()), None);
+    assert_eq!(spec.parse_index("img-3.png".as_ref()), None);
+
+    let spec = RenameSpec::new("{padded_idx}/{padded_idx}").unwrap();
+    assert_eq!(spec.parse_index("2/2".as_ref()), Some((2, 1)));
+    assert_eq!(spec.parse_index("2/3".as_ref()), None);
+
+    let spec = RenameSpec::new("{group}-{padded_idx}").unwrap();
+    assert_eq!(spec.parse_index("a-1".as_ref()), None);
+}
+
+#[test]
+fn max_path_len() {
+    let spec = RenameSpec::new("out/{padded_idx}-{name}")
+        .unwrap()
+        .with_kept_extension(true)
+        .with_max_path_len(Some(16));
+    let render = |from: &str| spec.render(0, 1, Path::new(from)).map(PathBuf::from);
+    assert_eq!(render("short.txt").unwrap(), Path::new("out/0-short.txt"));
+    let trimmed = render("a-very-long-name.txt").unwrap();
+    assert_eq!(trimmed, Path::new("out/0-a-v~me.txt"));
+    assert_eq!(trimmed.as_os_str().len(), 16);
+    assert!(matches!(
+        render("ab.a-very-long-extension"),
+        Err(RenderError::PathTooLong { .. })
+    ));
+}
+
+#[cfg(feature = "id3v1-tags")]
+#[test]
+fn id3v1_tags() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("01.mp3");
+    std::fs::write(&path, id3v1_tags::id3v1("What? / Why", "Band", 1)).unwrap();
+    let untagged = dir.path().join("02.mp3");
+    std::fs::write(&untagged, "").unwrap();
+
+    let spec = RenameSpec::new("{tag:artist} - {tag:title}").unwrap();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans([path.as_path()].into_iter(), spec.clone(), &mut visitor).unwrap();
+    assert_eq!(visitor.0, [PathBuf::from("Band - What_ _ Why")]);
+
+    let res = spec.render(0, 1, &untagged);
+    assert!(matches!(res, Err(RenderError::MissingTag { .. })));
+
+    let mut visitor = CollectTargets(Vec::new());
+    let spec = spec.with_missing_tag_fallback(Some("Unknown".into()));
+    zip_single_side_scans([untagged.as_path()].into_iter(), spec, &mut visitor).unwrap();
+    assert_eq!(visitor.0, [PathBuf::from("Unknown - Unknown")]);
+
+    // Titles that would be sanitized away are treated as missing.
+    let dots = dir.path().join("03.mp3");
+    std::fs::write(&dots, id3v1_tags::id3v1("...", "Band", 3)).unwrap();
+    let spec = RenameSpec::new("{tag:title}").unwrap();
+    assert!(matches!(
+        spec.render(0, 1, &dots),
+        Err(RenderError::MissingTag { .. })
+    ));
+    let spec = spec.with_missing_tag_fallback(Some("Unknown".into()));
+    assert_eq!(spec.render(0, 1, &dots).unwrap(), "Unknown");
+
+    assert!(RenameSpec::new("{tag:bogus}").is_err());
+}
+
+#[test]
+fn empty_names() {
+    let spec = RenameSpec::new("{ext}").unwrap();
+    assert_eq!(spec.render(0, 1, Path::new("a.jpg")).unwrap(), "jpg");
+    assert!(matches!(
+        spec.render(0, 1, Path::new("a")),
+        Err(RenderError::NoFileName { path }) if path.is_empty()
+    ));
+
+    for spec in ["sub/..", "sub/{ext}"] {
+        let spec = RenameSpec::new(spec).unwrap();
+        assert!(matches!(
+            spec.render(0, 1, Path::new("a")),
+            Err(RenderError::NoFileName { .. })
+        ));
+    }
+}
+
+#[test]
+fn file_name_checks() {
+    for name in ["a.jpg", "photo 1", "CONSOLE.txt", "nul-1", "日本.png"] {
+        check_file_name(name, true).unwrap();
+    }
+    assert_eq!(check_file_name("a:b?", false).is_ok(), !cfg!(windows));
+
+    for (name, c) in [
+        ("a:b", ':'),
+        ("a*", '*'),
+        ("<a>", '<'),
+        ("a|b", '|'),
+        ("a\tb", '\t'),
+    ] {
+        assert!(matches!(
+            check_file_name(name, true),
+            Err(FileNameError::IllegalChar { c: found }) if found == c
+        ));
+    }
+    assert!(matches!(
+        check_file_name("a\0b", false),
+        Err(FileNameError::IllegalChar { c: '\0' })
+    ));
+
+    for name in ["a.", "a ", "a.jpg."] {
+        assert!(matches!(
+            check_file_name(name, true),
+            Err(FileNameError::TrailingDotOrSpace)
+        ));
+    }
+
+    for name in ["CON", "nul", "Com1.txt", "lpt9.tar.gz", "aux .jpg"] {
+        assert!(matches!(
+            check_file_name(name, true),
+            Err(FileNameError::ReservedName { .. })
+        ));
+    }
+}
+
+#[test]
+fn resetting() {
+    let files = (0..25)
+        .map(|idx| PathBuf::from(format!("file-{idx}")))
+        .collect::<Vec<_>>();
+    let mut visitor = CollectTargets(Vec::new());
+    zip_resetting(
+        files.iter().map(|p| p.as_path()),
+        NonZeroUsize::new(12).unwrap(),
+        RenameSpec::new("{padded_idx}").unwrap(),
+        &mut visitor,
+    )
+    .unwrap();
+    let expected = (0..12)
+        .chain(0..12)
+        .chain(0..1)
+        .map(|idx| PathBuf::from(format!("{idx:02}")))
+        .collect::<Vec<_>>();
+    assert_eq!(visitor.0, expected);
+}
+
+#[test]
+fn dir_index() {
+    let files = ["a/x", "b/x", "a/y", "b/y", "b/z"].map(Path::new);
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(
+        files.into_iter(),
+        RenameSpec::new("{idx}-{dir_idx}").unwrap(),
+        &mut visitor,
+    )
+    .unwrap();
+    assert_eq!(
+        visitor.0,
+        ["0-0", "1-0", "2-1", "3-1", "4-2"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn captures() {
+    let pattern = Regex::new(r"^IMG_(\d{8})_(\w+)?").unwrap();
+    let spec = RenameSpec::new("{cap1}-{padded_idx}{cap2}.jpg")
+        .unwrap()
+        .with_capture_pattern(pattern.clone())
+        .unwrap();
+    assert_eq!(
+        spec.render(3, 10, Path::new("dcim/IMG_20230101_foo.jpg"))
+            .unwrap(),
+        "20230101-3foo.jpg"
+    );
+    assert_eq!(
+        spec.render(3, 10, Path::new("IMG_20230101_.jpg")).unwrap(),
+        "20230101-3.jpg"
+    );
+    assert!(matches!(
+        spec.render(0, 1, Path::new("IMG_2023.jpg")),
+        Err(RenderError::Unmatched { .. })
+    ));
+
+    let spec = RenameSpec::new("{cap3}").unwrap();
+    assert!(matches!(
+        spec.render(0, 1, Path::new("IMG_20230101_foo.jpg")),
+        Err(RenderError::NoCapturePattern)
+    ));
+    assert!(spec.with_capture_pattern(pattern).is_err());
+    assert!(RenameSpec::new("{cap0}").is_err());
+    assert!(RenameSpec::new("{cap+1}").is_err());
+}
+
+#[test]
+fn custom_content() {
+    let remaining =
+        CustomContent::new("remaining", |ctx| (ctx.count() - ctx.idx() - 1).to_string());
+    let shadowed = CustomContent::new("idx", |_ctx| "custom".to_owned());
+    let spec = RenameSpec::new_with_custom("{idx}-{remaining}.txt", &[remaining.clone(), shadowed])
+        .unwrap();
+    assert!(matches!(
+        spec.dynamic_content().nth(1),
+        Some(DynamicRenameContent::Custom(custom)) if custom.name() == "remaining"
+    ));
+
+    let files = ["a", "b", "c"].map(Path::new);
+    let mut visitor = CollectTargets(Vec::new());
+    zip_single_side_scans(files.into_iter(), spec, &mut visitor).unwrap();
+    assert_eq!(
+        visitor.0,
+        ["0-2.txt", "1-1.txt", "2-0.txt"].map(PathBuf::from)
+    );
+
+    assert!(RenameSpec::new("{remaining}").is_err());
+    assert!(matches!(
+        "padded_idx".parse(),
+        Ok(DynamicRenameContent::PaddedInteger)
+    ));
+    assert!("remaining".parse::<DynamicRenameContent>().is_err());
+}
+
+#[test]
+fn total() {
+    let pages = targets("page-{padded_idx1}-of-{total}", 123);
+    assert_eq!(pages[0], Path::new("page-001-of-123"));
+    assert_eq!(pages[122], Path::new("page-123-of-123"));
+
+    // Only as wide as the largest index, which is one less than the total.
+    let padded = targets("{padded_idx}/{padded_total}", 10);
+    assert_eq!(padded[0], Path::new("0/10"));
+    let padded = targets("{padded_idx}/{padded_total}", 11);
+    assert_eq!(padded[0], Path::new("00/11"));
+
+    let spec = RenameSpec::new("{total}").unwrap().with_pad_width(Some(4));
+    assert_eq!(spec.render(0, 7, Path::new("a")).unwrap(), "7");
+    let spec = RenameSpec::new("{padded_total}")
+        .unwrap()
+        .with_pad_width(Some(4));
+    assert_eq!(spec.render(0, 7, Path::new("a")).unwrap(), "0007");
+}
+
+#[test]
+fn case_modifiers() {
+    let render = |spec: &str, from: &str| {
+        RenameSpec::new(spec)
+            .unwrap()
+            .render(0, 1, Path::new(from))
+            .unwrap()
+    };
+    assert_eq!(
+        render("{name:lower}-{idx}.{ext}", "IMG Big_one.JPG"),
+        "img big_one-0.JPG"
+    );
+    assert_eq!(
+        render("{name:upper}.{ext:lower}", "Straße.JPG"),
+        "STRASSE.jpg"
+    );
+    assert_eq!(
+        render("{name:title}", "the QUICK-brown_fox2go"),
+        "The Quick-Brown_Fox2go"
+    );
+    assert_eq!(render("{name}.{ext:upper}", "README"), "README");
+
+    let spec = RenameSpec::new("{group:upper}-{tail:lower}").unwrap();
+    assert!(spec.dynamic_content().all(DynamicRenameContent::is_text));
+    let spec = RenameSpec::new("{mtime:%H:%M}").unwrap();
+    assert!(matches!(
+        spec.dynamic_content().next(),
+        Some(DynamicRenameContent::ModifiedDate { format: Some(format) }) if format == "%H:%M"
+    ));
+
+    for spec in [
+        "{idx:lower}",
+        "{padded_idx:upper}",
+        "{alpha:upper}",
+        "{date:title}",
+    ] {
+        assert!(matches!(
+            RenameSpec::new(spec),
+            Err(RenameSpecParseError {
+                source: RenameSpecParseErrorKind::CaseOfNonText { .. },
+                ..
+            })
+        ));
+    }
+    assert!(RenameSpec::new("{name:sideways}").is_err());
+    assert!(RenameSpec::new("{nam:lower}").is_err());
+
+    let shout = CustomContent::new("shout", |_ctx| "Hey".to_owned());
+    let spec = RenameSpec::new_with_custom("{shout:upper}", &[shout]).unwrap();
+    assert_eq!(spec.render(0, 1, Path::new("a")).unwrap(), "HEY");
+}
+
+#[cfg(feature = "id3v1-tags")]
+#[test]
+fn case_modifiers_of_tags() {
+    let spec = RenameSpec::new("{tag:title}").unwrap();
+    assert!(matches!(
+        spec.dynamic_content().next(),
+        Some(DynamicRenameContent::Tag {
+            tag: Id3v1Tag::Title
+        })
+    ));
+    assert!(RenameSpec::new("{tag:title:upper}").is_ok());
 }
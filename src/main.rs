@@ -1,159 +1,2902 @@
-use self::cli::MainArgs;
-use clap::Parser;
+use self::{
+    cli::MainArgs,
+    exec::{
+        confirm_proceed, is_noop, ExecOptions, ExecOutcome, LinePrompt, RealFs, RenameSummary,
+        TransferMode, ZipVisitor,
+    },
+    plan::PlanCollector,
+    progress::{Stderr, PROGRESS},
+};
+use clap::{Parser, ValueEnum};
 use color_eyre::eyre::{self, bail, WrapErr};
-use rename_seq::{zip_single_side_scans, RenameSpec, Visitor};
+use regex::Regex;
+use rename_seq::{
+    zip_resetting, BuiltinTransform, DynamicRenameContent, IndexField, Order, RenameSpec, Transform,
+};
 use std::{
-    convert::Infallible,
+    collections::HashSet,
     fs,
-    ops::ControlFlow,
-    path::{Path, PathBuf},
+    io::{self, IsTerminal, Write},
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
 };
 
 mod cli;
+mod exec;
+mod hash;
+mod plan;
+mod progress;
+mod suggest;
 
 fn main() -> eyre::Result<()> {
-    tracing_subscriber::fmt::init();
+    // Keep `stdout` free for output that scripts might want to consume.
+    tracing_subscriber::fmt().with_writer(Stderr).init();
+
+    let args = MainArgs::parse();
+
+    color_eyre::install().unwrap();
+
+    run(args, &mut io::stdout().lock())
+}
 
+fn run(args: MainArgs, stdout: &mut dyn Write) -> eyre::Result<()> {
     let MainArgs {
+        capabilities,
         go,
+        dry_run_to,
+        interactive,
+        confirm_each,
+        no_progress,
+        format,
+        report,
         allow_warnings,
+        strict_spec,
+        strict_names,
+        allow_escape,
+        count_only,
+        table,
+        table_header,
+        table_ruler,
+        suggest_spec,
+        detect_duplicates,
+        skip_duplicates,
+        dedup_targets_by_hash,
+        delete_duplicates,
         order,
+        numbering_base,
+        uppercase_digits,
+        start_at,
+        step,
+        no_sort,
+        reverse,
+        limit,
+        keep_ext,
+        seed,
+        index_field_only,
+        mirror,
+        names_from,
+        allow_mismatch,
+        fill_gaps,
+        stable_across_extensions,
+        sidecar_index,
+        reuse_freed_indices,
+        max_path_len,
+        counter_file,
+        counter_padding,
+        pad_width,
+        width_lock,
+        disable_placeholder,
+        transform,
+        case,
+        resolve_conflicts_interactively,
+        collision_strategy,
+        on_flatten_collision,
+        verify_copy,
+        copy,
+        link,
+        clobber,
+        no_clobber: _,
+        undo_log,
+        on_error,
+        fail_fast,
+        op_timeout,
+        mkdirs,
+        out_dir,
+        safe,
+        jobs,
+        tmp_suffix,
+        filter_ext,
+        exclude_ext,
+        match_pattern,
+        skip_unmatched,
+        reset_every,
+        reset_per_dir,
+        group_by,
+        #[cfg(feature = "id3v1-tags")]
+        missing_tag,
+        #[cfg(feature = "image")]
+        skip_non_images,
         rename_spec: rename_spec_str,
-        selection,
-    } = MainArgs::parse();
+        command,
+    } = args;
 
-    color_eyre::install().unwrap();
+    if capabilities {
+        serde_json::to_writer_pretty(&mut *stdout, &self::capabilities())
+            .wrap_err("failed to write capabilities")?;
+        writeln!(stdout).wrap_err("failed to write capabilities")?;
+        return Ok(());
+    }
+    let Some(command) = command else {
+        bail!("expected a selection subcommand (e.g., `from-files`)");
+    };
 
-    let rename_spec = RenameSpec::new(&rename_spec_str).wrap_err("failed to parse rename spec")?;
+    let mut sidecar = None;
+    let mut new_width_lock = None;
+    let mut counter = None;
+    let mut duplicates_to_delete = Vec::new();
+    // Relative targets are put under `--out-dir` once rendered, so leave room for it.
+    let rendered_max_path_len = max_path_len.map(|max| match &out_dir {
+        Some(dir)
+            if rename_spec_str
+                .as_deref()
+                .is_some_and(|spec| !Path::new(spec).is_absolute()) =>
+        {
+            max.saturating_sub(dir.join("").as_os_str().to_string_lossy().chars().count())
+        }
+        _ => max,
+    });
+    let mut plan = match command {
+        cli::Command::Apply { plan: path } => {
+            let plan = fs::File::open(&path)
+                .map_err(eyre::Report::from)
+                .and_then(|file| PlanCollector::load(io::BufReader::new(file)))
+                .wrap_err_with(|| format!("failed to load plan from {path:?}"))?;
+            plan::check_applicable(plan.renames(), safe)
+                .wrap_err("plan can no longer be applied")?;
+            plan
+        }
+        cli::Command::ReverseSpec { original, files } => {
+            let Some(current) = &rename_spec_str else {
+                bail!("expected the rename spec that `files` were renamed with");
+            };
+            let current = RenameSpec::new(current)
+                .wrap_err("failed to parse rename spec")?
+                .with_radix(numbering_base)?;
+            let original = RenameSpec::new(&original)
+                .wrap_err("failed to parse original rename spec")?
+                .with_radix(numbering_base)?;
+            let files = files.iter().map(|p| p.as_path()).collect::<Vec<_>>();
+            plan::reverse_spec(&files, &current, original)?
+        }
+        cli::Command::Select(selection) => {
+            let reverse =
+                reverse || matches!(selection, cli::Selection::FromGlob { reverse: true, .. });
+            let selection = if no_sort {
+                if matches!(selection, cli::Selection::FromGlob { .. }) {
+                    tracing::warn!(
+                        "`--no-sort` uses files in the order they are discovered, which may vary between \
+                        runs or platforms"
+                    );
+                }
+                selection.without_sorting()
+            } else {
+                selection
+            };
+            let mut files = selection.files()?;
+            if !filter_ext.is_empty() || !exclude_ext.is_empty() {
+                let ext_of = |path: &Path| {
+                    path.extension()
+                        .map(|ext| ext.to_string_lossy().to_lowercase())
+                };
+                files.retain(|path| {
+                    let ext = ext_of(path);
+                    let included = filter_ext.is_empty()
+                        || ext.as_ref().is_some_and(|ext| filter_ext.contains(ext));
+                    let excluded = ext.as_ref().is_some_and(|ext| exclude_ext.contains(ext));
+                    included && !excluded
+                });
+            }
+
+            if suggest_spec {
+                let files = files.iter().map(|p| p.as_path()).collect::<Vec<_>>();
+                match suggest::suggest_spec(&files) {
+                    Some(spec) => {
+                        writeln!(stdout, "{spec}").wrap_err("failed to write suggestion")?
+                    }
+                    None => tracing::warn!(
+                        "selected file names share no structure to suggest a spec from"
+                    ),
+                }
+                return Ok(());
+            }
+
+            let rename_spec = match &rename_spec_str {
+                _ if index_field_only.is_some() || mirror.is_some() || names_from.is_some() => None,
+                // Transforms alone rewrite files' current names.
+                None if !transform.is_empty() => None,
+                None => bail!("expected a rename spec"),
+                Some(rename_spec_str) => {
+                    let disabled = disable_placeholder
+                        .iter()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>();
+                    let rename_spec = RenameSpec::new_restricted(rename_spec_str, &disabled)
+                        .wrap_err("failed to parse rename spec")?
+                        .with_radix(numbering_base)?
+                        .with_uppercase_digits(uppercase_digits)
+                        .with_kept_extension(keep_ext)
+                        .with_start_at(start_at)
+                        .with_step(step)
+                        .with_pad_width(pad_width)
+                        .with_max_path_len(rendered_max_path_len);
+                    #[cfg(feature = "id3v1-tags")]
+                    let rename_spec = rename_spec.with_missing_tag_fallback(missing_tag);
+                    let rename_spec = match seed {
+                        Some(seed) => rename_spec.with_seed(seed),
+                        None => rename_spec,
+                    };
+                    let rename_spec = match &match_pattern {
+                        Some(pattern) => rename_spec.with_capture_pattern(pattern.clone())?,
+                        None if rename_spec
+                            .dynamic_content()
+                            .any(|c| matches!(c, DynamicRenameContent::Capture { .. })) =>
+                        {
+                            bail!("`{{capN}}` was used in the rename spec, but `--match` was not specified")
+                        }
+                        None => rename_spec,
+                    };
+
+                    if strict_spec && files.len() > 1 {
+                        if !rename_spec.has_dynamic_content() {
+                            bail!(
+                                "rename spec {rename_spec_str:?} does not have any dynamic \
+                                content, so all {} selected files would be renamed to the same \
+                                name",
+                                files.len()
+                            );
+                        }
+                        let has_index = rename_spec.dynamic_content().any(|c| {
+                            matches!(
+                                c,
+                                DynamicRenameContent::PaddedInteger
+                                    | DynamicRenameContent::Integer
+                                    | DynamicRenameContent::OneBasedPaddedInteger
+                                    | DynamicRenameContent::OneBasedInteger
+                                    | DynamicRenameContent::Alphabetic { .. }
+                                    | DynamicRenameContent::Roman { .. }
+                                    | DynamicRenameContent::DirIndex { .. }
+                                    | DynamicRenameContent::GlobalIndex
+                            )
+                        });
+                        if !has_index {
+                            bail!(
+                                "rename spec {rename_spec_str:?} does not use `{{padded_idx}}` or `{{idx}}`, \
+                                so selected files may be renamed to the same name"
+                            );
+                        }
+                    }
+                    if !rename_spec.has_dynamic_content() {
+                        tracing::warn!("rename spec {rename_spec_str:?} does not have any dynamic content; this probably isn't what you want!");
+                        if !allow_warnings {
+                            bail!("warning(s) emitted, and `--allow-warnings` was not specified; bailing");
+                        }
+                    }
+                    Some(rename_spec)
+                }
+            };
+            let uses_content = |f: fn(&DynamicRenameContent) -> bool| {
+                rename_spec
+                    .as_ref()
+                    .is_some_and(|spec| spec.dynamic_content().any(f))
+            };
+
+            if detect_duplicates {
+                let groups = hash::duplicate_groups(files.iter().map(|p| p.as_path()))
+                    .wrap_err("failed to detect duplicates")?;
+                for group in &groups {
+                    for path in group {
+                        writeln!(stdout, "{}", path.display())
+                            .wrap_err("failed to write duplicates")?;
+                    }
+                    writeln!(stdout).wrap_err("failed to write duplicates")?;
+                }
+                if !skip_duplicates {
+                    return Ok(());
+                }
+                let skipped = groups
+                    .iter()
+                    .flat_map(|group| &group[1..])
+                    .collect::<HashSet<_>>();
+                tracing::info!("skipping {} duplicate file(s)", skipped.len());
+                files.retain(|path| !skipped.contains(path));
+            }
+
+            if dedup_targets_by_hash {
+                let groups = hash::duplicate_groups(files.iter().map(|p| p.as_path()))
+                    .wrap_err("failed to detect duplicates")?;
+                let extras = groups
+                    .into_iter()
+                    .flat_map(|group| group.into_iter().skip(1))
+                    .collect::<HashSet<_>>();
+                tracing::info!(
+                    "renaming only the first of each group of identical files, leaving out {} \
+                    duplicate(s)",
+                    extras.len()
+                );
+                files.retain(|path| !extras.contains(path));
+                if delete_duplicates {
+                    duplicates_to_delete.extend(extras);
+                }
+            }
+
+            #[cfg(feature = "image")]
+            if skip_non_images
+                && uses_content(|c| {
+                    matches!(
+                        c,
+                        DynamicRenameContent::ImageWidth | DynamicRenameContent::ImageHeight
+                    )
+                })
+            {
+                files.retain(|path| match rename_seq::read_image_dimensions(path) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        tracing::info!("skipping {path:?}, which is not a readable image: {e}");
+                        false
+                    }
+                });
+            }
+
+            if let (Some(pattern), true) = (&match_pattern, skip_unmatched) {
+                files.retain(|path| {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    let matched = pattern.is_match(&name);
+                    if !matched {
+                        tracing::info!("skipping {path:?}, whose name doesn't match `--match`");
+                    }
+                    matched
+                });
+            }
 
-    if !rename_spec.has_dynamic_content() {
-        tracing::warn!("rename spec {rename_spec_str:?} does not have any dynamic content; this probably isn't what you want!");
-        if !allow_warnings {
-            bail!("warning(s) emitted, and `--allow-warnings` was not specified; bailing");
+            if reverse {
+                files.reverse();
+            }
+            let mut files = order
+                .apply(files.iter(), seed)
+                .map(|p| p.as_path())
+                .collect::<Vec<_>>();
+            if let Some(limit) = limit.filter(|limit| limit.get() < files.len()) {
+                tracing::info!(
+                    "renaming only the first {limit} of {} selected file(s)",
+                    files.len()
+                );
+                files.truncate(limit.get());
+            }
+
+            let groups = match group_by {
+                Some(group_by) => Some(group_keys(&files, &group_by)?),
+                None if reset_per_dir => Some(
+                    files
+                        .iter()
+                        .map(|path| {
+                            let dir = path.parent().unwrap_or(Path::new(""));
+                            dir.to_string_lossy().into_owned()
+                        })
+                        .collect(),
+                ),
+                None if uses_content(|c| matches!(c, DynamicRenameContent::Group)) => {
+                    bail!(
+                        "`{{group}}` was used in the rename spec, but neither `--group-by` nor \
+                        `--reset-per-dir` was specified"
+                    )
+                }
+                None => None,
+            };
+            if uses_content(|c| matches!(c, DynamicRenameContent::DirIndex { .. }))
+                && (fill_gaps || sidecar_index.is_some())
+            {
+                bail!(
+                    "`{{dir_idx}}` numbers files by their position in their directories, so it \
+                    can't be used with `--fill-gaps` or `--sidecar-index`"
+                );
+            }
+
+            let uses_tail = uses_content(|c| matches!(c, DynamicRenameContent::Tail));
+            let uses_global_idx = uses_content(|c| matches!(c, DynamicRenameContent::GlobalIndex));
+            let rename_spec = match rename_spec {
+                Some(rename_spec) if uses_tail => {
+                    let stems = files
+                        .iter()
+                        .map(|path| path.file_stem().unwrap_or_default().to_string_lossy())
+                        .collect::<Vec<_>>();
+                    let field =
+                        IndexField::find(stems.iter().map(|stem| &**stem)).unwrap_or_default();
+                    Some(rename_spec.with_index_field(field))
+                }
+                rename_spec => rename_spec,
+            };
+            let rename_spec = match rename_spec {
+                Some(rename_spec) if uses_global_idx => {
+                    let Some(path) = &counter_file else {
+                        bail!("`{{global_idx}}` was used in the rename spec, but `--counter-file` was not specified");
+                    };
+                    let locked = plan::GlobalCounter::lock(path)
+                        .wrap_err_with(|| format!("failed to read counter file {path:?}"))?;
+                    let rename_spec =
+                        rename_spec.with_global_counter(locked.start, counter_padding);
+                    let files = files.iter().map(|f| f.to_path_buf()).collect::<Vec<_>>();
+                    counter = Some((path.clone(), locked, files));
+                    Some(rename_spec)
+                }
+                rename_spec => rename_spec,
+            };
+            let rename_spec = match (rename_spec, width_lock) {
+                (Some(rename_spec), Some(path)) => {
+                    let needed = rename_spec.padding_width(files.len());
+                    let lock = plan::WidthLock::load(&path)
+                        .wrap_err_with(|| format!("failed to load width lock {path:?}"))?;
+                    match lock {
+                        Some(lock) if lock.width < needed => bail!(
+                            "{} selected file(s) need indices of {needed} digit(s), but the width \
+                            lock {path:?} only allows {}",
+                            files.len(),
+                            lock.width,
+                        ),
+                        Some(lock) => Some(rename_spec.with_min_padding(lock.width)),
+                        None => {
+                            new_width_lock = Some((path, plan::WidthLock { width: needed }));
+                            Some(rename_spec)
+                        }
+                    }
+                }
+                (rename_spec, _) => rename_spec,
+            };
+
+            // Rename specs render whole paths, but other names are for the directories their
+            // sources are already in.
+            let renders_paths = rename_spec.is_some();
+            let mut plan = PlanCollector::default();
+            let renames = match (rename_spec, index_field_only, mirror) {
+                (Some(rename_spec), ..) if sidecar_index.is_some() => {
+                    let path = sidecar_index.unwrap();
+                    let mut index = plan::SidecarIndex::load(&path)
+                        .wrap_err_with(|| format!("failed to load sidecar index {path:?}"))?;
+                    plan::zip_with_sidecar(
+                        &files,
+                        rename_spec,
+                        &mut index,
+                        reuse_freed_indices,
+                        &mut plan,
+                    )?;
+                    sidecar = Some((path, index));
+                    Vec::new()
+                }
+                (Some(rename_spec), ..) if fill_gaps => {
+                    plan::zip_filling_gaps(&files, rename_spec, &mut plan)?;
+                    Vec::new()
+                }
+                (Some(rename_spec), ..) if stable_across_extensions => {
+                    plan::zip_by_stem(&files, rename_spec, &mut plan);
+                    Vec::new()
+                }
+                (Some(rename_spec), ..) if reset_every.is_some() => {
+                    let every = reset_every.unwrap();
+                    let Ok(()) =
+                        zip_resetting(files.iter().copied(), every, rename_spec, &mut plan);
+                    Vec::new()
+                }
+                (Some(rename_spec), ..) => {
+                    let Ok(()) = plan::zip(&files, groups.as_deref(), rename_spec, &mut plan);
+                    Vec::new()
+                }
+                (None, Some(width), _) => plan::repad_index_field(&files, width)?,
+                (None, None, Some(reference)) => {
+                    plan::mirror(&files, &reference, allow_mismatch)
+                        .wrap_err_with(|| format!("failed to mirror names in {reference:?}"))?
+                }
+                (None, None, None) if names_from.is_some() => {
+                    let manifest = names_from.as_deref().unwrap();
+                    plan::names_from(&files, manifest, allow_mismatch)
+                        .wrap_err_with(|| format!("failed to use names from {manifest:?}"))?
+                }
+                (None, None, None) => files
+                    .iter()
+                    .map(|&from| (from.to_owned(), from.to_owned()))
+                    .collect(),
+            };
+            plan.plan
+                .extend(renames.into_iter().map(|(from, to)| (from, Ok(to))));
+
+            let explicit_dirs = rename_spec_str
+                .as_ref()
+                .is_some_and(|spec| spec.contains(std::path::is_separator));
+            if out_dir.is_none() && !(renders_paths && explicit_dirs) {
+                let in_other_dirs = plan::targets_in_other_dirs(&plan, |from| {
+                    if renders_paths {
+                        Path::new("")
+                    } else {
+                        from.parent().unwrap_or(Path::new(""))
+                    }
+                });
+                for (from, to) in &in_other_dirs {
+                    tracing::warn!(
+                        "the name rendered for {from:?} contains a path separator, so it would be \
+                        moved to {to:?}; use `--out-dir`, or separators in the rename spec, to \
+                        move files into directories"
+                    );
+                }
+                if !in_other_dirs.is_empty() && !allow_warnings {
+                    bail!("warning(s) emitted, and `--allow-warnings` was not specified; bailing");
+                }
+            }
+
+            if let Some(dir) = &out_dir {
+                plan::move_into(&mut plan, dir);
+            }
+            plan
+        }
+    };
+
+    // `--case` is shorthand for a final transform.
+    let mut transforms = transform;
+    match case {
+        cli::Case::Lower => transforms.push(BuiltinTransform::Lowercase),
+        cli::Case::Upper => transforms.push(BuiltinTransform::Uppercase),
+        cli::Case::Preserve => (),
+    }
+    plan::apply_transforms(
+        &mut plan,
+        &transforms
+            .iter()
+            .map(|t| t as &dyn Transform)
+            .collect::<Vec<_>>(),
+    );
+
+    if !allow_escape {
+        let cwd = std::env::current_dir().wrap_err("failed to get current directory")?;
+        let escaping = plan
+            .renames()
+            .filter(|(_from, to)| plan::escapes_root(to, &cwd))
+            .collect::<Vec<_>>();
+        if !escaping.is_empty() {
+            bail!(
+                "refusing to move files outside of the current directory without `--allow-escape`: \
+                {escaping:?}"
+            );
         }
     }
 
-    let files = selection.files()?;
+    let mut illegal_names = false;
+    for (from, to) in plan.renames() {
+        // Existing directories are none of this run's business, but those it creates are.
+        let created_dirs = to
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists());
+        let names = to
+            .file_name()
+            .into_iter()
+            .chain(created_dirs.filter_map(Path::file_name))
+            .map(|name| name.to_string_lossy());
+        for name in names {
+            if let Err(e) = rename_seq::check_file_name(&name, strict_names) {
+                tracing::warn!("target {to:?} of {from:?} has an invalid name: {e}");
+                illegal_names = true;
+            }
+        }
+    }
+    if illegal_names && !allow_warnings {
+        bail!("warning(s) emitted, and `--allow-warnings` was not specified; bailing");
+    }
 
-    let dry_run = !go;
+    let mut overwritten = HashSet::new();
+    if resolve_conflicts_interactively {
+        if io::stdin().is_terminal() {
+            let mut prompt = LinePrompt {
+                input: io::stdin().lock(),
+                output: io::stderr(),
+            };
+            overwritten = plan::resolve_conflicts(&mut plan, &mut prompt)
+                .wrap_err("failed to resolve conflicts")?;
+        } else {
+            tracing::warn!(
+                "not resolving conflicts interactively, since standard input is not a terminal"
+            );
+        }
+    }
+    match on_flatten_collision {
+        cli::OnFlattenCollision::Error => (),
+        cli::OnFlattenCollision::Number => plan::number_collisions(&mut plan),
+        cli::OnFlattenCollision::Subdir => plan::keep_parent_dirs_of_collisions(&mut plan),
+    }
+    if let cli::CollisionStrategy::Timestamp = collision_strategy {
+        plan::append_timestamps(&mut plan).wrap_err("failed to disambiguate collisions")?;
+    }
+    if let Some(max) = max_path_len {
+        plan::fail_long_targets(&mut plan, max);
+    }
+    let collisions = plan::target_collisions(plan.renames());
+    if !collisions.is_empty() {
+        for (to, froms) in &collisions {
+            tracing::error!("multiple files would be renamed to {to:?}: {froms:?}");
+        }
+        let listing = collisions
+            .iter()
+            .map(|(to, froms)| format!("{to:?} (from {froms:?})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        bail!(
+            "refusing to rename, since {} target(s) would be shared by multiple files: {listing}",
+            collisions.len()
+        );
+    }
+
+    let clobbered = plan::clobbered(&plan, &overwritten)
+        .wrap_err("failed to check for existing files at targets")?;
+    if !clobbered.is_empty() {
+        if !clobber {
+            let listing = clobbered
+                .iter()
+                .map(|(from, to)| format!("{to:?} (from {from:?})"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!(
+                "refusing to overwrite {} existing file(s) that weren't selected without \
+                `--clobber`: {listing}",
+                clobbered.len()
+            );
+        }
+        for (from, to) in &clobbered {
+            tracing::warn!("renaming {from:?} will overwrite {to:?}, which wasn't selected");
+        }
+    }
+    let conflicts = clobbered.len();
+
+    let mode = match (copy, link) {
+        (_, Some(cli::Link::Hard)) => TransferMode::HardLink,
+        (_, Some(cli::Link::Symlink)) => TransferMode::Symlink,
+        (true, None) => TransferMode::Copy,
+        (false, None) => TransferMode::Rename,
+    };
+    if mode != TransferMode::Rename {
+        let sources = plan
+            .renames()
+            .map(|(from, _to)| from)
+            .collect::<HashSet<_>>();
+        let overwritten = plan
+            .renames()
+            .filter(|(from, to)| !is_noop(from, to) && sources.contains(to))
+            .map(|(_from, to)| to)
+            .collect::<Vec<_>>();
+        if !overwritten.is_empty() {
+            bail!(
+                "refusing to replace selected files, which would be lost, with copies or links: \
+                {overwritten:?}"
+            );
+        }
+    }
+
+    if let Some(path) = dry_run_to {
+        fs::File::create(&path)
+            .map_err(eyre::Report::from)
+            .and_then(|file| plan.save(io::BufWriter::new(file)))
+            .wrap_err_with(|| format!("failed to save plan to {path:?}"))?;
+        tracing::info!("saved plan to {path:?}; use `apply` to perform it");
+        return Ok(());
+    }
+
+    if count_only {
+        let mut count = 0;
+        for (from, to) in plan.plan {
+            let to = to.wrap_err_with(|| format!("failed to determine target path of {from:?}"))?;
+            if !is_noop(&from, &to) {
+                count += 1;
+            }
+        }
+        writeln!(stdout, "{count}").wrap_err("failed to write count")?;
+        return Ok(());
+    }
+
+    if table {
+        plan.write_table(table_header, table_ruler, false, &mut *stdout)
+            .wrap_err("failed to write table")?;
+        return Ok(());
+    }
+
+    if let cli::OutputFormat::Json | cli::OutputFormat::Table = format {
+        if go {
+            let format = format.to_possible_value().unwrap();
+            bail!(
+                "`--format {}` only prints the plan, so it can't be used with `--go`",
+                format.get_name()
+            );
+        }
+        match format {
+            cli::OutputFormat::Json => plan.write_json(&mut *stdout),
+            _ => plan.write_table(false, None, true, &mut *stdout),
+        }
+        .wrap_err("failed to write plan")?;
+        return Ok(());
+    }
+
+    if interactive {
+        if !io::stdin().is_terminal() {
+            bail!("`--interactive` requires standard input to be a terminal");
+        }
+        plan.write_table(table_header, table_ruler, false, io::stderr())
+            .wrap_err("failed to write table")?;
+        let proceed = confirm_proceed(io::stdin().lock(), io::stderr())
+            .wrap_err("failed to ask for confirmation")?;
+        if !proceed {
+            bail!("not confirmed; aborting without renaming any files");
+        }
+    }
+
+    let dry_run = !(go || interactive);
     if dry_run {
         tracing::info!("doing a dry run of all moves");
     }
 
-    let files_iter: Box<dyn Iterator<Item = &Path>> = match order {
-        cli::Order::Sequential => Box::new(files.iter().map(|p| p.as_ref())),
-        cli::Order::SingleSidedScans => Box::new(ZigZag::new(files.iter()).map(|p| p.as_ref())),
+    if confirm_each && !io::stdin().is_terminal() {
+        bail!("`--confirm-each` requires standard input to be a terminal");
+    }
+
+    let on_error = if fail_fast {
+        cli::OnError::Abort
+    } else {
+        on_error
+    };
+    let opts = ExecOptions {
+        dry_run,
+        mode,
+        on_error,
+        op_timeout,
+        mkdirs: mkdirs || out_dir.is_some(),
+        verify_copy,
+        two_phase: safe,
+        tmp_suffix,
+    };
+    let total = plan.plan.len();
+    // These keep their `{global_idx}` as much as renamed files do.
+    let already_named = plan
+        .renames()
+        .filter(|(from, to)| is_noop(from, to))
+        .map(|(from, _to)| from.to_owned())
+        .collect::<HashSet<_>>();
+    // Files that already have their targets are never counted as done.
+    let to_do = plan
+        .plan
+        .iter()
+        .filter(|(from, to)| to.as_ref().map_or(true, |to| !is_noop(from, to)))
+        .count();
+    let show_progress = !dry_run && !no_progress && !confirm_each && to_do >= progress::MIN_FILES;
+    if show_progress && io::stderr().is_terminal() {
+        PROGRESS.start(to_do);
+    }
+    let (outcome, res) = if jobs.get() > 1 {
+        exec::execute_parallel(plan, jobs, &opts, Arc::new(RealFs))
+    } else {
+        let mut visitor = ZipVisitor::new(opts, Arc::new(RealFs));
+        if confirm_each {
+            visitor = visitor.with_prompt(Box::new(LinePrompt {
+                input: io::stdin().lock(),
+                output: io::stderr(),
+            }));
+        }
+        let res = plan.replay(&mut visitor).and_then(|()| visitor.finish());
+        (visitor.into_outcome(), res)
     };
+    PROGRESS.finish();
+    let ExecOutcome {
+        summary,
+        failures,
+        completed,
+    } = outcome;
+    let res = res.wrap_err("failed to execute zipping operation");
+    // Files have been renamed, so everything below is attempted even if something else fails,
+    // starting with what's needed to put them back.
+    let mut bookkeeping_errors = Vec::new();
+    if let Some(path) = undo_log.filter(|_| !dry_run) {
+        let undo = PlanCollector {
+            plan: completed
+                .iter()
+                .rev()
+                .map(|(from, to)| (to.clone(), Ok(from.clone())))
+                .collect(),
+        };
+        let res = fs::File::create(&path)
+            .map_err(eyre::Report::from)
+            .and_then(|file| undo.save(io::BufWriter::new(file)))
+            .wrap_err_with(|| format!("failed to save undo log to {path:?}"));
+        bookkeeping_errors.extend(res.err());
+    }
+    if let Some(path) = report {
+        let res = write_report(&path, summary, &failures, dry_run)
+            .wrap_err_with(|| format!("failed to write report to {path:?}"));
+        bookkeeping_errors.extend(res.err());
+    }
+    // Even if renaming was aborted, so that completed renames keep their indices.
+    if let Some((path, index)) = sidecar.filter(|_| !dry_run) {
+        let res = index
+            .save(&path)
+            .wrap_err_with(|| format!("failed to save sidecar index {path:?}"));
+        bookkeeping_errors.extend(res.err());
+    }
+    if let Some((path, counter, files)) = counter.filter(|_| !dry_run) {
+        // Up to the last file that kept its index, by being renamed or already named, since
+        // indices before it can't be handed out again, even if their files were skipped.
+        let renamed = completed
+            .iter()
+            .map(|(from, _to)| from.as_path())
+            .collect::<HashSet<_>>();
+        let count = files
+            .iter()
+            .rposition(|file| renamed.contains(file.as_path()) || already_named.contains(file))
+            .map_or(0, |idx| idx + 1);
+        let res = counter
+            .advance(count)
+            .wrap_err_with(|| format!("failed to update counter file {path:?}"));
+        bookkeeping_errors.extend(res.err());
+    }
+    let RenameSummary {
+        renamed,
+        skipped,
+        failed,
+        bytes_transferred,
+        elapsed,
+    } = summary;
+    if !dry_run {
+        let mut summary = format!("renamed {renamed} file(s)");
+        if skipped > 0 {
+            summary += &format!(", skipped {skipped}");
+        }
+        if failed > 0 {
+            summary += &format!(", failed to rename {failed}");
+        }
+        if conflicts > 0 {
+            summary += &format!(", overwriting {conflicts} existing file(s)");
+        }
+        if bytes_transferred > 0 {
+            let secs = elapsed.as_secs_f64();
+            summary += &format!(
+                ", transferring {bytes_transferred} byte(s) in {}",
+                humantime::format_duration(elapsed)
+            );
+            if secs > 0.0 {
+                summary += &format!(" ({:.0} bytes/s)", bytes_transferred as f64 / secs);
+            }
+        }
+        tracing::info!("{summary}");
+    }
+
+    if let cli::OutputFormat::SummaryJson = format {
+        let summary = serde_json::json!({
+            "renamed": renamed,
+            "skipped": skipped,
+            "failed": failed,
+            "conflicts": conflicts,
+            "total": total,
+            "dry_run": dry_run,
+        });
+        writeln!(stdout, "{summary}").wrap_err("failed to write summary")?;
+    }
+
+    // Only once the summary is out, so that it's complete even if renaming was aborted.
+    if let Err(e) = res {
+        for bookkeeping_error in &bookkeeping_errors {
+            tracing::error!("{bookkeeping_error:#}");
+        }
+        return Err(e);
+    }
+    if !bookkeeping_errors.is_empty() {
+        let errors = bookkeeping_errors
+            .iter()
+            .map(|e| format!("{e:#}"))
+            .collect::<Vec<_>>();
+        bail!("{}", errors.join("; "));
+    }
+
+    duplicates_to_delete.sort();
+    for path in duplicates_to_delete {
+        if dry_run {
+            tracing::info!("deleting duplicate {path:?}");
+        } else if let Err(e) = fs::remove_file(&path) {
+            tracing::error!("failed to delete duplicate {path:?}: {e}");
+        }
+    }
 
-    zip_single_side_scans(files_iter, rename_spec, ZipVisitor { dry_run })
-        .wrap_err("failed to execute zipping operation")?;
+    if let Some((path, lock)) = new_width_lock.filter(|_| !dry_run) {
+        lock.save(&path)
+            .wrap_err_with(|| format!("failed to save width lock {path:?}"))?;
+    }
 
     if dry_run {
         tracing::info!("dry run complete; use the `--go` flag to actually rename files");
     }
 
+    if failed > 0 {
+        bail!("failed to rename {failed} file(s)");
+    }
     Ok(())
 }
 
-struct ZipVisitor {
+/// Describes the features supported by this build, for tools wrapping `rename-seq`.
+fn capabilities() -> serde_json::Value {
+    fn value_names<T: ValueEnum>() -> Vec<String> {
+        T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name().to_owned())
+            .collect()
+    }
+
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "placeholders": DynamicRenameContent::BY_NAME
+            .iter()
+            .map(|(name, _content)| name)
+            .chain(DynamicRenameContent::WITH_ARGUMENT)
+            .chain(DynamicRenameContent::NUMBERED)
+            .collect::<Vec<_>>(),
+        "sort_modes": cli::SortBy::SYNTAXES,
+        "order_modes": Order::BY_NAME
+            .iter()
+            .map(|(name, _order)| name)
+            .collect::<Vec<_>>(),
+        "on_error_modes": value_names::<cli::OnError>(),
+        "transforms": BuiltinTransform::SYNTAXES,
+        "case_modes": value_names::<cli::Case>(),
+        "output_formats": value_names::<cli::OutputFormat>(),
+        "collision_strategies": value_names::<cli::CollisionStrategy>(),
+        "flatten_collision_modes": value_names::<cli::OnFlattenCollision>(),
+        "actions": ["rename", "copy", "hard-link", "symlink"],
+    })
+}
+
+/// Writes a JSON report of a run's `summary` and `failures` to `path`, for `--report`.
+fn write_report(
+    path: &Path,
+    summary: RenameSummary,
+    failures: &[String],
     dry_run: bool,
+) -> eyre::Result<()> {
+    let RenameSummary {
+        renamed,
+        skipped,
+        failed,
+        bytes_transferred,
+        elapsed,
+    } = summary;
+    let report = serde_json::json!({
+        "timestamp": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        "command": std::env::args_os()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        "dry_run": dry_run,
+        "counts": {
+            "renamed": renamed,
+            "skipped": skipped,
+            "failed": failed,
+        },
+        "failures": failures,
+        "bytes_transferred": bytes_transferred,
+        "elapsed_secs": elapsed.as_secs_f64(),
+    });
+    let mut contents = serde_json::to_string_pretty(&report)?;
+    contents.push('\n');
+    fs::write(path, contents)?;
+    Ok(())
 }
 
-// [Workaround] for an upstream `tracing` issue where `tracing::event!(...)` only permits a constant
-// `$level` parameter.
-//
-// [Workaround]: https://github.com/tokio-rs/tracing/issues/372#issuecomment-762529515
-macro_rules! event {
-    ($level:expr, $($args:tt)*) => {{
-        use ::tracing::Level;
+/// Finds the key of each of `files`' group with `group_by`, which is matched against file names.
+///
+/// The key is the first capture group of `group_by`, if it has any, or the whole match otherwise.
+fn group_keys(files: &[&Path], group_by: &Regex) -> eyre::Result<Vec<String>> {
+    let mut unmatched = Vec::new();
+    let keys = files
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let key = group_by
+                .captures(&name)
+                .and_then(|caps| caps.get(1).or_else(|| caps.get(0)));
+            match key {
+                Some(key) => key.as_str().to_owned(),
+                None => {
+                    unmatched.push(*path);
+                    String::new()
+                }
+            }
+        })
+        .collect();
+    if !unmatched.is_empty() {
+        bail!("file names of {unmatched:?} did not match `--group-by` pattern {group_by}");
+    }
+    Ok(keys)
+}
 
-        match $level {
-            Level::ERROR => ::tracing::event!(Level::ERROR, $($args)*),
-            Level::WARN => ::tracing::event!(Level::WARN, $($args)*),
-            Level::INFO => ::tracing::event!(Level::INFO, $($args)*),
-            Level::DEBUG => ::tracing::event!(Level::DEBUG, $($args)*),
-            Level::TRACE => ::tracing::event!(Level::TRACE, $($args)*),
-        }
-    }};
+#[cfg(test)]
+fn run_to_string(args: &[&str]) -> eyre::Result<String> {
+    let mut stdout = Vec::new();
+    run(
+        MainArgs::try_parse_from(["rename-seq"].iter().chain(args))?,
+        &mut stdout,
+    )?;
+    Ok(String::from_utf8(stdout).unwrap())
 }
 
-impl Visitor for ZipVisitor {
-    type Error = Infallible;
+/// A temporary directory under the current one, which targets may be put in without
+/// `--allow-escape`.
+#[cfg(test)]
+fn test_dir() -> tempfile::TempDir {
+    tempfile::tempdir_in(std::env::current_dir().unwrap().join("target")).unwrap()
+}
 
-    fn visit(&mut self, idx: usize, from: &Path, to: PathBuf) -> ControlFlow<Self::Error> {
-        let &mut Self { dry_run } = self;
+#[cfg(test)]
+fn touch_all<'a>(dir: &Path, names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    names
+        .into_iter()
+        .map(|name| {
+            let path = dir.join(name);
+            std::fs::write(&path, name).unwrap();
+            path.to_str().unwrap().to_owned()
+        })
+        .collect()
+}
 
-        let _span = tracing::debug_span!("renaming file", rename_idx = idx,).entered();
+#[test]
+fn count_only_skips_noops() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["photo-0.jpg", "b.jpg", "c.jpg"]);
+    let spec = format!("{}/photo-{{padded_idx}}.jpg", dir.path().display());
 
-        let tracing_level = if dry_run {
-            tracing::Level::INFO
-        } else {
-            tracing::Level::DEBUG
-        };
-        event!(tracing_level, "renaming {from:?} to {to:?}",);
+    let mut args = vec!["--count-only", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert_eq!(run_to_string(&args).unwrap(), "2\n");
 
-        if !dry_run {
-            if let Err(e) = fs::rename(from, &to)
-                .wrap_err_with(|| format!("failed to rename file {from:?} to {to:?}"))
-            {
-                tracing::error!("{e:#}");
-            }
-        }
+    // Nothing should have been touched.
+    for file in &files {
+        assert!(Path::new(file).is_file());
+    }
+}
 
-        ControlFlow::Continue(())
+#[cfg(test)]
+fn assert_mkdirs_with_spec(spec_in_dir: impl FnOnce(&Path) -> String, expected: &[&str]) {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg"]);
+    let spec = spec_in_dir(dir.path());
+
+    let mut args = vec!["--go", "--mkdirs", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+
+    assert!(dir
+        .path()
+        .join(expected.iter().collect::<std::path::PathBuf>())
+        .is_file());
+}
+
+#[test]
+fn max_path_len_counts_out_dir_and_transforms() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["averyverylongname"]);
+    let out_dir = dir.path().join("some/deep/dir");
+    let out_dir_arg = format!("--out-dir={}", out_dir.display());
+    let prefix_len = out_dir.join("").to_str().unwrap().len();
+    let max_path_len = format!("--max-path-len={}", prefix_len + 16);
+
+    let run = |transforms: &[&str]| {
+        let mut args = vec!["--go", &max_path_len, &out_dir_arg];
+        args.extend(transforms);
+        args.extend(["{padded_idx}-{name}", "from-files", &files[0]]);
+        run_to_string(&args)
+    };
+
+    assert!(run(&["--transform", "set-ext=jpeg"]).is_err());
+    assert!(Path::new(&files[0]).exists());
+
+    run(&[]).unwrap();
+    let renamed = std::fs::read_dir(&out_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(renamed.len(), 1);
+    let name = renamed[0].file_name().unwrap().to_str().unwrap();
+    assert!(name.contains('~'), "{name}");
+    assert_eq!(renamed[0].to_str().unwrap().len(), prefix_len + 16);
+}
+
+#[test]
+fn out_dir_moves_files() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    let out_dir = dir.path().join("sorted/photos");
+    let out_dir = out_dir.to_str().unwrap();
+
+    let mut args = vec![
+        "--go",
+        "--out-dir",
+        out_dir,
+        "photo-{padded_idx}",
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    let out_dir = Path::new(out_dir);
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("photo-0")).unwrap(),
+        "a"
+    );
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("photo-1")).unwrap(),
+        "b"
+    );
+    assert!(!dir.path().join("a").exists());
+}
+
+#[test]
+fn mkdirs_creates_parents() {
+    assert_mkdirs_with_spec(
+        |dir| format!("{}/out/nested/{{padded_idx}}.jpg", dir.display()),
+        &["out", "nested", "0.jpg"],
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn mkdirs_creates_parents_with_backslashes() {
+    assert_mkdirs_with_spec(
+        |dir| format!("{}\\out\\nested\\{{padded_idx}}.jpg", dir.display()),
+        &["out", "nested", "0.jpg"],
+    );
+}
+
+#[test]
+fn skip_duplicates_renames_first_of_group() {
+    let dir = test_dir();
+    let files = ["a.jpg", "b.jpg", "c.jpg"].map(|name| dir.path().join(name));
+    for (path, contents) in files.iter().zip(["same", "unique", "same"]) {
+        std::fs::write(path, contents).unwrap();
+    }
+    let files = files.map(|p| p.to_str().unwrap().to_owned());
+    let spec = format!("{}/out-{{padded_idx}}.jpg", dir.path().display());
+
+    let mut args = vec!["--detect-duplicates", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert_eq!(
+        run_to_string(&args).unwrap(),
+        format!("{}\n{}\n\n", files[0], files[2])
+    );
+
+    args.splice(0..0, ["--go", "--skip-duplicates"]);
+    run_to_string(&args).unwrap();
+    assert!(dir.path().join("out-0.jpg").is_file());
+    assert!(dir.path().join("out-1.jpg").is_file());
+    assert!(Path::new(&files[2]).is_file());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn image_dimensions() {
+    let fixtures = || {
+        let dir = test_dir();
+        let images = [(3, 2), (1, 5)].map(|(width, height)| {
+            let path = dir.path().join(format!("{width}-by-{height}.png"));
+            image::RgbImage::new(width, height).save(&path).unwrap();
+            path.to_str().unwrap().to_owned()
+        });
+        let not_image = touch_all(dir.path(), ["notes.txt"]).remove(0);
+        let spec = format!(
+            "{}/{{width}}x{{height}}-{{padded_idx}}.png",
+            dir.path().display()
+        );
+        let mut args = vec!["--go".to_owned(), spec, "from-files".to_owned()];
+        args.extend(images.into_iter().chain([not_image]));
+        (dir, args)
+    };
+    let run = |args: &[String]| run_to_string(&args.iter().map(|a| a.as_str()).collect::<Vec<_>>());
+
+    let (dir, mut args) = fixtures();
+    args.insert(0, "--on-error=abort".to_owned());
+    assert!(run(&args).is_err());
+    assert!(dir.path().join("notes.txt").is_file());
+    assert!(dir.path().join("3x2-0.png").is_file());
+
+    let (dir, mut args) = fixtures();
+    args.insert(0, "--skip-non-images".to_owned());
+    run(&args).unwrap();
+    let mut names = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    // Only two files are selected, so `notes.txt` doesn't take up an index.
+    assert_eq!(names, ["1x5-1.png", "3x2-0.png", "notes.txt"]);
+}
+
+#[test]
+fn group_by_counts_per_group() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["cat_x.jpg", "cat_y.jpg", "dog_z.jpg"]);
+    let spec = format!("{}/{{group}}-{{padded_idx}}.jpg", dir.path().display());
+
+    let mut args = vec!["--go", "--group-by", "^([^_]+)_", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+
+    for name in ["cat-0.jpg", "cat-1.jpg", "dog-0.jpg"] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
     }
 }
 
-struct ZigZag<T, I>
-where
-    I: Iterator<Item = T> + DoubleEndedIterator,
-{
-    next: ForwardOrBackward,
-    inner: I,
+#[test]
+fn capabilities_lists_build_features() {
+    let capabilities: serde_json::Value =
+        serde_json::from_str(&run_to_string(&["--capabilities"]).unwrap()).unwrap();
+
+    let placeholders = capabilities["placeholders"].as_array().unwrap();
+    assert!(placeholders.contains(&"padded_idx".into()));
+    assert_eq!(
+        placeholders.contains(&"width".into()),
+        cfg!(feature = "image")
+    );
+    assert!(capabilities["sort_modes"]
+        .as_array()
+        .unwrap()
+        .contains(&"lexicographical".into()));
+    assert!(capabilities["order_modes"]
+        .as_array()
+        .unwrap()
+        .contains(&"single-sided-scans".into()));
+
+    assert!(run_to_string(&["--capabilities", "--go"]).is_err());
+}
+
+#[test]
+fn refuses_escaping_targets() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg"]);
+
+    let mut args = vec!["../{padded_idx}.jpg", "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+
+    args.insert(0, "--allow-escape");
+    run_to_string(&args).unwrap();
+
+    let outside = tempfile::tempdir().unwrap();
+    let spec = format!("{}/{{padded_idx}}.jpg", outside.path().display());
+    let files = touch_all(dir.path(), ["b.jpg"]);
+    let mut args = vec![&*spec, "from-files", &files[0]];
+    assert!(run_to_string(&args).is_err());
+    args.insert(0, "--allow-escape");
+    run_to_string(&args).unwrap();
+}
+
+#[test]
+fn refuses_colliding_targets() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b.jpg"]);
+    let spec = format!("{}/same.jpg", dir.path().display());
+
+    let mut args = vec!["--go", "--allow-warnings", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join("a.jpg").is_file());
+    assert!(dir.path().join("b.jpg").is_file());
+}
+
+#[test]
+fn suggest_spec_needs_no_rename_spec() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["IMG_0001.jpg", "IMG_0002.jpg"]);
+
+    let mut args = vec!["--suggest-spec", "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert_eq!(
+        run_to_string(&args).unwrap(),
+        format!("{}\n", dir.path().join("IMG_{padded_idx}.jpg").display())
+    );
+}
+
+/// Reads the original names that [`touch_all`] wrote into files renamed to `out-{padded_idx}`.
+#[cfg(test)]
+fn renamed_originals(dir: &Path, count: usize) -> Vec<String> {
+    (0..count)
+        .map(|idx| std::fs::read_to_string(dir.join(format!("out-{idx}"))).unwrap())
+        .collect()
+}
+
+#[test]
+fn no_sort_preserves_order() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["c", "a", "b"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--no-sort", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 3), ["c", "a", "b"]);
+
+    let dir = test_dir();
+    touch_all(dir.path(), ["z.in", "a.in", "m.in", "q.in"]);
+    let discovered = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let glob = format!("{}/*.in", dir.path().display());
+    run_to_string(&["--go", "--no-sort", &spec, "from-glob", &glob]).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 4), discovered);
+}
+
+#[test]
+fn index_field_only_repads() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a1b.txt", "a10b.txt", "a007b.txt"]);
+
+    let mut args = vec!["--go", "--index-field-only", "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in ["a01b.txt", "a10b.txt", "a07b.txt"] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+
+    let files = touch_all(dir.path(), ["a01b.txt", "a10b.txt"]);
+    let mut args = vec!["--go", "--index-field-only=3", "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in ["a001b.txt", "a010b.txt"] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+}
+
+#[test]
+fn dry_run_to_then_apply() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.txt", "b.txt"]);
+    let plan = dir.path().join("plan.json");
+    let plan = plan.to_str().unwrap();
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--dry-run-to", plan, &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert!(dir.path().join("a.txt").is_file());
+
+    touch_all(dir.path(), ["out-1"]);
+    assert!(run_to_string(&["--go", "apply", plan]).is_err());
+    assert!(dir.path().join("a.txt").is_file());
+
+    std::fs::remove_file(dir.path().join("out-1")).unwrap();
+    run_to_string(&["--go", "apply", plan]).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 2), ["a.txt", "b.txt"]);
+}
+
+#[test]
+fn warns_on_escaped_only_specs() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a"]);
+    let run = |spec: &str| {
+        let spec = format!("{}/{spec}", dir.path().display());
+        let mut args = vec![spec.as_str(), "from-files"];
+        args.extend(files.iter().map(|f| f.as_str()));
+        run_to_string(&args)
+    };
+
+    assert!(run("name").is_err());
+    assert!(run("name{{padded_idx}}").is_err());
+    run("{{name}}-{padded_idx}").unwrap();
+    run("name-{padded_idx}").unwrap();
+}
+
+#[test]
+fn mirror_matches_reference_names() {
+    let reference = test_dir();
+    touch_all(reference.path(), ["IMG_2.raw", "IMG_1.raw", "IMG_3"]);
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b.jpg", "c"]);
+    let reference = reference.path().to_str().unwrap();
+
+    let mut args = vec!["--go", "--mirror", reference, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for (name, contents) in [
+        ("IMG_1.jpg", "a.jpg"),
+        ("IMG_2.jpg", "b.jpg"),
+        ("IMG_3", "c"),
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
+    }
+
+    let files = touch_all(dir.path(), ["d", "e", "f", "g"]);
+    let mut args = vec!["--go", "--mirror", reference, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+}
+
+#[test]
+fn case_changes_can_collide() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["Photo.JPG", "photo.jpg", "Other.Png"]);
+    let spec = format!("{}/{{group}}", dir.path().display());
+
+    let mut args = vec![
+        "--go",
+        "--group-by",
+        ".*",
+        "--case",
+        "lower",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().skip(1).map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert!(dir.path().join("other.png").is_file());
+
+    let mut args = vec![
+        "--go",
+        "--group-by",
+        ".*",
+        "--case",
+        "upper",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().take(2).map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join("Photo.JPG").is_file());
+    assert!(dir.path().join("photo.jpg").is_file());
+}
+
+#[test]
+fn timestamps_disambiguate_collisions() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b.jpg"]);
+    for (file, secs) in files.iter().zip([1_700_000_000, 1_700_003_600]) {
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+    let spec = format!("{}/photo.jpg", dir.path().display());
+
+    let mut args = vec![
+        "--go",
+        "--allow-warnings",
+        "--collision-strategy",
+        "timestamp",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for (name, contents) in [("photo-221320.jpg", "a.jpg"), ("photo-231320.jpg", "b.jpg")] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
+    }
+}
+
+#[test]
+fn match_order_groups_by_glob() {
+    let dir = test_dir();
+    touch_all(dir.path(), ["b.png", "a.jpg", "c.jpg", "a.png"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let jpgs = format!("{}/*.jpg", dir.path().display());
+    let all = format!("{}/*", dir.path().display());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "match-order",
+        &jpgs,
+        &all,
+    ];
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        renamed_originals(dir.path(), 4),
+        ["a.jpg", "c.jpg", "a.png", "b.png"]
+    );
+}
+
+#[test]
+fn fill_gaps_uses_lowest_free_index() {
+    let dir = test_dir();
+    touch_all(dir.path(), ["img-001.jpg", "img-003.jpg", "img-004.jpg"]);
+    let files = touch_all(dir.path(), ["new.jpg"]);
+    let spec = format!("{}/img-{{padded_idx}}.jpg", dir.path().display());
+
+    let mut args = vec!["--go", "--fill-gaps", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("img-002.jpg")).unwrap(),
+        "new.jpg"
+    );
+
+    // With no gaps left, new files go after the end.
+    let files = touch_all(dir.path(), ["newer.jpg"]);
+    let mut args = vec!["--go", "--fill-gaps", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("img-005.jpg")).unwrap(),
+        "newer.jpg"
+    );
 }
 
-impl<T, I> ZigZag<T, I>
-where
-    I: Iterator<Item = T> + DoubleEndedIterator,
-{
-    pub fn new(iter: I) -> Self {
-        Self {
-            next: ForwardOrBackward::Forward,
-            inner: iter,
+#[test]
+fn sidecar_index_keeps_numbers_stable() {
+    let dir = test_dir();
+    let sidecar = dir.path().join("index.json");
+    let sidecar = format!("--sidecar-index={}", sidecar.display());
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let run = |files: &[String]| {
+        let mut args = vec!["--go", &sidecar, &spec, "from-files"];
+        args.extend(files.iter().map(|f| f.as_str()));
+        run_to_string(&args).unwrap();
+    };
+
+    run(&touch_all(dir.path(), ["b", "c"]));
+    assert_eq!(renamed_originals(dir.path(), 2), ["b", "c"]);
+
+    // `a` sorts first, but is new, and so is numbered after existing files.
+    let mut files = touch_all(dir.path(), ["a"]);
+    files.extend([0, 1].map(|idx| format!("{}/out-{idx}", dir.path().display())));
+    run(&files);
+    assert_eq!(renamed_originals(dir.path(), 3), ["b", "c", "a"]);
+}
+
+#[test]
+fn transforms_chain_in_order() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["IMG_Beach Day.JPG", "IMG_Dunes.JPG"]);
+
+    let mut args = vec![
+        "--go",
+        "--transform",
+        "strip-prefix=IMG_",
+        "--transform",
+        "normalize-spaces",
+        "--transform",
+        "add-index",
+        "--case",
+        "lower",
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for (name, contents) in [
+        ("beach_day-0.jpg", "IMG_Beach Day.JPG"),
+        ("dunes-1.jpg", "IMG_Dunes.JPG"),
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
+    }
+}
+
+#[test]
+fn strict_spec_rejects_colliding_specs() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b.jpg"]);
+    let run = |spec: &str, files: &[String]| {
+        let spec = format!("{}/{spec}", dir.path().display());
+        let mut args = vec!["--strict-spec", "--allow-warnings", &spec, "from-files"];
+        args.extend(files.iter().map(|f| f.as_str()));
+        run_to_string(&args)
+    };
+
+    assert!(run("same.jpg", &files).is_err());
+    assert!(run("{rand:8}.jpg", &files).is_err());
+    run("{padded_idx}.jpg", &files).unwrap();
+    run("{dir_idx}.jpg", &files).unwrap();
+    run("same.jpg", &files[..1]).unwrap();
+}
+
+#[test]
+fn names_from_manifest() {
+    let dir = test_dir();
+    touch_all(dir.path(), ["c.jpg", "a.jpg", "b.jpg"]);
+    let manifest = dir.path().join("names.txt");
+    std::fs::write(&manifest, "first.txt\nsecond.txt\n\nthird.txt\n").unwrap();
+    let manifest = manifest.to_str().unwrap();
+    let jpgs = format!("{}/*.jpg", dir.path().display());
+
+    let args = [
+        "--go",
+        "--names-from",
+        manifest,
+        "from-glob",
+        "--sort-by",
+        "lexicographical",
+        &jpgs,
+    ];
+    run_to_string(&args).unwrap();
+    for (name, contents) in [
+        ("first.txt", "a.jpg"),
+        ("second.txt", "b.jpg"),
+        ("third.txt", "c.jpg"),
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
+    }
+}
+
+#[test]
+fn dedup_targets_by_hash_collapses_identical_files() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "c"]);
+    let dup = dir.path().join("b");
+    std::fs::write(&dup, "a").unwrap();
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec![
+        "--go",
+        "--dedup-targets-by-hash",
+        "--delete-duplicates",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    args.insert(args.len() - 1, dup.to_str().unwrap());
+    run_to_string(&args).unwrap();
+
+    assert_eq!(renamed_originals(dir.path(), 2), ["a", "c"]);
+    assert!(!dup.exists());
+    assert!(!dir.path().join("out-2").exists());
+}
+
+#[test]
+fn width_lock_pads_batches_alike() {
+    let dir = test_dir();
+    let lock = format!("--width-lock={}", dir.path().join("width.json").display());
+    let run = |batch: &str, names: &[&str]| {
+        let spec = format!("{}/{batch}-{{padded_idx}}", dir.path().display());
+        let files = touch_all(dir.path(), names.iter().copied());
+        let mut args = vec!["--go", &lock, &spec, "from-files"];
+        args.extend(files.iter().map(|f| f.as_str()));
+        run_to_string(&args)
+    };
+
+    let first = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k"];
+    run("first", &first).unwrap();
+    assert!(dir.path().join("first-10").exists());
+    run("second", &["l", "m"]).unwrap();
+    assert!(dir.path().join("second-00").exists());
+    assert!(dir.path().join("second-01").exists());
+
+    let too_many = (0..101).map(|idx| format!("n{idx}")).collect::<Vec<_>>();
+    let too_many = too_many.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+    assert!(run("third", &too_many).is_err());
+}
+
+#[test]
+fn sort_by_sidecar_ratings() {
+    let dir = test_dir();
+    touch_all(dir.path(), ["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+    for (name, rating) in [("a", 3), ("b", 1), ("d", 2)] {
+        let sidecar = dir.path().join(format!("{name}.json"));
+        std::fs::write(sidecar, format!(r#"{{"rating": {rating}}}"#)).unwrap();
+    }
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let jpgs = format!("{}/*.jpg", dir.path().display());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "sidecar=rating",
+        &jpgs,
+    ];
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        renamed_originals(dir.path(), 4),
+        ["b.jpg", "d.jpg", "a.jpg", "c.jpg"]
+    );
+}
+
+#[test]
+fn stable_across_extensions_shares_indices() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.txt", "doc.docx", "doc.pdf", "z.pdf"]);
+    let spec = format!("{}/report-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec![
+        "--go",
+        "--keep-ext",
+        "--stable-across-extensions",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for (name, contents) in [
+        ("report-0.txt", "a.txt"),
+        ("report-1.docx", "doc.docx"),
+        ("report-1.pdf", "doc.pdf"),
+        ("report-2.pdf", "z.pdf"),
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
+    }
+}
+
+#[test]
+fn from_files_rejects_directories() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a"]);
+    let subdir = dir.path().join("sub");
+    std::fs::create_dir(&subdir).unwrap();
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-files",
+        &files[0],
+        subdir.to_str().unwrap(),
+    ];
+    let err = run_to_string(&args).unwrap_err();
+    assert!(err.to_string().contains("--include-dirs"), "{err}");
+    assert!(dir.path().join("a").exists());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-files",
+        "--include-dirs",
+        &files[0],
+        subdir.to_str().unwrap(),
+    ];
+    run_to_string(&args).unwrap();
+    assert!(dir.path().join("out-1").is_dir());
+}
+
+#[test]
+fn flatten_collisions() {
+    for (mode, targets) in [
+        ("error", None),
+        ("number", Some(["img-1.jpg", "img-2.jpg"])),
+        ("subdir", Some(["a/img.jpg", "b/img.jpg"])),
+    ] {
+        let dir = test_dir();
+        let mut files = Vec::new();
+        for parent in ["a", "b"] {
+            std::fs::create_dir(dir.path().join(parent)).unwrap();
+            files.extend(touch_all(&dir.path().join(parent), ["img.jpg"]));
+        }
+        let spec = format!("{}/flat/{{name}}", dir.path().display());
+        let on_flatten_collision = format!("--on-flatten-collision={mode}");
+
+        let mut args = vec![
+            "--go",
+            "--mkdirs",
+            "--keep-ext",
+            &on_flatten_collision,
+            &spec,
+            "from-files",
+        ];
+        args.extend(files.iter().map(|f| f.as_str()));
+        let res = run_to_string(&args);
+
+        let Some(targets) = targets else {
+            assert!(res.is_err(), "{mode}");
+            assert!(!dir.path().join("flat").exists(), "{mode}");
+            continue;
+        };
+        res.unwrap();
+        for target in targets {
+            assert!(
+                dir.path().join("flat").join(target).exists(),
+                "{mode}: {target}"
+            );
         }
     }
 }
 
-impl<T, I> Iterator for ZigZag<T, I>
-where
-    I: Iterator<Item = T> + DoubleEndedIterator,
-{
-    type Item = T;
+#[test]
+fn exclude_ext_leaves_out_sidecars() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "a.XMP", "b.jpg", "b.xmp", "c.png"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--exclude-ext", ".xmp", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        renamed_originals(dir.path(), 3),
+        ["a.jpg", "b.jpg", "c.png"]
+    );
+    assert!(dir.path().join("a.XMP").exists());
+    assert!(dir.path().join("b.xmp").exists());
+}
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+#[test]
+fn tail_keeps_trailing_tags() {
+    let dir = test_dir();
+    let files = touch_all(
+        dir.path(),
+        ["shot_001_final.jpg", "shot_002.jpg", "shot_010-b.jpg"],
+    );
+    let spec = format!("{}/{{padded_idx}}_{{tail}}.jpg", dir.path().display());
+
+    let mut args = vec!["--go", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for (name, contents) in [
+        ("0_final.jpg", "shot_001_final.jpg"),
+        ("1_.jpg", "shot_002.jpg"),
+        ("2_b.jpg", "shot_010-b.jpg"),
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
     }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let Self { next, inner } = self;
+#[test]
+fn report_records_mixed_run() {
+    let dir = test_dir();
+    let report = dir.path().join("report.json");
+    let files = touch_all(dir.path(), ["a", "c"]);
+    let missing = dir.path().join("b");
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let report_arg = format!("--report={}", report.display());
 
-        let item;
-        match *next {
-            ForwardOrBackward::Forward => {
-                item = inner.next()?;
-                *next = ForwardOrBackward::Backward;
-            }
-            ForwardOrBackward::Backward => {
-                item = inner.next_back()?;
-                *next = ForwardOrBackward::Forward;
-            }
+    let args = [
+        "--go",
+        &report_arg,
+        &spec,
+        "from-files",
+        &files[0],
+        missing.to_str().unwrap(),
+        &files[1],
+    ];
+    let err = run_to_string(&args).unwrap_err();
+    assert_eq!(err.to_string(), "failed to rename 1 file(s)");
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(
+        report["counts"],
+        serde_json::json!({ "renamed": 2, "skipped": 0, "failed": 1 })
+    );
+    let failures = report["failures"].as_array().unwrap();
+    assert_eq!(failures.len(), 1);
+    let failure = failures[0].as_str().unwrap();
+    assert!(failure.contains(&format!("{missing:?}")), "{failure}");
+    assert_eq!(report["dry_run"], false);
+    assert_eq!(report["bytes_transferred"], 0);
+    for field in ["timestamp", "command", "elapsed_secs"] {
+        assert!(report.get(field).is_some(), "{field}");
+    }
+}
+
+#[test]
+fn global_idx_continues_across_runs() {
+    let dir = test_dir();
+    let counter = format!("--counter-file={}", dir.path().join("counter").display());
+    let spec = format!("{}/archive-{{global_idx}}.bin", dir.path().display());
+    let run = |names: [&str; 2]| {
+        let files = touch_all(dir.path(), names);
+        let mut args = vec!["--go", &counter, "--counter-padding=3", &spec, "from-files"];
+        args.extend(files.iter().map(|f| f.as_str()));
+        run_to_string(&args).unwrap();
+    };
+
+    run(["a", "b"]);
+    run(["c", "d"]);
+    for (idx, contents) in ["a", "b", "c", "d"].into_iter().enumerate() {
+        let path = dir.path().join(format!("archive-{idx:03}.bin"));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), contents);
+    }
+}
+
+#[test]
+fn global_idx_ignores_groups() {
+    let dir = test_dir();
+    let counter = format!("--counter-file={}", dir.path().join("counter").display());
+    let files = touch_all(dir.path(), ["cat_x", "dog_y", "cat_z"]);
+    let spec = format!("{}/{{group}}-{{global_idx}}", dir.path().display());
+
+    let mut args = vec![
+        "--go",
+        &counter,
+        "--group-by",
+        "^([^_]+)_",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for (name, contents) in [("cat-0", "cat_x"), ("dog-1", "dog_y"), ("cat-2", "cat_z")] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            contents
+        );
+    }
+}
+
+#[test]
+fn global_idx_counts_already_named_files() {
+    let dir = test_dir();
+    let counter_path = dir.path().join("counter");
+    let counter = format!("--counter-file={}", counter_path.display());
+    let spec = format!("{}/archive-{{global_idx}}.bin", dir.path().display());
+
+    let files = touch_all(dir.path(), ["a", "archive-1.bin"]);
+    let mut args = vec!["--go", &counter, &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(std::fs::read_to_string(&counter_path).unwrap(), "2\n");
+
+    let files = touch_all(dir.path(), ["c"]);
+    run_to_string(&["--go", &counter, &spec, "from-files", &files[0]]).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("archive-2.bin")).unwrap(),
+        "c"
+    );
+}
+
+#[test]
+fn global_idx_overflow_is_an_error() {
+    let dir = test_dir();
+    let counter_path = dir.path().join("counter");
+    let max = format!("{}\n", usize::MAX);
+    std::fs::write(&counter_path, &max).unwrap();
+    let counter = format!("--counter-file={}", counter_path.display());
+    let spec = format!("{}/x-{{global_idx}}", dir.path().display());
+
+    // The first index fits, but the second doesn't, and neither does the next run's start.
+    let files = touch_all(dir.path(), ["a", "b"]);
+    let mut args = vec!["--go", &counter, &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join(format!("x-{}", usize::MAX)).exists());
+    assert!(dir.path().join("b").exists());
+    assert_eq!(std::fs::read_to_string(&counter_path).unwrap(), max);
+}
+
+#[test]
+fn global_idx_counts_renames_before_aborting() {
+    let dir = test_dir();
+    let counter = format!("--counter-file={}", dir.path().join("counter").display());
+    let spec = format!("{}/archive-{{global_idx}}.bin", dir.path().display());
+    let files = touch_all(dir.path(), ["a", "c"]);
+    let missing = dir.path().join("b");
+
+    let args = [
+        "--go",
+        "--fail-fast",
+        &counter,
+        &spec,
+        "from-files",
+        &files[0],
+        missing.to_str().unwrap(),
+        &files[1],
+    ];
+    assert!(run_to_string(&args).is_err());
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("counter")).unwrap(),
+        "1\n"
+    );
+
+    let files = touch_all(dir.path(), ["d"]);
+    let args = ["--go", &counter, &spec, "from-files", &files[0]];
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("archive-0.bin")).unwrap(),
+        "a"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("archive-1.bin")).unwrap(),
+        "d"
+    );
+}
+
+#[test]
+fn correctly_named_files_are_untouched() {
+    let dir = test_dir();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    touch_all(dir.path(), ["out-0", "out-1", "c", "d"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let files = ["sub/../out-0", "./out-1", "c", "d"].map(|name| dir.path().join(name));
+    let mut args = vec![
+        "--go",
+        "--safe",
+        "--format",
+        "summary-json",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.to_str().unwrap()));
+    let summary: serde_json::Value = serde_json::from_str(&run_to_string(&args).unwrap()).unwrap();
+    assert_eq!(summary["renamed"], 2);
+    assert_eq!(
+        renamed_originals(dir.path(), 4),
+        ["out-0", "out-1", "c", "d"]
+    );
+}
+
+#[test]
+fn summary_json_shape() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    for (go, dry_run) in [(None, true), (Some("--go"), false)] {
+        let mut args = go.into_iter().collect::<Vec<_>>();
+        args.extend(["--format", "summary-json", &spec, "from-files"]);
+        args.extend(files.iter().map(|f| f.as_str()));
+        let summary: serde_json::Value =
+            serde_json::from_str(&run_to_string(&args).unwrap()).unwrap();
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "renamed": 2,
+                "skipped": 0,
+                "failed": 0,
+                "conflicts": 0,
+                "total": 2,
+                "dry_run": dry_run,
+            })
+        );
+    }
+}
+
+#[test]
+fn reverse_spec_restores_originals() {
+    let dir = test_dir();
+    let spec = format!("{}/photo-{{padded_idx}}.jpg", dir.path().display());
+    let original_spec = format!("{}/IMG_{{padded_idx}}.jpg", dir.path().display());
+    let renamed = (8..=10)
+        .map(|idx| {
+            let path = dir.path().join(format!("photo-{idx:02}.jpg"));
+            std::fs::write(&path, format!("IMG_{idx:02}.jpg")).unwrap();
+            path.to_str().unwrap().to_owned()
+        })
+        .collect::<Vec<_>>();
+
+    let mut args = vec!["--go", &spec, "reverse-spec", &original_spec];
+    args.extend(renamed.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in ["IMG_08.jpg", "IMG_09.jpg", "IMG_10.jpg"] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            name
+        );
+    }
+
+    let args = [
+        "--go",
+        &spec,
+        "reverse-spec",
+        &original_spec,
+        &original_spec,
+    ];
+    assert!(run_to_string(&args).is_err());
+}
+
+#[test]
+fn start_at_one_pads_to_last_index() {
+    let dir = test_dir();
+    let names = (0..10).map(|idx| format!("{idx}.jpg")).collect::<Vec<_>>();
+    let files = touch_all(dir.path(), names.iter().map(|n| n.as_str()));
+    let spec = format!("{}/photo-{{padded_idx}}.jpg", dir.path().display());
+
+    let mut args = vec!["--go", "--start-at", "1", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert!(dir.path().join("photo-01.jpg").exists());
+    assert!(dir.path().join("photo-10.jpg").exists());
+    assert!(!dir.path().join("photo-0.jpg").exists());
+}
+
+#[test]
+fn step_rejects_zero() {
+    let err = MainArgs::try_parse_from([
+        "rename-seq",
+        "--step",
+        "0",
+        "out-{padded_idx}",
+        "from-files",
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+}
+
+#[test]
+fn glob_exclude() {
+    let dir = test_dir();
+    touch_all(
+        dir.path(),
+        ["a.jpg", "a-thumb.jpg", "b.jpg", "b-thumb.jpg", "c.png"],
+    );
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let glob = format!("{}/*.jpg", dir.path().display());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "lexicographical",
+        "--exclude",
+        "**/*-thumb.jpg",
+        "--exclude",
+        "**/c.*",
+        &glob,
+    ];
+    run_to_string(&args).unwrap();
+    let out = |idx| dir.path().join(format!("out-{idx}"));
+    assert_eq!(std::fs::read_to_string(out(0)).unwrap(), "a.jpg");
+    assert_eq!(std::fs::read_to_string(out(1)).unwrap(), "b.jpg");
+    assert!(!out(2).exists());
+    assert!(dir.path().join("a-thumb.jpg").exists());
+}
+
+#[test]
+fn glob_max_depth() {
+    let dir = test_dir();
+    for subdir in ["a", "a/b"] {
+        std::fs::create_dir(dir.path().join(subdir)).unwrap();
+    }
+    touch_all(dir.path(), ["0.jpg", "a/1.jpg", "a/b/2.jpg"]);
+    let base_dir = dir.path().to_str().unwrap();
+
+    let selected = |max_depth: Option<&str>| {
+        let mut args = vec!["from-glob", "--sort-by", "lexicographical"];
+        if let Some(max_depth) = max_depth {
+            args.extend(["--max-depth", max_depth]);
         }
-        Some(item)
+        args.extend(["--base-dir", base_dir, "**/*.jpg"]);
+        let selection = cli::Selection::try_parse_from(["rename-seq"].iter().chain(&args));
+        let files = selection.unwrap().files().unwrap();
+        files
+            .iter()
+            .map(|path| {
+                path.strip_prefix(base_dir)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(selected(Some("1")), ["0.jpg"]);
+    assert_eq!(selected(Some("2")), ["0.jpg", "a/1.jpg"]);
+    assert_eq!(selected(None), ["0.jpg", "a/1.jpg", "a/b/2.jpg"]);
+}
+
+#[test]
+fn glob_base_dir() {
+    let dir = test_dir();
+    for subdir in ["photos", "photos/raw", "other"] {
+        std::fs::create_dir(dir.path().join(subdir)).unwrap();
     }
+    touch_all(
+        dir.path(),
+        ["photos/a.jpg", "photos/raw/b.jpg", "other/c.jpg"],
+    );
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let base_dir = dir.path().join("photos");
+
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "lexicographical",
+        "--base-dir",
+        base_dir.to_str().unwrap(),
+        "**/*.jpg",
+    ];
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        renamed_originals(dir.path(), 2),
+        ["photos/a.jpg", "photos/raw/b.jpg"]
+    );
+    assert!(!dir.path().join("out-2").exists());
 }
 
-#[derive(Clone, Copy, Debug)]
-enum ForwardOrBackward {
-    Forward,
-    Backward,
+#[test]
+fn sort_by_natural() {
+    let dir = test_dir();
+    touch_all(
+        dir.path(),
+        [
+            "file20",
+            "file2",
+            "file10",
+            "file1",
+            "file02",
+            "ファイル3",
+            "ファイル",
+        ],
+    );
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let glob = format!("{}/*i*", dir.path().display());
+    let glob_jp = format!("{}/ファ*", dir.path().display());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "natural",
+        &glob,
+        &glob_jp,
+    ];
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        renamed_originals(dir.path(), 7),
+        [
+            "file1",
+            "file2",
+            "file02",
+            "file10",
+            "file20",
+            "ファイル",
+            "ファイル3"
+        ]
+    );
+}
+
+#[test]
+fn sort_by_modified_time() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b.jpg", "c.jpg"]);
+    let epoch = SystemTime::UNIX_EPOCH;
+    for (file, secs) in files.iter().zip([300, 100, 200]) {
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(epoch + Duration::from_secs(secs))
+            .unwrap();
+    }
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let jpgs = format!("{}/*.jpg", dir.path().display());
+
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "modified-time",
+        &jpgs,
+    ];
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        renamed_originals(dir.path(), 3),
+        ["b.jpg", "c.jpg", "a.jpg"]
+    );
+
+    let outs = format!("{}/out-*", dir.path().display());
+    let spec = format!("{}/rev-{{padded_idx}}", dir.path().display());
+    let args = [
+        "--go",
+        "--reverse",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "modified-time",
+        &outs,
+    ];
+    run_to_string(&args).unwrap();
+    let originals = (0..3)
+        .map(|idx| std::fs::read_to_string(dir.path().join(format!("rev-{idx}"))).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(originals, ["a.jpg", "c.jpg", "b.jpg"]);
+}
+
+#[test]
+fn reverse_numbers_back_to_front() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b", "c", "d"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--reverse", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 4), ["d", "c", "b", "a"]);
+
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b", "c", "d"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let mut args = vec![
+        "--go",
+        "--reverse",
+        "--order",
+        "single-sided-scans",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 4), ["d", "a", "c", "b"]);
+
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["c", "a", "b"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let mut args = vec!["--go", "--no-sort", "--reverse", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 3), ["b", "a", "c"]);
+
+    // `--reverse` used to only be a flag of `from-glob`.
+    let dir = test_dir();
+    touch_all(dir.path(), ["c.in", "a.in", "b.in"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let glob = format!("{}/*.in", dir.path().display());
+    let args = [
+        "--go",
+        &spec,
+        "from-glob",
+        "--sort-by",
+        "lexicographical",
+        "--reverse",
+        &glob,
+    ];
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 3), ["c.in", "b.in", "a.in"]);
+}
+
+#[test]
+fn pad_width_is_fixed() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b", "c"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--pad-width", "5", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for idx in ["00000", "00001", "00002"] {
+        assert!(dir.path().join(format!("out-{idx}")).exists());
+    }
+}
+
+#[test]
+fn radix_names_common_bases() {
+    let dir = test_dir();
+    let names = (0..17).map(|idx| idx.to_string()).collect::<Vec<_>>();
+    let files = touch_all(dir.path(), names.iter().map(|n| n.as_str()));
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec![
+        "--go",
+        "--radix",
+        "hex",
+        "--uppercase-digits",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert!(dir.path().join("out-0F").exists());
+    assert!(dir.path().join("out-10").exists());
+
+    for base in ["1", "37", "hexa"] {
+        let res = MainArgs::try_parse_from(["rename-seq", "--radix", base, "out", "from-files"]);
+        assert!(res.is_err());
+    }
+}
+
+#[test]
+fn copy_keeps_originals() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let report = dir.path().join("report.json");
+    let report_arg = report.to_str().unwrap();
+    let mut args = vec![
+        "--go",
+        "--copy",
+        "--report",
+        report_arg,
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 2), ["a", "b"]);
+    for file in &files {
+        assert!(Path::new(file).exists());
+    }
+    // Each file contains its one-letter name.
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["bytes_transferred"], 2);
+
+    // Copying `out-0` and `out-1` onto each other would lose one of them.
+    let outs = (0..2)
+        .map(|idx| dir.path().join(format!("out-{idx}")))
+        .collect::<Vec<_>>();
+    let mut args = vec!["--go", "--copy", "--safe", &spec, "from-files"];
+    args.extend(outs.iter().rev().map(|f| f.to_str().unwrap()));
+    assert!(run_to_string(&args).is_err());
+    assert_eq!(renamed_originals(dir.path(), 2), ["a", "b"]);
+}
+
+#[test]
+fn hard_links_share_contents() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--link=hard", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    std::fs::write(&files[0], "changed").unwrap();
+    assert_eq!(renamed_originals(dir.path(), 2), ["changed", "b"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_point_at_absolute_originals() {
+    let dir = test_dir();
+    touch_all(dir.path(), ["a"]);
+    let spec = format!("{}/links/out-{{padded_idx}}", dir.path().display());
+
+    let original = dir.path().join("a");
+    let args = [
+        "--go",
+        "--link=symlink",
+        "--mkdirs",
+        &spec,
+        "from-files",
+        original.to_str().unwrap(),
+    ];
+    run_to_string(&args).unwrap();
+    let link = dir.path().join("links/out-0");
+    assert_eq!(std::fs::read_link(&link).unwrap(), original);
+    assert_eq!(std::fs::read_to_string(link).unwrap(), "a");
+    assert!(original.exists());
+}
+
+#[test]
+fn collisions_fail_dry_runs() {
+    let dir = test_dir();
+    std::fs::create_dir_all(dir.path().join("x")).unwrap();
+    std::fs::create_dir_all(dir.path().join("y")).unwrap();
+    let files = touch_all(dir.path(), ["x/a", "y/a", "x/b"]);
+
+    let same = format!("{}/same", dir.path().display());
+    let mut args = vec!["--allow-warnings", &same, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    let err = run_to_string(&args).unwrap_err().to_string();
+    assert!(err.contains("1 target(s)"), "{err}");
+    assert!(files.iter().all(|f| err.contains(f.as_str())), "{err}");
+
+    let by_name = format!("{}/out-{{name}}", dir.path().display());
+    let mut args = vec![&*by_name, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    let err = run_to_string(&args).unwrap_err().to_string();
+    assert!(err.contains("out-a"), "{err}");
+    assert!(!err.contains("out-b"), "{err}");
+    assert!(err.contains(&files[0]) && err.contains(&files[1]), "{err}");
+}
+
+#[test]
+fn undo_log_restores_names() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["x", "y", "z"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let undo = dir.path().join("undo.json");
+    let undo_log = format!("--undo-log={}", undo.display());
+
+    let mut args = vec!["--go", &undo_log, &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    assert_eq!(renamed_originals(dir.path(), 3), ["x", "y", "z"]);
+
+    run_to_string(&["--go", "apply", undo.to_str().unwrap()]).unwrap();
+    for file in &files {
+        let name = Path::new(file).file_name().unwrap().to_str().unwrap();
+        assert_eq!(std::fs::read_to_string(file).unwrap(), name);
+    }
+    assert!(!dir.path().join("out-0").exists());
+}
+
+#[test]
+fn undo_log_is_saved_when_reporting_fails() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["x", "y"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+    let undo = dir.path().join("undo.json");
+    let undo_log = format!("--undo-log={}", undo.display());
+    let report = format!(
+        "--report={}",
+        dir.path().join("missing/report.json").display()
+    );
+
+    let mut args = vec!["--go", &report, &undo_log, &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    let err = run_to_string(&args).unwrap_err();
+    assert!(format!("{err}").contains("failed to write report"), "{err}");
+    assert_eq!(renamed_originals(dir.path(), 2), ["x", "y"]);
+
+    run_to_string(&["--go", "apply", undo.to_str().unwrap()]).unwrap();
+    assert_eq!(std::fs::read_to_string(&files[0]).unwrap(), "x");
+}
+
+#[test]
+fn fail_fast_stops_at_first_failure() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "c"]);
+    let missing = dir.path().join("b");
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let args = [
+        "--go",
+        "--fail-fast",
+        &spec,
+        "from-files",
+        &files[0],
+        missing.to_str().unwrap(),
+        &files[1],
+    ];
+    let err = run_to_string(&args).unwrap_err();
+    assert!(
+        format!("{err:?}").contains(&format!("{missing:?}")),
+        "{err:?}"
+    );
+    assert!(dir.path().join("out-0").exists());
+    assert!(!dir.path().join("out-2").exists());
+    assert!(Path::new(&files[1]).exists());
+}
+
+#[test]
+fn empty_target_names_are_rejected() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b"]);
+    let spec = format!("{}/{{ext}}", dir.path().display());
+
+    let mut args = vec!["--go", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join("jpg").exists());
+    assert!(dir.path().join("b").exists());
+}
+
+#[test]
+fn injected_separators_warn() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    let manifest = dir.path().join("names.txt");
+    std::fs::write(&manifest, "first\nsub/second\n").unwrap();
+    let manifest = manifest.to_str().unwrap();
+
+    let mut args = vec!["--go", "--names-from", manifest, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join("b").exists());
+
+    args.insert(0, "--allow-warnings");
+    run_to_string(&args).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("sub/second")).unwrap(),
+        "b"
+    );
+}
+
+#[test]
+fn strict_names_apply_windows_rules() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    let spec = format!("{}/take:{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--strict-names", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join("a").exists());
+
+    #[cfg(not(windows))]
+    {
+        args.remove(1);
+        run_to_string(&args).unwrap();
+        assert!(dir.path().join("take:0").exists());
+
+        // Only the names of files and directories being created are checked.
+        std::fs::create_dir(dir.path().join("old:dir")).unwrap();
+        let files = touch_all(dir.path(), ["c"]);
+        let run = |spec: &str| {
+            let spec = format!("{}/{spec}", dir.path().display());
+            run_to_string(&[
+                "--go",
+                "--strict-names",
+                "--mkdirs",
+                &spec,
+                "from-files",
+                &files[0],
+            ])
+        };
+        assert!(run("new:dir/{padded_idx}").is_err());
+        run("old:dir/{padded_idx}").unwrap();
+        assert!(dir.path().join("old:dir/0").exists());
+    }
+}
+
+#[test]
+fn existing_targets_are_not_clobbered() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b"]);
+    touch_all(dir.path(), ["out-1"]);
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+    assert!(dir.path().join("a").exists());
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("out-1")).unwrap(),
+        "out-1"
+    );
+
+    let overridden = ["--clobber", "--no-clobber"]
+        .into_iter()
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>();
+    assert!(run_to_string(&overridden).is_err());
+
+    args.splice(0..0, ["--clobber", "--format", "summary-json"]);
+    let summary: serde_json::Value = serde_json::from_str(&run_to_string(&args).unwrap()).unwrap();
+    assert_eq!(summary["conflicts"], 1);
+    assert_eq!(renamed_originals(dir.path(), 2), ["a", "b"]);
+}
+
+#[test]
+fn reset_every_restarts_indices() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a", "b", "c", "d", "e"]);
+    let spec = format!("{}/{{name}}-{{idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--reset-every", "2", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in ["a-0", "b-1", "c-0", "d-1", "e-0"] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+}
+
+#[test]
+fn reset_per_dir_numbers_each_dir() {
+    let dir = test_dir();
+    let mut files = Vec::new();
+    for (album, count) in [("album-a", 2), ("album-b", 11)] {
+        let album = dir.path().join(album);
+        std::fs::create_dir(&album).unwrap();
+        let names = (0..count)
+            .map(|idx| format!("img{idx:02}.jpg"))
+            .collect::<Vec<_>>();
+        files.extend(touch_all(&album, names.iter().map(|n| n.as_str())));
+    }
+
+    let mut args = vec![
+        "--go",
+        "--reset-per-dir",
+        "{group}/{padded_idx}.jpg",
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in [
+        "album-a/0.jpg",
+        "album-a/1.jpg",
+        "album-b/00.jpg",
+        "album-b/10.jpg",
+    ] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("album-b/10.jpg")).unwrap(),
+        "img10.jpg"
+    );
+}
+
+#[test]
+fn dir_idx_counts_each_dir() {
+    let dir = test_dir();
+    let mut files = Vec::new();
+    for (album, count) in [("album-a", 3), ("album-b", 12)] {
+        let album = dir.path().join(album);
+        std::fs::create_dir(&album).unwrap();
+        let names = (0..count)
+            .map(|idx| format!("{idx:02}.jpg"))
+            .collect::<Vec<_>>();
+        files.extend(touch_all(&album, names.iter().map(|n| n.as_str())));
+    }
+    let spec = format!(
+        "{}/{{padded_idx}}-{{padded_dir_idx}}.jpg",
+        dir.path().display()
+    );
+
+    let mut args = vec!["--go", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    let mut names = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".jpg"))
+        .collect::<Vec<_>>();
+    names.sort();
+    let expected = (0..3)
+        .map(|idx| format!("{idx:02}-{idx}.jpg"))
+        .chain((0..12).map(|idx| format!("{:02}-{idx:02}.jpg", idx + 3)))
+        .collect::<Vec<_>>();
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn dir_idx_is_rejected_with_chosen_indices() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.jpg", "b.jpg"]);
+    let spec = format!("{}/{{dir_idx}}.jpg", dir.path().display());
+    let sidecar = format!(
+        "--sidecar-index={}",
+        dir.path().join("index.json").display()
+    );
+
+    for flag in ["--fill-gaps", &sidecar] {
+        let mut args = vec!["--go", flag, &spec, "from-files"];
+        args.extend(files.iter().map(|f| f.as_str()));
+        assert!(run_to_string(&args).is_err(), "{flag}");
+    }
+    assert!(dir.path().join("a.jpg").exists());
+}
+
+#[test]
+fn match_captures_from_names() {
+    let dir = test_dir();
+    let files = touch_all(
+        dir.path(),
+        ["IMG_20230101_a.jpg", "IMG_20230102_b.jpg", "notes.txt"],
+    );
+    let spec = format!("{}/{{cap1}}-{{padded_idx}}.jpg", dir.path().display());
+
+    let mut args = vec!["--match", r"^IMG_(\d+)_", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    assert!(run_to_string(&args).is_err());
+
+    args.splice(0..0, ["--go", "--skip-unmatched"]);
+    run_to_string(&args).unwrap();
+    for name in ["20230101-0.jpg", "20230102-1.jpg", "notes.txt"] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+}
+
+#[test]
+fn total_counts_selected_files() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["a.png", "b.png", "c.png", "notes.txt"]);
+    let spec = format!("{}/page-{{idx1}}-of-{{total}}.png", dir.path().display());
+
+    let mut args = vec!["--go", "--filter-ext", "png", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in [
+        "page-1-of-3.png",
+        "page-2-of-3.png",
+        "page-3-of-3.png",
+        "notes.txt",
+    ] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+}
+
+#[test]
+fn case_modifiers_change_source_names() {
+    let dir = test_dir();
+    let files = touch_all(dir.path(), ["IMG_A.JPG", "IMG_B.JPG"]);
+    let spec = format!(
+        "{}/{{name:lower}}-{{idx}}.{{ext:lower}}",
+        dir.path().display()
+    );
+
+    let mut args = vec!["--go", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    for name in ["img_a-0.jpg", "img_b-1.jpg"] {
+        assert!(dir.path().join(name).is_file(), "{name} missing");
+    }
+
+    let spec = format!("{}/{{idx:upper}}", dir.path().display());
+    assert!(run_to_string(&[&spec, "from-files", &files[0]]).is_err());
+}
+
+#[test]
+fn summary_counts_failures() {
+    let dir = test_dir();
+    std::fs::create_dir(dir.path().join("kept")).unwrap();
+    let files = touch_all(dir.path(), ["kept-a", "gone-b"]);
+    let spec = format!("{}/{{cap1}}/out-{{idx}}", dir.path().display());
+
+    // `gone` doesn't exist, and isn't created without `--mkdirs`.
+    let mut args = vec![
+        "rename-seq",
+        "--go",
+        "--format",
+        "summary-json",
+        "--match",
+        r"^(\w+)-",
+        &spec,
+        "from-files",
+    ];
+    args.extend(files.iter().map(|f| f.as_str()));
+    let mut stdout = Vec::new();
+    let res = run(MainArgs::try_parse_from(args).unwrap(), &mut stdout);
+    assert!(res.is_err());
+    let summary: serde_json::Value = serde_json::from_slice(&stdout).unwrap();
+    assert_eq!(summary["renamed"], 1);
+    assert_eq!(summary["failed"], 1);
+    assert!(dir.path().join("kept/out-0").is_file());
+    assert!(dir.path().join("gone-b").is_file());
+}
+
+#[test]
+fn limit_renames_only_the_first_files() {
+    let dir = test_dir();
+    let names = (0..100).map(|n| format!("f{n:03}")).collect::<Vec<_>>();
+    let files = touch_all(dir.path(), names.iter().map(|n| n.as_str()));
+    let spec = format!("{}/out-{{padded_idx}}", dir.path().display());
+
+    let mut args = vec!["--go", "--limit", "3", &spec, "from-files"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    run_to_string(&args).unwrap();
+    // Padded to the width of the limited count, rather than that of all 100 files.
+    assert_eq!(renamed_originals(dir.path(), 3), ["f000", "f001", "f002"]);
+    assert!(!dir.path().join("out-00").exists());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 100);
+    assert!(dir.path().join("f003").is_file());
+}
+
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_selects_linked_files() {
+    let dir = test_dir();
+    for sub in ["library", "links"] {
+        std::fs::create_dir(dir.path().join(sub)).unwrap();
+    }
+    touch_all(&dir.path().join("library"), ["real.jpg"]);
+    touch_all(&dir.path().join("links"), ["plain.jpg"]);
+    std::os::unix::fs::symlink("../library/real.jpg", dir.path().join("links/linked.jpg")).unwrap();
+    let links = dir.path().join("links");
+    let links = links.to_str().unwrap();
+
+    let count = |follow: &[&str]| {
+        let args = ["--count-only", "out-{padded_idx}", "from-glob"];
+        let args = [&args[..], follow, &["--base-dir", links, "**/*.jpg"]].concat();
+        run_to_string(&args).map(|count| count.trim().to_owned())
+    };
+    assert_eq!(count(&[]).unwrap(), "1");
+    assert_eq!(count(&["--follow-symlinks"]).unwrap(), "2");
+
+    // A cycle is reported, rather than followed forever.
+    std::os::unix::fs::symlink("..", dir.path().join("links/parent")).unwrap();
+    assert!(count(&["--follow-symlinks"]).is_err());
+    assert_eq!(count(&[]).unwrap(), "1");
 }
@@ -0,0 +1,168 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use snafu::Snafu;
+use std::{fmt, str::FromStr};
+
+/// The order in which files are numbered, relative to the order in which they were selected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Order {
+    /// Files are numbered in the order they were selected.
+    #[default]
+    Sequential,
+    /// Files are numbered alternately from either end of the selection, as with [`ZigZag`].
+    SingleSidedScans,
+    /// Files are numbered in a random order, which is the same between runs with the same seed.
+    Shuffle,
+}
+
+impl Order {
+    /// Every order, by the name parsed by [`FromStr`].
+    pub const BY_NAME: &'static [(&'static str, Self)] = &[
+        ("sequential", Self::Sequential),
+        ("single-sided-scans", Self::SingleSidedScans),
+        ("shuffle", Self::Shuffle),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        Self::BY_NAME
+            .iter()
+            .find(|(_name, order)| order == self)
+            .expect("order is missing from `BY_NAME`")
+            .0
+    }
+
+    /// Rearranges `iter`, in which items are in the order they were selected, into this order.
+    /// [`Self::Shuffle`] is seeded with `seed`, if given, or randomly otherwise.
+    ///
+    /// ```
+    /// use rename_seq::Order;
+    ///
+    /// let scans = Order::SingleSidedScans.apply(1..=4, None).collect::<Vec<_>>();
+    /// assert_eq!(scans, [1, 4, 2, 3]);
+    /// ```
+    pub fn apply<'a, I>(self, iter: I, seed: Option<u64>) -> Box<dyn Iterator<Item = I::Item> + 'a>
+    where
+        I: DoubleEndedIterator + 'a,
+    {
+        match self {
+            Self::Sequential => Box::new(iter),
+            Self::SingleSidedScans => Box::new(ZigZag::new(iter)),
+            Self::Shuffle => {
+                let mut rng = match seed {
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                    None => StdRng::from_entropy(),
+                };
+                let mut items = iter.collect::<Vec<_>>();
+                items.shuffle(&mut rng);
+                Box::new(items.into_iter())
+            }
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = ParseOrderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::BY_NAME
+            .iter()
+            .find(|(name, _order)| *name == s)
+            .map(|(_name, order)| *order)
+            .ok_or_else(|| ParseOrderError { name: s.to_owned() })
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display(
+    "unrecognized order `{name}`; expected one of {:?}",
+    Order::BY_NAME.iter().map(|(name, _order)| name).collect::<Vec<_>>()
+))]
+pub struct ParseOrderError {
+    name: String,
+}
+
+/// An iterator adapter that alternates between the front and back of a double-ended iterator,
+/// starting from the front.
+///
+/// For `n` items, this yields the items at indices `0`, `n - 1`, `1`, `n - 2`, and so on, like
+/// scanning both sides of a stack of pages without flipping it over:
+///
+/// ```
+/// use rename_seq::ZigZag;
+///
+/// let pages = ZigZag::new(0..6).collect::<Vec<_>>();
+/// assert_eq!(pages, [0, 5, 1, 4, 2, 3]);
+///
+/// let pages = ZigZag::new(0..5).collect::<Vec<_>>();
+/// assert_eq!(pages, [0, 4, 1, 3, 2]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ZigZag<I> {
+    next: ForwardOrBackward,
+    inner: I,
+}
+
+impl<I> ZigZag<I>
+where
+    I: DoubleEndedIterator,
+{
+    pub fn new(iter: I) -> Self {
+        Self {
+            next: ForwardOrBackward::Forward,
+            inner: iter,
+        }
+    }
+}
+
+impl<I> Iterator for ZigZag<I>
+where
+    I: DoubleEndedIterator,
+{
+    type Item = I::Item;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { next, inner } = self;
+
+        let item;
+        match *next {
+            ForwardOrBackward::Forward => {
+                item = inner.next()?;
+                *next = ForwardOrBackward::Backward;
+            }
+            ForwardOrBackward::Backward => {
+                item = inner.next_back()?;
+                *next = ForwardOrBackward::Forward;
+            }
+        }
+        Some(item)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ForwardOrBackward {
+    Forward,
+    Backward,
+}
+
+#[test]
+fn shuffle_is_seeded() {
+    let shuffle = |seed| Order::Shuffle.apply(0..100, seed).collect::<Vec<_>>();
+
+    let shuffled = shuffle(Some(1));
+    assert_eq!(shuffled, shuffle(Some(1)));
+    assert_ne!(shuffled, shuffle(Some(2)));
+    assert_ne!(shuffled, (0..100).collect::<Vec<_>>());
+
+    let mut sorted = shuffle(None);
+    sorted.sort();
+    assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+}
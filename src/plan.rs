@@ -0,0 +1,1374 @@
+use crate::exec::LinePrompt;
+use color_eyre::eyre::{self, bail, eyre, WrapErr};
+use rename_seq::{
+    zip_counted, zip_grouped, zip_indexed, DynamicRenameContent, IndexField, RenameSpec,
+    RenderError, Transform, TransformContext, Visitor,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::Infallible,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, Read, Seek, Write},
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::{Component, Path, PathBuf},
+};
+
+/// Zips `files` with `rename_spec` into `visitor`, numbering each of `groups` separately, if
+/// specified.
+pub fn zip<V>(
+    files: &[&Path],
+    groups: Option<&[String]>,
+    rename_spec: RenameSpec,
+    visitor: V,
+) -> Result<(), V::Error>
+where
+    V: Visitor,
+{
+    match groups {
+        None => zip_counted(files.iter().copied(), files.len(), rename_spec, visitor),
+        Some(groups) => {
+            let files = files
+                .iter()
+                .copied()
+                .zip(groups.iter().map(|g| g.as_str()))
+                .collect::<Vec<_>>();
+            zip_grouped(&files, rename_spec, visitor)
+        }
+    }
+}
+
+/// Zips `files` with `rename_spec` into `plan`, giving files that don't already have a name
+/// rendered by `rename_spec` the smallest indices not yet used by one, in order, starting from
+/// the smallest used index (or the [start](RenameSpec::with_start_at) of the spec, if none are).
+///
+/// Used indices are found among `files`, and the other files in the directory of the first
+/// target. Indices are padded to at least as many digits as the widest used index.
+pub fn zip_filling_gaps(
+    files: &[&Path],
+    rename_spec: RenameSpec,
+    plan: &mut PlanCollector,
+) -> eyre::Result<()> {
+    if rename_spec
+        .dynamic_content()
+        .any(|c| !matches!(c, DynamicRenameContent::PaddedInteger))
+    {
+        bail!("filling gaps requires `{{padded_idx}}` to be the only kind of replacement group");
+    }
+
+    let mut used = BTreeSet::new();
+    let mut width = 0;
+    let mut indexed = files
+        .iter()
+        .map(|&from| {
+            let idx = rename_spec.parse_index(from).map(|(idx, digits)| {
+                used.insert(idx);
+                width = width.max(digits);
+                idx
+            });
+            (from, idx)
+        })
+        .collect::<Vec<_>>();
+
+    let mut first = PlanCollector::default();
+    if let Some(&from) = files.first() {
+        let Ok(()) = zip_indexed(&[(from, 0)], rename_spec.clone(), &mut first);
+    }
+    let target_dir = first
+        .renames()
+        .next()
+        .and_then(|(_from, to)| to.parent())
+        .map(|dir| dir.to_owned());
+    if let Some(dir) = target_dir {
+        let read_dir = if dir.as_os_str().is_empty() {
+            fs::read_dir(".")
+        } else {
+            fs::read_dir(&dir)
+        };
+        match read_dir {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry.wrap_err_with(|| format!("failed to list {dir:?}"))?;
+                    if let Some((idx, digits)) =
+                        rename_spec.parse_index(&dir.join(entry.file_name()))
+                    {
+                        used.insert(idx);
+                        width = width.max(digits);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).wrap_err_with(|| format!("failed to list {dir:?}")),
+        }
+    }
+
+    let lowest = used.first().copied().unwrap_or(0);
+    let mut free = (lowest..).filter(|idx| !used.contains(idx));
+    let indexed = indexed
+        .iter_mut()
+        .map(|(from, idx)| (*from, *idx.get_or_insert_with(|| free.next().unwrap())))
+        .collect::<Vec<_>>();
+
+    let Ok(()) = zip_indexed(&indexed, rename_spec.with_min_padding(width), plan);
+    Ok(())
+}
+
+/// Plans renaming each of `files` from the name that `current` rendered for its index back to the
+/// name that `original` renders for the same index.
+///
+/// Both specs must use `{padded_idx}` as their only kind of replacement group. Original names are
+/// padded to as many digits as the widest index in `files`.
+pub fn reverse_spec(
+    files: &[&Path],
+    current: &RenameSpec,
+    original: RenameSpec,
+) -> eyre::Result<PlanCollector> {
+    if original
+        .dynamic_content()
+        .any(|c| !matches!(c, DynamicRenameContent::PaddedInteger))
+    {
+        bail!("reversing requires `{{padded_idx}}` to be the only kind of replacement group");
+    }
+
+    let mut width = 0;
+    let mut unmatched = Vec::new();
+    let indexed = files
+        .iter()
+        .filter_map(|&from| match current.parse_index(from) {
+            Some((idx, digits)) => {
+                width = width.max(digits);
+                Some((from, idx))
+            }
+            None => {
+                unmatched.push(from);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if !unmatched.is_empty() {
+        bail!("the following file names were not rendered by the rename spec: {unmatched:?}");
+    }
+
+    let mut plan = PlanCollector::default();
+    let Ok(()) = zip_indexed(&indexed, original.with_min_padding(width), &mut plan);
+    Ok(plan)
+}
+
+/// Zips `files` with `rename_spec` into `plan`, giving files that differ only in their extension
+/// (e.g., `doc.pdf` and `doc.docx`) the same index. Each distinct stem is numbered in the order it
+/// first appears among `files`.
+pub fn zip_by_stem(files: &[&Path], rename_spec: RenameSpec, plan: &mut PlanCollector) {
+    let mut stems = HashMap::new();
+    let indexed = files
+        .iter()
+        .map(|&from| {
+            let next = stems.len();
+            let idx = *stems.entry(from.with_extension("")).or_insert(next);
+            (from, idx)
+        })
+        .collect::<Vec<_>>();
+    let Ok(()) = zip_indexed(&indexed, rename_spec, plan);
+}
+
+/// The indices assigned to files in earlier runs, by their original file names, as saved in a
+/// sidecar file so that they stay stable as files are added or removed.
+#[derive(Debug, Default)]
+pub struct SidecarIndex {
+    pub indices: BTreeMap<String, usize>,
+}
+
+impl SidecarIndex {
+    pub const DEFAULT_PATH: &'static str = ".rename-seq-index.json";
+
+    /// Reads the sidecar at `path`, or returns an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let value: serde_json::Value = serde_json::from_slice(&contents)?;
+        let indices = value
+            .get("indices")
+            .and_then(|indices| indices.as_object())
+            .ok_or_else(|| eyre!("expected an `indices` object"))?
+            .iter()
+            .map(|(name, idx)| {
+                let idx = idx
+                    .as_u64()
+                    .and_then(|idx| usize::try_from(idx).ok())
+                    .ok_or_else(|| eyre!("expected an index for {name:?}, got {idx}"))?;
+                Ok((name.clone(), idx))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self { indices })
+    }
+
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        let mut contents =
+            serde_json::to_string_pretty(&serde_json::json!({ "indices": self.indices }))?;
+        contents.push('\n');
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+/// The padding width chosen by the first of several runs, as saved in a lock file so that later
+/// runs pad indices alike.
+#[derive(Clone, Copy, Debug)]
+pub struct WidthLock {
+    pub width: usize,
+}
+
+impl WidthLock {
+    /// Reads the lock at `path`, if it exists.
+    pub fn load(path: &Path) -> eyre::Result<Option<Self>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let value: serde_json::Value = serde_json::from_slice(&contents)?;
+        let width = value
+            .get("width")
+            .and_then(|width| width.as_u64())
+            .and_then(|width| usize::try_from(width).ok())
+            .ok_or_else(|| eyre!("expected a `width` number"))?;
+        Ok(Some(Self { width }))
+    }
+
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        let mut contents =
+            serde_json::to_string_pretty(&serde_json::json!({ "width": self.width }))?;
+        contents.push('\n');
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+/// A counter of files renamed by every run sharing a counter file, so that `{global_idx}` never
+/// repeats. The file stays locked until this is dropped, making concurrent runs wait their turn.
+#[derive(Debug)]
+pub struct GlobalCounter {
+    file: fs::File,
+    pub start: usize,
+}
+
+impl GlobalCounter {
+    /// Locks the counter file at `path`, creating it if it doesn't exist (starting from `0`), and
+    /// reads where this run starts counting.
+    pub fn lock(path: &Path) -> eyre::Result<Self> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.lock()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let start = match contents.trim() {
+            "" => 0,
+            count => count
+                .parse()
+                .wrap_err_with(|| format!("expected a count, got {count:?}"))?,
+        };
+        Ok(Self { file, start })
+    }
+
+    /// Records that `count` more files were renamed, so that the next run starts after them.
+    pub fn advance(mut self, count: usize) -> eyre::Result<()> {
+        let end = self
+            .start
+            .checked_add(count)
+            .ok_or_else(|| eyre!("counting {count} more files from {} overflows", self.start))?;
+        self.file.set_len(0)?;
+        self.file.seek(io::SeekFrom::Start(0))?;
+        writeln!(self.file, "{end}")?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Zips `files` with `rename_spec` into `plan`, reusing the indices recorded in `sidecar` for
+/// files seen in earlier runs, and recording the indices of new files.
+///
+/// A file is recognized either by its original name, or by already having the name that
+/// `rename_spec` renders for a recorded index. New files get the smallest indices not in use.
+/// Indices of recorded files that are no longer selected stay reserved, unless `reuse_freed` is
+/// set, in which case they are forgotten.
+pub fn zip_with_sidecar(
+    files: &[&Path],
+    rename_spec: RenameSpec,
+    sidecar: &mut SidecarIndex,
+    reuse_freed: bool,
+    plan: &mut PlanCollector,
+) -> eyre::Result<()> {
+    let names_by_idx = sidecar
+        .indices
+        .iter()
+        .map(|(name, &idx)| (idx, name.clone()))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut seen = BTreeSet::new();
+    let mut indexed = Vec::with_capacity(files.len());
+    for &from in files {
+        let name = from
+            .file_name()
+            .unwrap_or_default()
+            .to_str()
+            .ok_or_else(|| eyre!("file name of {from:?} is not valid UTF-8"))?;
+        let recorded = match sidecar.indices.get(name) {
+            Some(&idx) => Some((name.to_owned(), idx)),
+            None => rename_spec
+                .parse_index(from)
+                .and_then(|(idx, _digits)| Some((names_by_idx.get(&idx)?.clone(), idx))),
+        };
+        match recorded {
+            Some((original, idx)) => {
+                seen.insert(original);
+                indexed.push((from, Some(idx)));
+            }
+            None => indexed.push((from, None)),
+        }
+    }
+
+    if reuse_freed {
+        sidecar.indices.retain(|name, _idx| seen.contains(name));
+    }
+    let used = sidecar.indices.values().copied().collect::<BTreeSet<_>>();
+    let lowest = used.first().copied().unwrap_or(0);
+    let mut free = (lowest..).filter(|idx| !used.contains(idx));
+    let indexed = indexed
+        .into_iter()
+        .map(|(from, idx)| {
+            let idx = idx.unwrap_or_else(|| {
+                let idx = free.next().unwrap();
+                let name = from.file_name().unwrap_or_default().to_string_lossy();
+                sidecar.indices.insert(name.into_owned(), idx);
+                idx
+            });
+            (from, idx)
+        })
+        .collect::<Vec<_>>();
+
+    let Ok(()) = zip_indexed(&indexed, rename_spec, plan);
+    Ok(())
+}
+
+/// Plans renaming each of `files` so that the [`IndexField`] varying between their names is
+/// zero-padded to `width` digits, or the width of the
+/// largest existing value (ignoring leading zeros) if `None`.
+pub fn repad_index_field(
+    files: &[&Path],
+    width: Option<usize>,
+) -> eyre::Result<Vec<(PathBuf, PathBuf)>> {
+    let names = files
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .unwrap_or_default()
+                .to_str()
+                .ok_or_else(|| eyre!("file name of {path:?} is not valid UTF-8"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let Some(field) = IndexField::find(names.iter().copied()) else {
+        bail!("found no run of digits that varies between selected file names");
+    };
+    let width = width.unwrap_or_else(|| {
+        names
+            .iter()
+            .filter_map(|name| field.locate(name).map(|range| &name[range]))
+            .map(|digits| digits.trim_start_matches('0').len().max(1))
+            .max()
+            .unwrap_or(1)
+    });
+
+    Ok(files
+        .iter()
+        .zip(names)
+        .map(|(from, name)| {
+            // Every name has the field, since it was found among them.
+            let to = from.with_file_name(field.repad(name, width).unwrap());
+            (from.to_path_buf(), to)
+        })
+        .collect())
+}
+
+/// Plans renaming `files` to the names of the files directly inside `reference`, sorted
+/// lexicographically, by position. Each file keeps its own extension, and stays in its own
+/// directory.
+///
+/// It is an error for `reference` and `files` to have different numbers of files, unless
+/// `allow_mismatch` is set, in which case the longer of the two is truncated.
+pub fn mirror(
+    files: &[&Path],
+    reference: &Path,
+    allow_mismatch: bool,
+) -> eyre::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(reference)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(PathBuf::from(entry.file_name()));
+        }
+    }
+    names.sort();
+
+    let names = match_counts(files, names, allow_mismatch, || {
+        format!("reference directory {reference:?}")
+    })?;
+    Ok(files
+        .iter()
+        .zip(names)
+        .map(|(from, name)| {
+            let mut to = from.with_file_name(name.file_stem().unwrap_or_default());
+            if let Some(ext) = from.extension() {
+                let mut name = to.into_os_string();
+                name.push(".");
+                name.push(ext);
+                to = name.into();
+            }
+            (from.to_path_buf(), to)
+        })
+        .collect())
+}
+
+/// Plans renaming `files` to the names listed in `manifest`, one per line, by position. Names are
+/// relative to each file's own directory, and blank lines are ignored.
+///
+/// Counts must match as with [`mirror`].
+pub fn names_from(
+    files: &[&Path],
+    manifest: &Path,
+    allow_mismatch: bool,
+) -> eyre::Result<Vec<(PathBuf, PathBuf)>> {
+    let names = fs::read_to_string(manifest)?
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+
+    let names = match_counts(files, names, allow_mismatch, || format!("{manifest:?}"))?;
+    Ok(files
+        .iter()
+        .zip(names)
+        .map(|(from, name)| (from.to_path_buf(), from.with_file_name(name)))
+        .collect())
+}
+
+/// Checks that there are as many `names` as `files`, returning them truncated to the shorter of
+/// the two if they differ and `allow_mismatch` is set.
+fn match_counts(
+    files: &[&Path],
+    mut names: Vec<PathBuf>,
+    allow_mismatch: bool,
+    source: impl FnOnce() -> String,
+) -> eyre::Result<Vec<PathBuf>> {
+    if names.len() != files.len() {
+        let source = source();
+        if !allow_mismatch {
+            bail!(
+                "{} file(s) were selected, but {source} has {} name(s); use `--allow-mismatch` to \
+                rename only as many as both have",
+                files.len(),
+                names.len(),
+            );
+        }
+        tracing::warn!(
+            "renaming only {} of {} selected file(s) to match {source}",
+            names.len().min(files.len()),
+            files.len(),
+        );
+        names.truncate(files.len());
+    }
+    Ok(names)
+}
+
+/// A [`Visitor`] that records each planned rename, rather than performing it, so that the
+/// entire plan can be checked before any file is touched.
+#[derive(Debug, Default)]
+pub struct PlanCollector {
+    pub plan: Vec<(PathBuf, Result<PathBuf, RenderError>)>,
+}
+
+impl PlanCollector {
+    /// Iterates over planned renames whose targets were rendered successfully.
+    pub fn renames(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.plan
+            .iter()
+            .filter_map(|(from, to)| Some((from.as_path(), to.as_ref().ok()?.as_path())))
+    }
+
+    /// Writes the planned renames to `writer` as JSON, in the form
+    /// `{"renames": [{"from": "…", "to": "…"}, …]}`.
+    ///
+    /// Fails if any target could not be rendered, or any path is not valid UTF-8.
+    pub fn save(&self, mut writer: impl Write) -> eyre::Result<()> {
+        let to_str = |path: &Path| {
+            path.to_str()
+                .ok_or_else(|| eyre!("path {path:?} is not valid UTF-8"))
+                .map(|s| s.to_owned())
+        };
+        let renames = self
+            .plan
+            .iter()
+            .map(|(from, to)| {
+                let to = to
+                    .as_ref()
+                    .map_err(|e| eyre!("failed to determine target path of {from:?}: {e}"))?;
+                Ok(serde_json::json!({ "from": to_str(from)?, "to": to_str(to)? }))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        serde_json::to_writer_pretty(&mut writer, &serde_json::json!({ "renames": renames }))?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a plan written by [`Self::save`].
+    pub fn load(reader: impl Read) -> eyre::Result<Self> {
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        let renames = value
+            .get("renames")
+            .and_then(|renames| renames.as_array())
+            .ok_or_else(|| eyre!("expected a `renames` array"))?;
+        let plan = renames
+            .iter()
+            .enumerate()
+            .map(|(idx, rename)| {
+                let path = |key| {
+                    rename
+                        .get(key)
+                        .and_then(|path| path.as_str())
+                        .map(PathBuf::from)
+                        .ok_or_else(|| eyre!("expected a `{key}` string in rename {idx}"))
+                };
+                Ok((path("from")?, Ok(path("to")?)))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self { plan })
+    }
+
+    /// Writes the planned renames to `writer` as a JSON array, in the form `[{"index": n, "from":
+    /// …, "to": …}, …]`, where `index` is each rename's position in the plan.
+    ///
+    /// Unlike [`Self::save`], this never fails on account of the plan: targets that could not be
+    /// rendered are `null`, with an `"error"` describing why, and paths that aren't valid UTF-8
+    /// are written [losslessly](path_to_json).
+    pub fn write_json(&self, mut writer: impl Write) -> io::Result<()> {
+        let renames = self
+            .plan
+            .iter()
+            .enumerate()
+            .map(|(index, (from, to))| match to {
+                Ok(to) => serde_json::json!({
+                    "index": index,
+                    "from": path_to_json(from),
+                    "to": path_to_json(to),
+                }),
+                Err(e) => serde_json::json!({
+                    "index": index,
+                    "from": path_to_json(from),
+                    "to": null,
+                    "error": e.to_string(),
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::to_writer_pretty(&mut writer, &renames)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    /// Writes the planned renames to `writer` as a table of aligned index, source, and target
+    /// columns, optionally preceded by a `header` row and with a separator line every `ruler`
+    /// rows. Sources and targets are separated by `->` if `arrows` is set.
+    pub fn write_table(
+        &self,
+        header: bool,
+        ruler: Option<NonZeroUsize>,
+        arrows: bool,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let rows = self
+            .plan
+            .iter()
+            .enumerate()
+            .map(|(idx, (from, to))| {
+                let to = match to {
+                    Ok(to) => to.display().to_string(),
+                    Err(e) => format!("<error: {e}>"),
+                };
+                (idx.to_string(), from.display().to_string(), to)
+            })
+            .collect::<Vec<_>>();
+        let header = header.then(|| ("#".to_owned(), "FROM".to_owned(), "TO".to_owned()));
+
+        let width = |column: fn(&(String, String, String)) -> &String| {
+            header
+                .iter()
+                .chain(&rows)
+                .map(|row| column(row).chars().count())
+                .max()
+                .unwrap_or(0)
+        };
+        let idx_width = width(|row| &row.0);
+        let from_width = width(|row| &row.1);
+        let to_width = width(|row| &row.2);
+        let separator = if arrows { " -> " } else { "  " };
+        let write_row = |writer: &mut dyn Write, (idx, from, to): &(String, String, String)| {
+            writeln!(
+                writer,
+                "{idx:>idx_width$}  {from:<from_width$}{separator}{to}"
+            )
+        };
+        let rule = "-".repeat(idx_width + 2 + from_width + separator.len() + to_width);
+
+        if let Some(header) = &header {
+            write_row(&mut writer, header)?;
+            writeln!(writer, "{rule}")?;
+        }
+        for (idx, row) in rows.iter().enumerate() {
+            if let Some(ruler) = ruler {
+                if idx > 0 && idx % ruler.get() == 0 {
+                    writeln!(writer, "{rule}")?;
+                }
+            }
+            write_row(&mut writer, row)?;
+        }
+        writer.flush()
+    }
+
+    /// Splits the plan into parts that touch disjoint sets of directories, so that they can be
+    /// performed independently of each other (e.g., in parallel).
+    ///
+    /// Renames are grouped with every other rename whose source or target shares a directory with
+    /// their own source or target, so chains and cycles of renames always stay within one part.
+    /// Each part keeps the plan's order.
+    pub fn partition_by_dir(self) -> Vec<Self> {
+        fn root(parents: &mut [usize], mut idx: usize) -> usize {
+            while parents[idx] != idx {
+                parents[idx] = parents[parents[idx]];
+                idx = parents[idx];
+            }
+            idx
+        }
+
+        let dir = |path: &Path| path.parent().unwrap_or(Path::new("")).to_owned();
+        let mut dirs = HashMap::new();
+        let mut parents = Vec::new();
+        let mut dir_idx = |dir: PathBuf, parents: &mut Vec<usize>| {
+            *dirs.entry(dir).or_insert_with(|| {
+                parents.push(parents.len());
+                parents.len() - 1
+            })
+        };
+        let rename_dirs = self
+            .plan
+            .iter()
+            .map(|(from, to)| {
+                let from_dir = dir_idx(dir(from), &mut parents);
+                if let Ok(to) = to {
+                    let to_dir = dir_idx(dir(to), &mut parents);
+                    let (a, b) = (root(&mut parents, from_dir), root(&mut parents, to_dir));
+                    parents[a] = b;
+                }
+                from_dir
+            })
+            .collect::<Vec<_>>();
+
+        let mut parts = Vec::<Self>::new();
+        let mut part_by_root = HashMap::new();
+        for (rename, dir) in self.plan.into_iter().zip(rename_dirs) {
+            let part = *part_by_root
+                .entry(root(&mut parents, dir))
+                .or_insert_with(|| {
+                    parts.push(Self::default());
+                    parts.len() - 1
+                });
+            parts[part].plan.push(rename);
+        }
+        parts
+    }
+
+    /// Feeds the recorded plan into `visitor`, as if it were being zipped again.
+    pub fn replay<V>(self, mut visitor: V) -> Result<(), V::Error>
+    where
+        V: Visitor,
+    {
+        for (idx, (from, to)) in self.plan.into_iter().enumerate() {
+            let flow = match to {
+                Ok(to) => visitor.visit(idx, &from, to),
+                Err(e) => visitor.render_failed(idx, &from, e),
+            };
+            if let ControlFlow::Break(e) = flow {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for PlanCollector {
+    type Error = Infallible;
+
+    fn visit(&mut self, _idx: usize, from: &Path, to: PathBuf) -> ControlFlow<Self::Error> {
+        self.plan.push((from.to_owned(), Ok(to)));
+        ControlFlow::Continue(())
+    }
+
+    fn render_failed(
+        &mut self,
+        _idx: usize,
+        from: &Path,
+        error: RenderError,
+    ) -> ControlFlow<Self::Error> {
+        self.plan.push((from.to_owned(), Err(error)));
+        ControlFlow::Continue(())
+    }
+}
+
+/// Fails renames in `plan` whose final targets are longer than `max` characters, e.g., once
+/// transforms have lengthened what [`RenameSpec::with_max_path_len`] already shortened.
+pub fn fail_long_targets(plan: &mut PlanCollector, max: usize) {
+    for (_from, to) in &mut plan.plan {
+        let len = match to {
+            Ok(path) => path.as_os_str().to_string_lossy().chars().count(),
+            Err(_) => continue,
+        };
+        if len > max {
+            *to = Err(RenderError::PathTooLong { len, max });
+        }
+    }
+}
+
+/// Places each target in `plan` under `dir`, as with [`Path::join`], so that absolute targets are
+/// left as they are.
+pub fn move_into(plan: &mut PlanCollector, dir: &Path) {
+    for (_from, to) in &mut plan.plan {
+        if let Ok(to) = to {
+            *to = dir.join(&*to);
+        }
+    }
+}
+
+/// Renames in `plan` whose targets aren't directly in the directory that `dir_of` expects for
+/// their sources, e.g., because a rendered name contained a path separator.
+pub fn targets_in_other_dirs(
+    plan: &PlanCollector,
+    dir_of: impl Fn(&Path) -> &Path,
+) -> Vec<(&Path, &Path)> {
+    plan.renames()
+        .filter(|&(from, to)| to.parent().unwrap_or(Path::new("")) != dir_of(from))
+        .collect()
+}
+
+/// Rewrites the file name of each target in `plan` with `transforms`, in order.
+///
+/// File names that aren't valid UTF-8 are left as they are.
+pub fn apply_transforms(plan: &mut PlanCollector, transforms: &[&dyn Transform]) {
+    if transforms.is_empty() {
+        return;
+    }
+    let count = plan.plan.len();
+    for (idx, (_from, to)) in plan.plan.iter_mut().enumerate() {
+        let Ok(to) = to else { continue };
+        let Some(name) = to.file_name() else { continue };
+        let Some(name) = name.to_str() else {
+            tracing::warn!("not transforming {to:?}, whose file name is not valid UTF-8");
+            continue;
+        };
+        let ctx = TransformContext { idx, count };
+        let name = transforms
+            .iter()
+            .fold(name.to_owned(), |name, t| t.apply(&name, &ctx));
+        to.set_file_name(name);
+    }
+}
+
+/// Represents `path` in JSON as a string, if it's valid UTF-8. Otherwise, it's an object holding its
+/// raw form on this platform: `{"unix_bytes": [n, …]}` on Unix, or `{"windows_wide": [n, …]}`
+/// (i.e., UTF-16 code units) on Windows.
+pub fn path_to_json(path: &Path) -> serde_json::Value {
+    if let Some(path) = path.to_str() {
+        return path.into();
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        serde_json::json!({ "unix_bytes": path.as_os_str().as_bytes() })
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+
+        serde_json::json!({ "windows_wide": path.as_os_str().encode_wide().collect::<Vec<_>>() })
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        path.to_string_lossy().into()
+    }
+}
+
+/// Finds targets of `renames` shared by more than one source, along with those sources.
+///
+/// Sources whose rename would be a no-op are included, since they occupy their target.
+pub fn target_collisions<'a>(
+    renames: impl Iterator<Item = (&'a Path, &'a Path)>,
+) -> Vec<(&'a Path, Vec<&'a Path>)> {
+    let mut sources_by_target = BTreeMap::<_, Vec<_>>::new();
+    for (from, to) in renames {
+        sources_by_target.entry(to).or_default().push(from);
+    }
+    sources_by_target
+        .into_iter()
+        .filter(|(_to, froms)| froms.len() > 1)
+        .collect()
+}
+
+/// Checks that `renames` can still be performed against the current state of the file system
+/// (e.g., when applying a plan saved earlier).
+///
+/// Every source must exist, and no target may exist unless it is also a source. Targets that are
+/// also sources (e.g., chains like `a` to `b` and `b` to `c`, or cycles) are only permitted when
+/// renaming in two phases, since otherwise they would be overwritten before being moved.
+pub fn check_applicable<'a>(
+    renames: impl Iterator<Item = (&'a Path, &'a Path)>,
+    two_phase: bool,
+) -> eyre::Result<()> {
+    let renames = renames.filter(|(from, to)| from != to).collect::<Vec<_>>();
+    let sources = renames
+        .iter()
+        .map(|&(from, _to)| from)
+        .collect::<HashSet<_>>();
+
+    let mut problems = Vec::new();
+    for &(from, to) in &renames {
+        if !from.try_exists()? {
+            problems.push(format!("source {from:?} no longer exists"));
+        }
+        if sources.contains(to) {
+            if !two_phase {
+                problems.push(format!(
+                    "target {to:?} is also a source, which requires `--safe`"
+                ));
+            }
+        } else if to.try_exists()? {
+            problems.push(format!("target {to:?} already exists"));
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("{}", problems.join("; "));
+    }
+    Ok(())
+}
+
+/// Renames in `plan` whose targets already exist, but aren't sources in `plan`, so that renaming
+/// would overwrite files that weren't selected. Targets in `overwritten` were already chosen to be
+/// overwritten, e.g., by [`resolve_conflicts`], and aren't included.
+pub fn clobbered<'a>(
+    plan: &'a PlanCollector,
+    overwritten: &HashSet<PathBuf>,
+) -> io::Result<Vec<(&'a Path, &'a Path)>> {
+    let sources = plan
+        .renames()
+        .map(|(from, _to)| from)
+        .collect::<HashSet<_>>();
+    let mut clobbered = Vec::new();
+    for (from, to) in plan.renames() {
+        if crate::exec::is_noop(from, to)
+            || sources.contains(to)
+            || overwritten.contains(to)
+            || !to.try_exists()?
+        {
+            continue;
+        }
+        // Changing only the case of a name finds the source itself on case-insensitive file
+        // systems.
+        let source = fs::canonicalize(from).ok();
+        if source.is_some() && source == fs::canonicalize(to).ok() {
+            continue;
+        }
+        clobbered.push((from, to));
+    }
+    Ok(clobbered)
+}
+
+/// Disambiguates targets shared by more than one source in `plan` by appending each source's
+/// modification time, as `-HHMMSS` in UTC, before the target's extension.
+///
+/// Files modified within the same second are given the same suffix, and so still share a target.
+pub fn append_timestamps(plan: &mut PlanCollector) -> eyre::Result<()> {
+    let colliding = target_collisions(plan.renames())
+        .into_iter()
+        .flat_map(|(_to, froms)| froms)
+        .map(|from| from.to_owned())
+        .collect::<HashSet<_>>();
+
+    for (from, to) in &mut plan.plan {
+        let Ok(to) = to else { continue };
+        if !colliding.contains(from) {
+            continue;
+        }
+
+        let modified = fs::metadata(&*from)
+            .and_then(|metadata| metadata.modified())
+            .wrap_err_with(|| format!("failed to read modification time of {from:?}"))?;
+        // e.g., `2023-09-30T14:30:22Z`
+        let timestamp = humantime::format_rfc3339_seconds(modified).to_string();
+        let time = timestamp[11..19].replace(':', "");
+
+        let mut name = to.file_stem().unwrap_or_default().to_owned();
+        name.push(format!("-{time}"));
+        if let Some(ext) = to.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        to.set_file_name(name);
+    }
+    Ok(())
+}
+
+/// Why a planned rename can't go ahead as it is, as presented to a [`ConflictPrompt`].
+#[derive(Clone, Copy, Debug)]
+pub enum Conflict<'a> {
+    /// Another selected file, `with`, is already being renamed to (or has) the same target.
+    Collision { with: &'a Path },
+    /// The target already exists, and isn't going to be moved out of the way.
+    Clobber,
+}
+
+/// How to resolve a [`Conflict`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConflictDecision {
+    /// Use the given file name for the target instead, which may conflict in turn.
+    Rename(OsString),
+    /// Keep the target: an existing file is replaced, and the selected file it collides with
+    /// (if any) is left where it is.
+    Overwrite,
+    /// Leave the file where it is.
+    Skip,
+}
+
+/// Asks how to resolve each [`Conflict`] found by [`resolve_conflicts`].
+pub trait ConflictPrompt {
+    fn resolve(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        conflict: Conflict<'_>,
+    ) -> io::Result<ConflictDecision>;
+}
+
+/// Asks for a [`ConflictDecision`] by reading `r`, `o`, or `s` lines, followed by a new file name
+/// after `r`, asking again on anything else.
+///
+/// Reaching the end of `input` is treated as [`ConflictDecision::Skip`].
+impl<R: BufRead, W: Write> ConflictPrompt for LinePrompt<R, W> {
+    fn resolve(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        conflict: Conflict<'_>,
+    ) -> io::Result<ConflictDecision> {
+        match conflict {
+            Conflict::Collision { with } => writeln!(
+                self.output,
+                "{from:?} would be renamed to {to:?}, which {with:?} is also renamed to"
+            )?,
+            Conflict::Clobber => writeln!(
+                self.output,
+                "{from:?} would be renamed to {to:?}, which already exists"
+            )?,
+        }
+        loop {
+            let Some(answer) = self.ask("[r]ename, [o]verwrite, or [s]kip?")? else {
+                return Ok(ConflictDecision::Skip);
+            };
+            match answer.to_ascii_lowercase().as_str() {
+                "r" | "rename" => {
+                    let Some(name) = self.ask("new file name:")? else {
+                        return Ok(ConflictDecision::Skip);
+                    };
+                    if name.is_empty() || name.contains(std::path::is_separator) {
+                        writeln!(
+                            self.output,
+                            "please enter a file name, without any directories"
+                        )?;
+                        continue;
+                    }
+                    return Ok(ConflictDecision::Rename(name.into()));
+                }
+                "o" | "overwrite" => return Ok(ConflictDecision::Overwrite),
+                "s" | "skip" => return Ok(ConflictDecision::Skip),
+                _ => writeln!(self.output, "please answer `r`, `o`, or `s`")?,
+            }
+        }
+    }
+}
+
+/// Asks `prompt` how to resolve each [`Conflict`] in `plan`, in order, and rewrites `plan`
+/// accordingly. Renames that would be no-ops are never conflicts, but occupy their targets.
+///
+/// Skipped files stay where they are, so renames to them are checked again once they've been
+/// skipped, without asking again about targets that were chosen to be overwritten. Those existing
+/// targets are returned, to be passed to [`clobbered`].
+pub fn resolve_conflicts(
+    plan: &mut PlanCollector,
+    prompt: &mut dyn ConflictPrompt,
+) -> eyre::Result<HashSet<PathBuf>> {
+    let mut overwritten = HashSet::new();
+    loop {
+        let dropped = resolve_conflicts_once(plan, prompt, &mut overwritten)?;
+        if dropped.is_empty() {
+            return Ok(overwritten);
+        }
+        tracing::info!("skipping {} conflicting rename(s)", dropped.len());
+        let mut idx = 0;
+        plan.plan.retain(|_| {
+            idx += 1;
+            !dropped.contains(&(idx - 1))
+        });
+    }
+}
+
+/// Makes a single pass of [`resolve_conflicts`], returning the indices of renames to drop from
+/// `plan`. Existing targets in `overwritten` aren't conflicts, and targets chosen to be overwritten
+/// are added to it.
+fn resolve_conflicts_once(
+    plan: &mut PlanCollector,
+    prompt: &mut dyn ConflictPrompt,
+    overwritten: &mut HashSet<PathBuf>,
+) -> eyre::Result<HashSet<usize>> {
+    let is_noop =
+        |from: &Path, to: &Result<PathBuf, RenderError>| to.as_ref().is_ok_and(|to| from == to);
+    let sources = plan
+        .plan
+        .iter()
+        .filter(|(from, to)| !is_noop(from, to))
+        .map(|(from, _to)| from.clone())
+        .collect::<HashSet<_>>();
+    let mut claims = plan
+        .plan
+        .iter()
+        .enumerate()
+        .filter(|(_idx, (from, to))| is_noop(from, to))
+        .map(|(idx, (from, _to))| (from.clone(), idx))
+        .collect::<HashMap<_, _>>();
+
+    let mut dropped = HashSet::new();
+    for idx in 0..plan.plan.len() {
+        let (from, to) = &plan.plan[idx];
+        let Ok(to) = to else { continue };
+        if from == to {
+            continue;
+        }
+        let (from, mut to) = (from.clone(), to.clone());
+
+        loop {
+            let conflict = match claims.get(&to) {
+                Some(&other) => Conflict::Collision {
+                    with: &plan.plan[other].0,
+                },
+                None if !sources.contains(&to)
+                    && !overwritten.contains(&to)
+                    && to.try_exists()? =>
+                {
+                    Conflict::Clobber
+                }
+                None => {
+                    claims.insert(to.clone(), idx);
+                    break;
+                }
+            };
+            let clobber = matches!(conflict, Conflict::Clobber);
+            match prompt.resolve(&from, &to, conflict)? {
+                ConflictDecision::Rename(name) => to.set_file_name(name),
+                ConflictDecision::Overwrite => {
+                    if clobber {
+                        overwritten.insert(to.clone());
+                    }
+                    if let Some(other) = claims.insert(to.clone(), idx) {
+                        dropped.insert(other);
+                    }
+                    break;
+                }
+                ConflictDecision::Skip => {
+                    dropped.insert(idx);
+                    break;
+                }
+            }
+        }
+        plan.plan[idx].1 = Ok(to);
+    }
+    Ok(dropped)
+}
+
+/// Disambiguates targets of `plan` shared by multiple sources, by appending each source's position
+/// among those sharing the target (e.g., `-1`, `-2`, etc.) before the target's extension.
+///
+/// A numbered target can be the same as another, unnumbered target that already ended in `-1`.
+pub fn number_collisions(plan: &mut PlanCollector) {
+    let positions = target_collisions(plan.renames())
+        .into_iter()
+        .flat_map(|(_to, froms)| froms.into_iter().zip(1usize..))
+        .map(|(from, position)| (from.to_owned(), position))
+        .collect::<HashMap<_, _>>();
+
+    for (from, to) in &mut plan.plan {
+        let Ok(to) = to else { continue };
+        let Some(&position) = positions.get(from) else {
+            continue;
+        };
+        let mut name = to.file_stem().unwrap_or_default().to_owned();
+        name.push(format!("-{position}"));
+        if let Some(ext) = to.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        to.set_file_name(name);
+    }
+}
+
+/// Disambiguates targets of `plan` shared by multiple sources, by moving each target into a
+/// subdirectory named after its source's parent directory (e.g., `flat/a/img.jpg` for
+/// `a/img.jpg`).
+///
+/// Sources in different directories with the same name (e.g., `x/a/img.jpg` and `y/a/img.jpg`)
+/// still share a target.
+pub fn keep_parent_dirs_of_collisions(plan: &mut PlanCollector) {
+    let colliding = target_collisions(plan.renames())
+        .into_iter()
+        .flat_map(|(_to, froms)| froms)
+        .map(|from| from.to_owned())
+        .collect::<HashSet<_>>();
+
+    for (from, to) in &mut plan.plan {
+        let Ok(to) = to else { continue };
+        if !colliding.contains(from) {
+            continue;
+        }
+        let Some(parent) = from.parent().and_then(|parent| parent.file_name()) else {
+            continue;
+        };
+        let name = to.file_name().unwrap_or_default().to_owned();
+        to.set_file_name(parent);
+        to.push(name);
+    }
+}
+
+/// Whether `path`, resolved against the directory `root` with its `.` and `..` components
+/// applied lexically (i.e., without following symbolic links), leads outside of `root`. Absolute
+/// paths are resolved on their own, so they escape unless they're under `root`.
+pub fn escapes_root(path: &Path, root: &Path) -> bool {
+    let mut normalized = PathBuf::new();
+    for component in root.join(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    !normalized.starts_with(root)
+}
+
+#[test]
+fn escaping_paths() {
+    let root = Path::new("/work/dir");
+    for path in [
+        "a",
+        "./a",
+        "a/../b",
+        "a/b/../../c",
+        "/work/dir/a",
+        "/work/x/../dir/a",
+    ] {
+        assert!(
+            !escapes_root(path.as_ref(), root),
+            "{path:?} should not escape"
+        );
+    }
+    for path in [
+        "..",
+        "../a",
+        "a/../../b",
+        "./../a",
+        "/a",
+        "/work/dir/../../b",
+        "/work/directory/a",
+    ] {
+        assert!(escapes_root(path.as_ref(), root), "{path:?} should escape");
+    }
+}
+
+#[test]
+fn table_with_header_and_ruler() {
+    let plan = PlanCollector {
+        plan: ["a.jpg", "long-name.jpg", "b.jpg", "c.jpg"]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, from)| (from.into(), Ok(format!("{idx}.jpg").into())))
+            .collect(),
+    };
+    let mut table = Vec::new();
+    plan.write_table(true, NonZeroUsize::new(2), false, &mut table)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(table).unwrap(),
+        "\
+#  FROM           TO
+-----------------------
+0  a.jpg          0.jpg
+1  long-name.jpg  1.jpg
+-----------------------
+2  b.jpg          2.jpg
+3  c.jpg          3.jpg
+"
+    );
+
+    let mut table = Vec::new();
+    plan.write_table(false, None, true, &mut table).unwrap();
+    assert_eq!(
+        String::from_utf8(table).unwrap(),
+        "\
+0  a.jpg         -> 0.jpg
+1  long-name.jpg -> 1.jpg
+2  b.jpg         -> 2.jpg
+3  c.jpg         -> 3.jpg
+"
+    );
+}
+
+#[test]
+fn json_plan_shape() {
+    let plan = PlanCollector {
+        plan: vec![
+            ("a.jpg".into(), Ok("photo-0.jpg".into())),
+            ("b.jpg".into(), Err(RenderError::NoGroup)),
+        ],
+    };
+    let mut json = Vec::new();
+    plan.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([
+            { "index": 0, "from": "a.jpg", "to": "photo-0.jpg" },
+            {
+                "index": 1,
+                "from": "b.jpg",
+                "to": null,
+                "error": RenderError::NoGroup.to_string(),
+            },
+        ])
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"caf\xe9.jpg"));
+        assert_eq!(
+            path_to_json(path),
+            serde_json::json!({ "unix_bytes": b"caf\xe9.jpg" })
+        );
+    }
+}
+
+#[test]
+fn conflicts_resolved_as_answered() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a", "b", "c", "d", "taken"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    let path = |name: &str| dir.path().join(name);
+    let mut plan = PlanCollector {
+        plan: [("a", "x"), ("b", "x"), ("c", "taken"), ("d", "x")]
+            .into_iter()
+            .map(|(from, to)| (path(from), Ok(path(to))))
+            .collect(),
+    };
+
+    let mut prompt = LinePrompt {
+        // `b` is renamed to `x` (which collides again) and then `y`; `c` skips, and `d`
+        // overwrites, leaving `a` in place.
+        input: "r\nx\nwhat\nr\ny\ns\no\n".as_bytes(),
+        output: Vec::new(),
+    };
+    resolve_conflicts(&mut plan, &mut prompt).unwrap();
+    assert_eq!(
+        plan.renames().collect::<Vec<_>>(),
+        [
+            (path("b").as_path(), path("y").as_path()),
+            (path("d").as_path(), path("x").as_path()),
+        ]
+    );
+}
+
+#[test]
+fn conflicts_rechecked_after_skips() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a", "b", "c", "d"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    let path = |name: &str| dir.path().join(name);
+    let mut plan = PlanCollector {
+        plan: [("a", "b"), ("b", "c"), ("c", "d"), ("d", "c")]
+            .into_iter()
+            .map(|(from, to)| (path(from), Ok(path(to))))
+            .collect(),
+    };
+
+    let mut prompt = LinePrompt {
+        // `d` collides with `b`, and skips, leaving `d` in place; `c` is then asked about
+        // overwriting it, and does.
+        input: "s\no\n".as_bytes(),
+        output: Vec::new(),
+    };
+    resolve_conflicts(&mut plan, &mut prompt).unwrap();
+    assert_eq!(
+        plan.renames().collect::<Vec<_>>(),
+        [
+            (path("a").as_path(), path("b").as_path()),
+            (path("b").as_path(), path("c").as_path()),
+            (path("c").as_path(), path("d").as_path()),
+        ]
+    );
+    let output = String::from_utf8(prompt.output).unwrap();
+    assert!(output.contains("which already exists"), "{output}");
+}
+
+#[test]
+fn overwrites_chosen_when_resolving_are_not_clobbered() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a", "b", "taken"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    let path = |name: &str| dir.path().join(name);
+    let mut plan = PlanCollector {
+        plan: [("a", "taken"), ("b", "c")]
+            .into_iter()
+            .map(|(from, to)| (path(from), Ok(path(to))))
+            .collect(),
+    };
+    assert_eq!(
+        clobbered(&plan, &HashSet::new()).unwrap(),
+        [(path("a").as_path(), path("taken").as_path())]
+    );
+
+    let mut prompt = LinePrompt {
+        input: "o\n".as_bytes(),
+        output: Vec::new(),
+    };
+    let overwritten = resolve_conflicts(&mut plan, &mut prompt).unwrap();
+    assert_eq!(overwritten, HashSet::from([path("taken")]));
+    assert_eq!(clobbered(&plan, &overwritten).unwrap(), []);
+}
@@ -0,0 +1,118 @@
+use std::{
+    io::{self, Write},
+    sync::{Mutex, MutexGuard},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The progress of the current run through its files, drawn on standard error once
+/// [started](Progress::start).
+pub static PROGRESS: Progress = Progress::new();
+
+/// The fewest files whose renaming is worth showing progress for.
+pub const MIN_FILES: usize = 100;
+
+/// Erases the line that the cursor is on, and returns to its start.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// A single line counting finished files, kept below log lines written with [`Stderr`].
+pub struct Progress {
+    state: Mutex<Option<State>>,
+}
+
+struct State {
+    done: usize,
+    total: usize,
+}
+
+impl Progress {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(None),
+        }
+    }
+
+    /// Starts showing progress through `total` files.
+    pub fn start(&self, total: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .insert(State { done: 0, total })
+            .draw();
+    }
+
+    /// Counts another file as finished, if progress is being shown.
+    pub fn advance(&self) {
+        if let Some(state) = &mut *self.state.lock().unwrap() {
+            state.done += 1;
+            state.draw();
+        }
+    }
+
+    /// Stops showing progress, erasing it.
+    pub fn finish(&self) {
+        if self.state.lock().unwrap().take().is_some() {
+            let _ = io::stderr().write_all(CLEAR_LINE.as_bytes());
+        }
+    }
+}
+
+impl State {
+    fn draw(&self) {
+        let Self { done, total } = self;
+        let _ = write!(io::stderr(), "{CLEAR_LINE}{done}/{total} file(s) done");
+    }
+}
+
+/// Writes log lines to standard error, erasing [`PROGRESS`] before each and drawing it again after,
+/// so that they aren't interleaved.
+pub struct Stderr;
+
+impl<'a> MakeWriter<'a> for Stderr {
+    type Writer = StderrWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        let progress = PROGRESS.state.lock().unwrap();
+        if progress.is_some() {
+            let _ = io::stderr().write_all(CLEAR_LINE.as_bytes());
+        }
+        StderrWriter { progress }
+    }
+}
+
+/// Holds [`PROGRESS`] while a log line is written, so that it can't be drawn in the middle of it.
+pub struct StderrWriter {
+    progress: MutexGuard<'static, Option<State>>,
+}
+
+impl Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl Drop for StderrWriter {
+    fn drop(&mut self) {
+        if let Some(state) = &*self.progress {
+            state.draw();
+        }
+    }
+}
+
+#[test]
+fn counts_until_finished() {
+    let progress = Progress::new();
+    progress.advance();
+    assert!(progress.state.lock().unwrap().is_none());
+
+    progress.start(3);
+    progress.advance();
+    progress.advance();
+    assert_eq!(progress.state.lock().unwrap().as_ref().unwrap().done, 2);
+
+    progress.finish();
+    assert!(progress.state.lock().unwrap().is_none());
+}
@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+/// Suggests a rename spec for `files`, keeping the longest prefix and suffix that their file names
+/// share, and inserting `{padded_idx}` for the part that differs between them.
+///
+/// Digits are trimmed from the shared prefix and suffix, since the differing part of names is
+/// usually an existing index (e.g., `IMG_0001.jpg` and `IMG_0002.jpg` have a shared prefix of
+/// `IMG_000`). If every file shares the same parent directory, it is prepended to the suggestion.
+///
+/// Returns `None` if there are no files, or their names share no structure at all.
+pub fn suggest_spec(files: &[&Path]) -> Option<String> {
+    let names = files
+        .iter()
+        .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+        .collect::<Vec<_>>();
+    let (first, rest) = names.split_first()?;
+
+    let prefix_len = rest.iter().fold(first.len(), |len, name| {
+        common_len(first[..len].chars(), name.chars())
+    });
+    let prefix = first[..prefix_len].trim_end_matches(|c: char| c.is_ascii_digit());
+
+    let mut suffix_len = rest.iter().fold(first.len(), |len, name| {
+        len.min(common_len(first.chars().rev(), name.chars().rev()))
+            // Don't let the suffix overlap the prefix.
+            .min(name.len() - prefix.len())
+    });
+    suffix_len = suffix_len.min(first.len() - prefix.len());
+    while !first.is_char_boundary(first.len() - suffix_len) {
+        suffix_len -= 1;
+    }
+    let suffix = first[first.len() - suffix_len..].trim_start_matches(|c: char| c.is_ascii_digit());
+
+    if prefix.is_empty() && suffix.is_empty() {
+        return None;
+    }
+
+    // Braces in literal text must be escaped, so they aren't taken as replacement groups.
+    let escape = |s: &str| s.replace('{', "{{").replace('}', "}}");
+    let name = format!("{}{{padded_idx}}{}", escape(prefix), escape(suffix));
+    let parent = files[0].parent().unwrap_or(Path::new(""));
+    let spec = if files.iter().all(|path| path.parent() == Some(parent)) {
+        PathBuf::from(escape(&parent.to_string_lossy())).join(name)
+    } else {
+        name.into()
+    };
+    Some(spec.to_string_lossy().into_owned())
+}
+
+/// The length, in bytes, of the common prefix of two strings' `chars`.
+fn common_len(a: impl Iterator<Item = char>, b: impl Iterator<Item = char>) -> usize {
+    a.zip(b)
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _b)| a.len_utf8())
+        .sum()
+}
+
+#[test]
+fn suggestions() {
+    fn suggest(files: &[&str]) -> Option<String> {
+        suggest_spec(&files.iter().map(Path::new).collect::<Vec<_>>())
+    }
+
+    assert_eq!(
+        suggest(&["IMG_0001.jpg", "IMG_0002.jpg", "IMG_0010.jpg"]).as_deref(),
+        Some("IMG_{padded_idx}.jpg")
+    );
+    assert_eq!(
+        suggest(&["dir/song-a-final.mp3", "dir/song-bb-final.mp3"]).as_deref(),
+        Some("dir/song-{padded_idx}-final.mp3")
+    );
+    assert_eq!(suggest(&["aa", "aaa"]).as_deref(), Some("aa{padded_idx}"));
+    assert_eq!(suggest(&["cat", "dog"]), None);
+    assert_eq!(suggest(&["é1é", "é2é"]).as_deref(), Some("é{padded_idx}é"));
+    assert_eq!(
+        suggest(&["{a}/x1}", "{a}/x2}"]).as_deref(),
+        Some("{{a}}/x{padded_idx}}}")
+    );
+    assert_eq!(suggest(&[]), None);
+}
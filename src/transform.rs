@@ -0,0 +1,141 @@
+use snafu::Snafu;
+use std::{fmt, str::FromStr};
+
+/// A step in a pipeline that rewrites target file names, applied after a rename spec (if any) is
+/// rendered.
+pub trait Transform: fmt::Debug {
+    fn apply(&self, name: &str, ctx: &TransformContext) -> String;
+}
+
+/// What a [`Transform`] may know about the file whose name it rewrites.
+#[derive(Clone, Copy, Debug)]
+pub struct TransformContext {
+    /// The position of the file among those being renamed.
+    pub idx: usize,
+    /// The number of files being renamed.
+    pub count: usize,
+}
+
+/// The [`Transform`]s built into `rename-seq`, as parsed from `name` or `name=ARG`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BuiltinTransform {
+    /// `strip-prefix=PREFIX`: removes `PREFIX` from the start of the name, if it's there.
+    StripPrefix(String),
+    /// `strip-suffix=SUFFIX`: removes `SUFFIX` from the end of the stem, if it's there.
+    StripSuffix(String),
+    /// `lowercase`
+    Lowercase,
+    /// `uppercase`
+    Uppercase,
+    /// `normalize-spaces`: replaces each run of whitespace with a single `_`, trimming it from
+    /// the ends of the name.
+    NormalizeSpaces,
+    /// `set-ext=EXT`: replaces the extension (or adds one), or removes it if `EXT` is empty.
+    SetExtension(String),
+    /// `add-index`: appends `-` and the file's zero-padded index to its stem.
+    AddIndex,
+}
+
+impl BuiltinTransform {
+    /// The syntax of every built-in transform.
+    pub const SYNTAXES: &'static [&'static str] = &[
+        "strip-prefix=PREFIX",
+        "strip-suffix=SUFFIX",
+        "lowercase",
+        "uppercase",
+        "normalize-spaces",
+        "set-ext=EXT",
+        "add-index",
+    ];
+}
+
+impl FromStr for BuiltinTransform {
+    type Err = ParseTransformError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once('=') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let transform = match (name, arg) {
+            ("strip-prefix", Some(prefix)) => Self::StripPrefix(prefix.to_owned()),
+            ("strip-suffix", Some(suffix)) => Self::StripSuffix(suffix.to_owned()),
+            ("lowercase", None) => Self::Lowercase,
+            ("uppercase", None) => Self::Uppercase,
+            ("normalize-spaces", None) => Self::NormalizeSpaces,
+            ("set-ext", Some(ext)) => Self::SetExtension(ext.to_owned()),
+            ("add-index", None) => Self::AddIndex,
+            _ => return UnrecognizedTransformSnafu { transform: s }.fail(),
+        };
+        Ok(transform)
+    }
+}
+
+impl Transform for BuiltinTransform {
+    fn apply(&self, name: &str, ctx: &TransformContext) -> String {
+        match self {
+            Self::StripPrefix(prefix) => name.strip_prefix(&**prefix).unwrap_or(name).to_owned(),
+            Self::StripSuffix(suffix) => {
+                let (stem, ext) = split_extension(name);
+                let stem = stem.strip_suffix(&**suffix).unwrap_or(stem);
+                format!("{stem}{ext}")
+            }
+            Self::Lowercase => name.to_lowercase(),
+            Self::Uppercase => name.to_uppercase(),
+            Self::NormalizeSpaces => name.split_whitespace().collect::<Vec<_>>().join("_"),
+            Self::SetExtension(ext) => {
+                let (stem, _ext) = split_extension(name);
+                if ext.is_empty() {
+                    stem.to_owned()
+                } else {
+                    format!("{stem}.{ext}")
+                }
+            }
+            Self::AddIndex => {
+                let (stem, ext) = split_extension(name);
+                let width = crate::digits(ctx.count.saturating_sub(1), 10);
+                format!("{stem}-{:0width$}{ext}", ctx.idx)
+            }
+        }
+    }
+}
+
+/// Splits `name` into its stem and its extension, including the `.`. A leading `.` (e.g., of a
+/// hidden file) does not start an extension.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(0) | None => (name, ""),
+        Some(idx) => name.split_at(idx),
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum ParseTransformError {
+    #[snafu(display(
+        "unrecognized transform `{transform}`; expected one of {:?}",
+        BuiltinTransform::SYNTAXES
+    ))]
+    UnrecognizedTransform { transform: String },
+}
+
+#[test]
+fn chained_transforms() {
+    let transforms = ["strip-prefix=IMG_", "lowercase", "add-index"]
+        .map(|t| t.parse::<BuiltinTransform>().unwrap());
+    let ctx = TransformContext { idx: 3, count: 12 };
+    let name = transforms
+        .iter()
+        .fold("IMG_Beach.JPG".to_owned(), |name, t| t.apply(&name, &ctx));
+    assert_eq!(name, "beach-03.jpg");
+
+    assert_eq!(
+        BuiltinTransform::NormalizeSpaces.apply(" a  b\tc.txt ", &ctx),
+        "a_b_c.txt"
+    );
+    assert_eq!(
+        BuiltinTransform::SetExtension("md".into()).apply(".hidden", &ctx),
+        ".hidden.md"
+    );
+    assert!("lowercase=x".parse::<BuiltinTransform>().is_err());
+    assert!("strip-prefix".parse::<BuiltinTransform>().is_err());
+}